@@ -0,0 +1,5 @@
+// `sqlx::migrate!` embeds `migrations/` at compile time, so new or changed
+// migrations need a rebuild
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
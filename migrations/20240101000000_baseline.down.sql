@@ -0,0 +1,7 @@
+-- these tables belong to `cloud-migrations` and hold data other services
+-- rely on: they're never dropped from here
+do $$
+begin
+    raise exception 'the baseline migration can''t be reverted, its tables belong to cloud-migrations';
+end
+$$;
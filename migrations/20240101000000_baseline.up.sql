@@ -0,0 +1,30 @@
+-- the tables this service started with, as created by `cloud-migrations`.
+-- `if not exists` makes this a no-op on databases that already have them,
+-- which only need it recorded as applied
+
+create table if not exists users (
+    id text primary key,
+    name text not null,
+    surname text not null,
+    email text not null unique,
+    password text not null,
+    propic_url text
+);
+
+create table if not exists fcm_tokens (
+    token text primary key,
+    user_id text not null references users (id) on delete cascade
+);
+
+create index if not exists fcm_tokens_user_idx on fcm_tokens (user_id);
+
+create table if not exists notifications (
+    id text primary key,
+    to_user text not null references users (id) on delete cascade,
+    -- see `NotificationPayload` for what goes in here
+    data jsonb not null,
+    created_at timestamptz not null default now()
+);
+
+create index if not exists notifications_to_user_idx
+    on notifications (to_user, created_at desc);
@@ -0,0 +1,3 @@
+drop index if exists notifications_unread_idx;
+
+alter table notifications drop column read_at;
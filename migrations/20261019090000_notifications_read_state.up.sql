@@ -0,0 +1,7 @@
+alter table notifications add column read_at timestamptz;
+
+-- unread counters are computed straight from this partial index instead of
+-- being kept in a separate counter, so they can't drift under concurrent updates
+create index notifications_unread_idx
+    on notifications (to_user)
+    where read_at is null;
@@ -0,0 +1,3 @@
+drop index if exists notifications_created_at_idx;
+
+alter table notifications drop column archived_at;
@@ -0,0 +1,4 @@
+alter table notifications add column archived_at timestamptz;
+
+-- the retention job scans by age
+create index notifications_created_at_idx on notifications (created_at);
@@ -0,0 +1,3 @@
+drop trigger if exists notifications_inserted on notifications;
+
+drop function if exists notify_notification_inserted();
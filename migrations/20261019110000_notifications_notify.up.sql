@@ -0,0 +1,15 @@
+-- lets every replica (and anybody else listening) know about new notifications,
+-- no matter which service inserted them
+create or replace function notify_notification_inserted() returns trigger as $$
+begin
+    perform pg_notify(
+        'notifications_inserted',
+        json_build_object('id', new.id, 'to_user', new.to_user)::text
+    );
+    return new;
+end;
+$$ language plpgsql;
+
+create trigger notifications_inserted
+    after insert on notifications
+    for each row execute function notify_notification_inserted();
@@ -0,0 +1,3 @@
+drop index if exists notifications_idempotency_idx;
+
+alter table notifications drop column idempotency_key;
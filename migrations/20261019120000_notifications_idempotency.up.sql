@@ -0,0 +1,6 @@
+alter table notifications add column idempotency_key text;
+
+-- retried calls to the internal API insert nothing new
+create unique index notifications_idempotency_idx
+    on notifications (idempotency_key, to_user)
+    where idempotency_key is not null;
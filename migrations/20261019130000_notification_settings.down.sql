@@ -0,0 +1,3 @@
+drop table if exists held_pushes;
+
+drop table if exists notification_settings;
@@ -0,0 +1,22 @@
+create table notification_settings (
+    user_id text primary key references users (id) on delete cascade,
+    -- { "<notification type>": { "push": bool, "email": bool, "in_app": bool } }
+    channels jsonb not null default '{}',
+    muted_senders text[] not null default '{}',
+    muted_cars text[] not null default '{}',
+    quiet_hours_start time,
+    quiet_hours_end time,
+    time_zone text not null default 'UTC',
+    updated_at timestamptz not null default now()
+);
+
+-- pushes that came in during quiet hours, sent as a digest once they're over
+create table held_pushes (
+    id bigserial primary key,
+    user_id text not null references users (id) on delete cascade,
+    title text not null,
+    body text not null,
+    created_at timestamptz not null default now()
+);
+
+create index held_pushes_user_idx on held_pushes (user_id);
@@ -0,0 +1,3 @@
+alter table users
+    drop column locale,
+    drop column time_zone;
@@ -0,0 +1,3 @@
+alter table users
+    add column locale text not null default 'en',
+    add column time_zone text not null default 'UTC';
@@ -0,0 +1,5 @@
+-- disabled users can't log in, and the tokens they already have stop working
+alter table users
+    add column disabled_at timestamptz,
+    -- tokens issued up to this second are rejected (`iat` has no fractions)
+    add column tokens_valid_after timestamptz;
@@ -0,0 +1,16 @@
+-- responses to mutating calls, replayed when clients retry with the same key
+create table idempotency_keys (
+    -- `user:<id>` or `ip:<address>`, so callers can't replay each other's
+    scope text not null,
+    key text not null,
+    -- sha256 of the method, path and body of the first call
+    request_hash text not null,
+    -- all null while the first call is still being handled
+    status smallint,
+    content_type text,
+    body bytea,
+    created_at timestamptz not null default now(),
+    primary key (scope, key)
+);
+
+create index idempotency_keys_created_at_idx on idempotency_keys (created_at);
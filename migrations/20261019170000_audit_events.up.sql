@@ -0,0 +1,33 @@
+-- security-relevant things that happened to accounts, never changed or removed
+create table audit_events (
+    id bigserial primary key,
+    -- e.g. `login.failure`
+    kind text not null,
+    -- `user`, `service`, `admin` or `anonymous`
+    actor_type text not null,
+    actor_id text,
+    -- the account it's about; no foreign key, the history outlives it
+    user_id text,
+    ip text,
+    user_agent text,
+    request_id text,
+    details jsonb not null default '{}',
+    created_at timestamptz not null default now()
+);
+
+create index audit_events_user_idx on audit_events (user_id, id desc);
+create index audit_events_created_at_idx on audit_events (created_at);
+
+create function audit_events_append_only() returns trigger as $$
+begin
+    raise exception 'audit_events is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_events_append_only
+    before update or delete on audit_events
+    for each row execute function audit_events_append_only();
+
+create trigger audit_events_no_truncate
+    before truncate on audit_events
+    for each statement execute function audit_events_append_only();
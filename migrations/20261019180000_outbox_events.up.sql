@@ -0,0 +1,20 @@
+-- domain events, written in the same transaction as the change they're
+-- about and published by the relay
+create table outbox_events (
+    id bigserial primary key,
+    -- e.g. `user.registered`
+    kind text not null,
+    -- events of the same user are published in order
+    user_id text not null,
+    payload jsonb not null,
+    created_at timestamptz not null default now(),
+    published_at timestamptz,
+    attempts integer not null default 0,
+    last_error text,
+    next_attempt_at timestamptz not null default now()
+);
+
+create index outbox_events_pending_idx on outbox_events (user_id, id)
+    where published_at is null;
+create index outbox_events_published_idx on outbox_events (published_at)
+    where published_at is not null;
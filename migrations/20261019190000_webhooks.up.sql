@@ -0,0 +1,48 @@
+-- partners told about user events, managed by administrators
+create table webhook_subscriptions (
+    id text primary key,
+    url text not null,
+    -- event types to send, every one of them when empty
+    events text[] not null default '{}',
+    -- signs the payloads, shared with the partner
+    secret text not null,
+    description text,
+    created_at timestamptz not null default now(),
+    disabled_at timestamptz
+);
+
+-- one per event and subscription, written along with the outbox event
+create table webhook_deliveries (
+    id bigserial primary key,
+    subscription_id text not null references webhook_subscriptions (id) on delete cascade,
+    event_id bigint not null,
+    kind text not null,
+    user_id text not null,
+    payload jsonb not null,
+    occurred_at timestamptz not null,
+    -- `pending`, `delivered`, or `dead` once it ran out of attempts
+    state text not null default 'pending',
+    attempts integer not null default 0,
+    next_attempt_at timestamptz not null default now(),
+    created_at timestamptz not null default now(),
+    delivered_at timestamptz
+);
+
+create index webhook_deliveries_pending_idx
+    on webhook_deliveries (subscription_id, user_id, id)
+    where state = 'pending';
+create index webhook_deliveries_subscription_idx
+    on webhook_deliveries (subscription_id, id);
+
+-- the log of every request made for a delivery
+create table webhook_attempts (
+    id bigserial primary key,
+    delivery_id bigint not null references webhook_deliveries (id) on delete cascade,
+    attempted_at timestamptz not null default now(),
+    -- `null` when there was no response at all
+    status_code smallint,
+    error text,
+    duration_ms integer not null
+);
+
+create index webhook_attempts_delivery_idx on webhook_attempts (delivery_id);
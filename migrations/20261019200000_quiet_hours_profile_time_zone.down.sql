@@ -0,0 +1,6 @@
+alter table notification_settings
+    add column time_zone text not null default 'UTC';
+
+update notification_settings s set time_zone = u.time_zone
+from users u
+where u.id = s.user_id;
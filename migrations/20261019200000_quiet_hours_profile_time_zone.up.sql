@@ -0,0 +1,9 @@
+-- quiet hours follow the time zone of the profile. users who only ever set
+-- one for their quiet hours keep it, as the one of their profile
+update users u set time_zone = s.time_zone
+from notification_settings s
+where s.user_id = u.id
+and s.quiet_hours_start is not null
+and u.time_zone = 'UTC';
+
+alter table notification_settings drop column time_zone;
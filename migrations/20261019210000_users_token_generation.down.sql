@@ -0,0 +1,5 @@
+alter table users add column tokens_valid_after timestamptz;
+
+update users set tokens_valid_after = now() where token_generation > 0;
+
+alter table users drop column token_generation;
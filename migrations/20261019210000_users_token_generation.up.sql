@@ -0,0 +1,9 @@
+-- revocations bump a counter tokens carry, rather than rejecting tokens by
+-- the second they were issued at, which also rejected the ones issued right
+-- after. tokens from before this have generation 0: users who had theirs
+-- revoked need a new one
+alter table users add column token_generation integer not null default 0;
+
+update users set token_generation = 1 where tokens_valid_after is not null;
+
+alter table users drop column tokens_valid_after;
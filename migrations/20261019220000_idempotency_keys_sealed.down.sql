@@ -0,0 +1,5 @@
+-- sealed responses can't be replayed by a version that doesn't open them
+delete from idempotency_keys;
+
+comment on column idempotency_keys.request_hash is null;
+comment on column idempotency_keys.body is null;
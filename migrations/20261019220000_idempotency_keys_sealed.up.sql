@@ -0,0 +1,8 @@
+-- responses used to be stored as they were, tokens included, and request
+-- hashes weren't keyed: none of them can be replayed anymore
+delete from idempotency_keys;
+
+comment on column idempotency_keys.request_hash is
+    'HMAC of the method, path and body of the first call, keyed with IDEMPOTENCY_SECRET';
+comment on column idempotency_keys.body is
+    'the response, encrypted with a key derived from IDEMPOTENCY_SECRET';
@@ -0,0 +1,3 @@
+-- events are leased to a relay while it publishes them, instead of staying
+-- locked in a transaction for as long as the sink takes
+alter table outbox_events add column locked_until timestamptz;
@@ -0,0 +1,3 @@
+-- deliveries are leased to a dispatcher while it sends them, instead of
+-- staying locked in a transaction for as long as partners take to answer
+alter table webhook_deliveries add column locked_until timestamptz;
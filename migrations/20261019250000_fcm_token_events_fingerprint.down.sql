@@ -0,0 +1 @@
+-- the tokens are gone for good: nothing to restore
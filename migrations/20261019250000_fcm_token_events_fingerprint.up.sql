@@ -0,0 +1,13 @@
+-- `fcm_token.added` events used to carry the token itself, which is enough
+-- to push to the device: keep only its fingerprint, wherever it was copied
+update outbox_events set payload = jsonb_build_object(
+    'user_id', payload->>'user_id',
+    'token_sha256', encode(sha256(convert_to(payload->>'token', 'UTF8')), 'hex')
+)
+where kind = 'fcm_token.added' and payload ? 'token';
+
+update webhook_deliveries set payload = jsonb_build_object(
+    'user_id', payload->>'user_id',
+    'token_sha256', encode(sha256(convert_to(payload->>'token', 'UTF8')), 'hex')
+)
+where kind = 'fcm_token.added' and payload ? 'token';
@@ -0,0 +1 @@
+alter table held_pushes drop column locked_until;
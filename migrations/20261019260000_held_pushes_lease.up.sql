@@ -0,0 +1,3 @@
+-- held pushes are leased to a replica while it sends their digest, instead of
+-- staying locked in a transaction for as long as fcm-messenger takes
+alter table held_pushes add column locked_until timestamptz;
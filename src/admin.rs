@@ -0,0 +1,292 @@
+//! What the administration subcommands do, on top of the same models the
+//! API uses.
+
+use std::{io::BufRead, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::{json, Value};
+use sqlx::{Pool, Postgres};
+use validator::Validate;
+
+use crate::{
+    cli::{MigrateCommand, OpenapiCommand, UserCommand},
+    config::Config,
+    db::{self, MigrationStatus},
+    web::{
+        dto::{
+            admin_claims::AdminClaims, auth::register_request::RegisterRequest,
+            Claim,
+        },
+        extractors::token::Token,
+        locale::Locale,
+        models::{
+            audit_events::{self, Actor, AuditKind, NewAuditEvent},
+            outbox::{self, NewOutboxEvent},
+            users::{NewUser, User},
+        },
+        repositories::{postgres::PgUserRepository, UserRepository},
+        util::hash_password,
+        api_doc,
+    },
+};
+
+pub async fn migrate(
+    pool: &Pool<Postgres>,
+    command: MigrateCommand,
+) -> Result<(), anyhow::Error> {
+    match command {
+        MigrateCommand::Run => db::migrate(pool).await?,
+        MigrateCommand::Revert { target } => db::revert(pool, target).await?,
+        MigrateCommand::Info => {
+            for (version, description, status) in db::status(pool).await? {
+                let status = match status {
+                    MigrationStatus::Applied => "applied",
+                    MigrationStatus::Pending => "pending",
+                    MigrationStatus::Modified => "applied, changed since",
+                    MigrationStatus::Unknown => "applied, unknown to this build",
+                };
+                println!("{version} {description:<40} {status}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn user(
+    pool: &Pool<Postgres>,
+    command: UserCommand,
+) -> Result<(), anyhow::Error> {
+    match command {
+        UserCommand::Create {
+            email,
+            name,
+            surname,
+            locale,
+            time_zone,
+            password_stdin,
+        } => {
+            let locale: Locale = locale
+                .parse()
+                .map_err(|_| anyhow!("unknown locale `{locale}`"))?;
+            // the same rules as signing up through the API
+            let request = RegisterRequest {
+                email,
+                name,
+                surname,
+                password: read_password(password_stdin)?,
+                locale,
+                time_zone: Some(time_zone),
+            };
+            request
+                .validate()
+                .map_err(|e| anyhow!("invalid user: {e}"))?;
+
+            let password_hash = hashed(&request.password).await?;
+            // like through the API, the rest of PIENO is told about it
+            let mut tx = pool.begin().await?;
+            let user_id = User::new_id();
+            let new_user = NewUser {
+                id: user_id.clone(),
+                email: request.email,
+                name: request.name,
+                surname: request.surname,
+                password_hash,
+                locale: request.locale,
+                time_zone: request.time_zone.unwrap_or("UTC".to_string()),
+            };
+            User::register(&mut *tx, &new_user)
+                .await
+                .map_err(|e| match e {
+                    sqlx::Error::Database(e) if e.code().as_deref() == Some("23505") => {
+                        anyhow!("`{}` is already registered", new_user.email)
+                    }
+                    e => e.into(),
+                })?;
+            let user = User::from_id(&mut *tx, &user_id)
+                .await?
+                .context("the new user is gone")?;
+            outbox::enqueue(&mut *tx, &NewOutboxEvent::user_registered(user.into())?)
+                .await?;
+            tx.commit().await?;
+            audit(pool, AuditKind::UserRegistered, Some(&user_id), json!({})).await?;
+            println!("created user {user_id}");
+        }
+        UserCommand::Disable { user } => {
+            let mut user = find(pool, &user).await?;
+            PgUserRepository::new(pool.clone())
+                .set_disabled(&mut user, true)
+                .await?;
+            audit(pool, AuditKind::UserDisabled, Some(&user.id), json!({})).await?;
+            println!("disabled {} ({}), their tokens are revoked", user.email, user.id);
+        }
+        UserCommand::Enable { user } => {
+            let mut user = find(pool, &user).await?;
+            PgUserRepository::new(pool.clone())
+                .set_disabled(&mut user, false)
+                .await?;
+            audit(pool, AuditKind::UserEnabled, Some(&user.id), json!({})).await?;
+            println!("enabled {} ({})", user.email, user.id);
+        }
+        UserCommand::Delete { user, yes } => {
+            let user = find(pool, &user).await?;
+            if !yes && !confirm(&format!("delete {} ({})?", user.email, user.id))? {
+                bail!("nothing was deleted");
+            }
+            let mut tx = pool.begin().await?;
+            user.delete(&mut *tx).await?;
+            outbox::enqueue(&mut *tx, &NewOutboxEvent::user_deleted(&user.id)).await?;
+            tx.commit().await?;
+            audit(
+                pool,
+                AuditKind::UserDeleted,
+                Some(&user.id),
+                json!({"email": user.email}),
+            )
+            .await?;
+            println!("deleted {} ({})", user.email, user.id);
+        }
+        UserCommand::ResetPassword {
+            user,
+            password_stdin,
+        } => {
+            let mut user = find(pool, &user).await?;
+            let password = read_password(password_stdin)?;
+            // same as `RegisterRequest`
+            if password.chars().count() < 8 {
+                bail!("the password must be at least 8 characters long");
+            }
+            PgUserRepository::new(pool.clone())
+                .set_password(&mut user, &hashed(&password).await?)
+                .await?;
+            audit(pool, AuditKind::PasswordReset, Some(&user.id), json!({})).await?;
+            println!(
+                "changed the password of {} ({}), their tokens are revoked",
+                user.email, user.id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Revokes the tokens of `user`, or of everybody when there's no user.
+pub async fn revoke_tokens(
+    pool: &Pool<Postgres>,
+    user: Option<String>,
+) -> Result<(), anyhow::Error> {
+    match user {
+        Some(user) => {
+            let user = find(pool, &user).await?;
+            User::revoke_tokens(pool, Some(&user.id)).await?;
+            audit(pool, AuditKind::TokensRevoked, Some(&user.id), json!({})).await?;
+            println!("revoked the tokens of {} ({})", user.email, user.id);
+        }
+        None => {
+            let users = User::revoke_tokens(pool, None).await?;
+            audit(pool, AuditKind::TokensRevoked, None, json!({"users": users})).await?;
+            println!("revoked the tokens of all {users} users");
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn admin_token(
+    config: &Config,
+    service: &str,
+    valid_for_secs: Option<u64>,
+) -> Result<(), anyhow::Error> {
+    if !config.server.admin_services.iter().any(|s| s == service) {
+        bail!("`{service}` isn't one of ADMIN_SERVICES, its token would be refused");
+    }
+    let valid_for = valid_for_secs.map_or(config.jwt.timeout, Duration::from_secs);
+    let claims = Claim::from(
+        AdminClaims {
+            service: service.to_string(),
+        },
+        valid_for,
+    );
+    let token = Token::generate(claims, &config.jwt)
+        .await
+        .map_err(|_| anyhow!("couldn't sign the token"))?;
+    println!("{token}");
+
+    Ok(())
+}
+
+/// Records what an administrator did, under their login name.
+async fn audit(
+    pool: &Pool<Postgres>,
+    kind: AuditKind,
+    user_id: Option<&str>,
+    details: Value,
+) -> Result<(), anyhow::Error> {
+    let operator = std::env::var("SUDO_USER").or(std::env::var("USER")).ok();
+    let mut event = NewAuditEvent::new(kind, Actor::Admin(operator)).details(details);
+    if let Some(user_id) = user_id {
+        event = event.user(user_id);
+    }
+    audit_events::append(pool, &event)
+        .await
+        .context("couldn't record the audit event")
+}
+
+pub fn openapi(command: OpenapiCommand) -> Result<(), anyhow::Error> {
+    match command {
+        OpenapiCommand::Export {
+            api_version,
+            output,
+        } => {
+            let json = api_doc(api_version).to_pretty_json()?;
+            match output {
+                Some(path) => std::fs::write(&path, json)
+                    .with_context(|| format!("couldn't write {}", path.display()))?,
+                None => println!("{json}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `user` is either an email or an id.
+async fn find(pool: &Pool<Postgres>, user: &str) -> Result<User, anyhow::Error> {
+    let found = if user.contains('@') {
+        User::from_email(pool, user).await?
+    } else {
+        User::from_id(pool, user).await?
+    };
+
+    found.ok_or(anyhow!("there's no user `{user}`"))
+}
+
+fn read_password(from_stdin: bool) -> Result<String, anyhow::Error> {
+    if from_stdin {
+        let mut password = String::new();
+        std::io::stdin().lock().read_line(&mut password)?;
+        return Ok(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let password = rpassword::prompt_password("password: ")?;
+    if rpassword::prompt_password("again: ")? != password {
+        bail!("the passwords don't match");
+    }
+
+    Ok(password)
+}
+
+fn confirm(question: &str) -> Result<bool, anyhow::Error> {
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+async fn hashed(password: &str) -> Result<String, anyhow::Error> {
+    // `HttpError` is meant for responses, and isn't an `Error`
+    hash_password(password)
+        .await
+        .map_err(|_| anyhow!("couldn't hash the password"))
+}
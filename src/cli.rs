@@ -0,0 +1,216 @@
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Parser, Subcommand};
+
+use crate::web::versions::ApiVersion;
+
+/// The PIENO users service.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serves the API, what happens without a command
+    Serve {
+        /// Apply pending migrations before starting, like
+        /// `DB_MIGRATE_ON_STARTUP` does
+        #[arg(long)]
+        migrate: bool,
+    },
+    /// Manages the database schema
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Manages user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manages access tokens
+    #[command(subcommand)]
+    Tokens(TokensCommand),
+    /// Works with the OpenAPI description of the API
+    #[command(subcommand)]
+    Openapi(OpenapiCommand),
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Applies every pending migration
+    Run,
+    /// Reverts the latest migration
+    Revert {
+        /// Revert every migration applied after this version instead, 0
+        /// reverts them all
+        #[arg(long)]
+        target: Option<i64>,
+    },
+    /// Lists the migrations and whether they've been applied
+    Info,
+}
+
+// users are given either by email or by id
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Creates a user, asking for their password
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        surname: String,
+        /// `en` or `it`
+        #[arg(long, default_value = "en")]
+        locale: String,
+        /// IANA time zone, e.g. `Europe/Rome`
+        #[arg(long, default_value = "UTC")]
+        time_zone: String,
+        /// Read the password from the standard input instead of asking
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Keeps a user from logging in, and revokes their tokens
+    Disable {
+        /// Email or id
+        user: String,
+    },
+    /// Lets a disabled user log in again
+    Enable {
+        /// Email or id
+        user: String,
+    },
+    /// Deletes a user and everything that's theirs, for good
+    Delete {
+        /// Email or id
+        user: String,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Sets a new password, and revokes the user's tokens
+    ResetPassword {
+        /// Email or id
+        user: String,
+        /// Read the password from the standard input instead of asking
+        #[arg(long)]
+        password_stdin: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TokensCommand {
+    /// Rejects every token issued so far, logging users out everywhere
+    #[command(group(ArgGroup::new("whose").required(true)))]
+    Revoke {
+        /// Email or id of the user whose tokens to revoke
+        #[arg(group = "whose")]
+        user: Option<String>,
+        /// Revoke the tokens of every user
+        #[arg(long, group = "whose")]
+        all: bool,
+    },
+    /// Prints a token for a back-office tool to call `/admin` with, signed
+    /// with `ADMIN_JWT_SECRET`
+    Admin {
+        /// Name of the tool, one of `ADMIN_SERVICES`
+        service: String,
+        /// How long it's valid for, `JWT_TIMEOUT_SECS` by default
+        #[arg(long)]
+        valid_for_secs: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum OpenapiCommand {
+    /// Prints the OpenAPI JSON of a version, what `/v1/json-schema` serves
+    /// for `v1`
+    Export {
+        /// `v1`, or `unversioned` for the deprecated routes at the root
+        #[arg(long, default_value = "v1")]
+        api_version: ApiVersion,
+        /// Write it to this file instead
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{error::ErrorKind, CommandFactory};
+
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("users").chain(args.split_whitespace()))
+    }
+
+    #[test]
+    fn the_commands_are_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert!(parse("").unwrap().command.is_none());
+        assert!(matches!(
+            parse("serve --migrate").unwrap().command,
+            Some(Command::Serve { migrate: true })
+        ));
+        assert!(matches!(
+            parse("migrate revert --target 0").unwrap().command,
+            Some(Command::Migrate(MigrateCommand::Revert { target: Some(0) }))
+        ));
+        assert!(matches!(
+            parse("user create --email mario@example.com --name Mario --surname Rossi")
+                .unwrap()
+                .command,
+            Some(Command::User(UserCommand::Create { locale, time_zone, password_stdin: false, .. }))
+                if locale == "en" && time_zone == "UTC"
+        ));
+        assert!(matches!(
+            parse("user reset-password mario@example.com --password-stdin")
+                .unwrap()
+                .command,
+            Some(Command::User(UserCommand::ResetPassword { user, password_stdin: true }))
+                if user == "mario@example.com"
+        ));
+        assert!(matches!(
+            parse("tokens revoke --all").unwrap().command,
+            Some(Command::Tokens(TokensCommand::Revoke { user: None, all: true }))
+        ));
+        assert!(matches!(
+            parse("tokens admin backoffice --valid-for-secs 60").unwrap().command,
+            Some(Command::Tokens(TokensCommand::Admin { service, valid_for_secs: Some(60) }))
+                if service == "backoffice"
+        ));
+        assert!(matches!(
+            parse("openapi export --api-version unversioned -o api.json")
+                .unwrap()
+                .command,
+            Some(Command::Openapi(OpenapiCommand::Export {
+                api_version: ApiVersion::Unversioned,
+                output: Some(_),
+            }))
+        ));
+    }
+
+    #[test]
+    fn bad_commands_are_refused() {
+        let cases = [
+            ("tokens revoke", ErrorKind::MissingRequiredArgument),
+            ("tokens revoke mario@example.com --all", ErrorKind::ArgumentConflict),
+            ("user create --email mario@example.com", ErrorKind::MissingRequiredArgument),
+            ("migrate revert --target latest", ErrorKind::ValueValidation),
+            ("openapi export --api-version v2", ErrorKind::ValueValidation),
+            ("users list", ErrorKind::InvalidSubcommand),
+        ];
+        for (args, kind) in cases {
+            match parse(args) {
+                Ok(_) => panic!("`{args}` was accepted"),
+                Err(e) => assert_eq!(e.kind(), kind, "{args}"),
+            }
+        }
+    }
+}
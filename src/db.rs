@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use sqlx::{
+    migrate::{Migrate, MigrateError, Migrator},
+    postgres::PgPoolOptions,
+    Pool, Postgres,
+};
+use thiserror::Error;
+use tracing::info;
+
+use crate::config::DatabaseConfig;
+
+/// Every migration in `migrations/`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("migrations {0:?} haven't been applied, run `users migrate run`")]
+    Pending(Vec<i64>),
+    #[error("the database has migrations {0:?} this build doesn't know about, it's too old for this schema")]
+    Unknown(Vec<i64>),
+    #[error("migration {0} changed after being applied")]
+    Modified(i64),
+    #[error("migration {0} failed halfway, the schema needs fixing by hand")]
+    Dirty(i64),
+    #[error(transparent)]
+    Migrate(#[from] MigrateError),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrationStatus {
+    Applied,
+    Pending,
+    /// applied, but its file changed since
+    Modified,
+    /// applied, but this build doesn't have it
+    Unknown,
+}
+
+pub async fn connect(
+    config: &DatabaseConfig,
+) -> Result<Pool<Postgres>, sqlx::Error> {
+    info!("acquiring pool...");
+
+    PgPoolOptions::new()
+        .acquire_timeout(config.acquire_timeout)
+        .max_connections(config.max_connections)
+        .connect(&config.url)
+        .await
+}
+
+/// Every migration, known to this build or applied to the database, by
+/// version.
+pub async fn status(
+    pool: &Pool<Postgres>,
+) -> Result<Vec<(i64, String, MigrationStatus)>, SchemaError> {
+    let mut conn = pool.acquire().await?;
+    // only looking, so the migrations table isn't created when missing
+    let tracked: bool = sqlx::query_scalar(
+        "
+            select to_regclass('_sqlx_migrations') is not null
+        ",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if tracked {
+        if let Some(version) = conn.dirty_version().await? {
+            return Err(SchemaError::Dirty(version));
+        }
+    }
+    let mut applied: HashMap<i64, _> = match tracked {
+        true => conn
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|m| (m.version, m.checksum))
+            .collect(),
+        false => HashMap::new(),
+    };
+
+    let mut migrations: Vec<_> = MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| {
+            let status = match applied.remove(&m.version) {
+                None => MigrationStatus::Pending,
+                Some(checksum) if checksum != m.checksum => {
+                    MigrationStatus::Modified
+                }
+                Some(_) => MigrationStatus::Applied,
+            };
+            (m.version, m.description.to_string(), status)
+        })
+        .collect();
+    migrations.extend(
+        applied
+            .into_keys()
+            .map(|v| (v, String::new(), MigrationStatus::Unknown)),
+    );
+    migrations.sort_by_key(|(version, ..)| *version);
+
+    Ok(migrations)
+}
+
+/// Fails unless the database schema is exactly the one this build expects.
+pub async fn check_schema(pool: &Pool<Postgres>) -> Result<(), SchemaError> {
+    check(&status(pool).await?)
+}
+
+/// What's wrong with a database whose migrations are in `migrations`, the
+/// worst first.
+fn check(
+    migrations: &[(i64, String, MigrationStatus)],
+) -> Result<(), SchemaError> {
+    let with = |wanted: MigrationStatus| -> Vec<i64> {
+        migrations
+            .iter()
+            .filter(|(_, _, status)| *status == wanted)
+            .map(|(version, ..)| *version)
+            .collect()
+    };
+
+    if let Some(version) = with(MigrationStatus::Modified).first() {
+        return Err(SchemaError::Modified(*version));
+    }
+    let unknown = with(MigrationStatus::Unknown);
+    if !unknown.is_empty() {
+        return Err(SchemaError::Unknown(unknown));
+    }
+    let pending = with(MigrationStatus::Pending);
+    if !pending.is_empty() {
+        return Err(SchemaError::Pending(pending));
+    }
+
+    Ok(())
+}
+
+/// Applies every pending migration. Replicas starting together wait for
+/// each other, sqlx holds an advisory lock while migrating.
+pub async fn migrate(pool: &Pool<Postgres>) -> Result<(), SchemaError> {
+    MIGRATOR.run(pool).await?;
+    info!("database schema is up to date");
+
+    Ok(())
+}
+
+/// Reverts the migrations applied after `target`, or just the latest one
+/// when there's no target.
+pub async fn revert(
+    pool: &Pool<Postgres>,
+    target: Option<i64>,
+) -> Result<(), SchemaError> {
+    let applied: Vec<i64> = status(pool)
+        .await?
+        .into_iter()
+        .filter(|(_, _, status)| *status != MigrationStatus::Pending)
+        .map(|(version, ..)| version)
+        .collect();
+    let target = target.unwrap_or(previous(&applied));
+
+    MIGRATOR.undo(pool, target).await?;
+    info!("reverted the database schema to migration {target}");
+
+    Ok(())
+}
+
+/// The migration before the latest of `applied`, so that undoing everything
+/// newer than it reverts just the latest: 0 means everything.
+fn previous(applied: &[i64]) -> i64 {
+    applied.iter().rev().nth(1).copied().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_migration_can_be_reverted() {
+        let ups: Vec<_> = MIGRATOR
+            .iter()
+            .filter(|m| !m.migration_type.is_down_migration())
+            .collect();
+        assert!(ups.windows(2).all(|pair| pair[0].version < pair[1].version));
+        for up in &ups {
+            assert!(
+                MIGRATOR.iter().any(|m| m.migration_type.is_down_migration()
+                    && m.version == up.version
+                    && m.description == up.description),
+                "{} has no down migration",
+                up.version
+            );
+        }
+        // the one that refuses to be reverted comes before everything else
+        assert_eq!(ups[0].description, "baseline");
+    }
+
+    #[test]
+    fn the_schema_has_to_match_exactly() {
+        use MigrationStatus::*;
+
+        let schema = |statuses: Vec<MigrationStatus>| {
+            let migrations: Vec<_> = statuses
+                .into_iter()
+                .enumerate()
+                .map(|(i, status)| (i as i64 + 1, String::new(), status))
+                .collect();
+            check(&migrations)
+        };
+
+        assert!(schema(vec![Applied, Applied]).is_ok());
+        assert!(schema(vec![]).is_ok());
+        assert!(matches!(
+            schema(vec![Applied, Pending, Pending]),
+            Err(SchemaError::Pending(v)) if v == [2, 3]
+        ));
+        assert!(matches!(
+            schema(vec![Applied, Unknown, Pending]),
+            Err(SchemaError::Unknown(v)) if v == [2]
+        ));
+        assert!(matches!(
+            schema(vec![Pending, Modified, Unknown]),
+            Err(SchemaError::Modified(2))
+        ));
+    }
+
+    #[test]
+    fn reverting_undoes_the_latest_by_default() {
+        assert_eq!(previous(&[1, 2, 3]), 2);
+        assert_eq!(previous(&[1]), 0);
+        assert_eq!(previous(&[]), 0);
+    }
+}
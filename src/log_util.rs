@@ -3,7 +3,8 @@
 
 use std::fmt::Debug;
 
-/// Trait to easily [`log`] function outcomes.
+/// Trait to easily log function outcomes as [`tracing`] events.
+#[allow(dead_code)]
 pub trait LoggableOutcome {
     fn log_err_to_trace(self, msg: &str) -> Self;
     fn log_err_to_debug(self, msg: &str) -> Self;
@@ -18,35 +19,35 @@ where
 {
     fn log_err_to_trace(self, msg: &str) -> Self {
         self.map_err(|err| {
-            log::trace!("{msg}: {err:?}");
+            tracing::trace!("{msg}: {err:?}");
             err
         })
     }
 
     fn log_err_to_debug(self, msg: &str) -> Self {
         self.map_err(|err| {
-            log::debug!("{msg}: {err:?}");
+            tracing::debug!("{msg}: {err:?}");
             err
         })
     }
 
     fn log_err_to_info(self, msg: &str) -> Self {
         self.map_err(|err| {
-            log::info!("{msg}: {err:?}");
+            tracing::info!("{msg}: {err:?}");
             err
         })
     }
 
     fn log_err_to_warn(self, msg: &str) -> Self {
         self.map_err(|err| {
-            log::warn!("{msg}: {err:?}");
+            tracing::warn!("{msg}: {err:?}");
             err
         })
     }
 
     fn log_err_to_error(self, msg: &str) -> Self {
         self.map_err(|err| {
-            log::error!("{msg}: {err:?}");
+            tracing::error!("{msg}: {err:?}");
             err
         })
     }
@@ -55,35 +56,35 @@ where
 impl<T> LoggableOutcome for Option<T> {
     fn log_err_to_trace(self, msg: &str) -> Self {
         if self.is_none() {
-            log::trace!("{msg}");
+            tracing::trace!("{msg}");
         }
         self
     }
 
     fn log_err_to_debug(self, msg: &str) -> Self {
         if self.is_none() {
-            log::debug!("{msg}");
+            tracing::debug!("{msg}");
         }
         self
     }
 
     fn log_err_to_info(self, msg: &str) -> Self {
         if self.is_none() {
-            log::info!("{msg}");
+            tracing::info!("{msg}");
         }
         self
     }
 
     fn log_err_to_warn(self, msg: &str) -> Self {
         if self.is_none() {
-            log::warn!("{msg}");
+            tracing::warn!("{msg}");
         }
         self
     }
 
     fn log_err_to_error(self, msg: &str) -> Self {
         if self.is_none() {
-            log::error!("{msg}");
+            tracing::error!("{msg}");
         }
         self
     }
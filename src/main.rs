@@ -1,12 +1,110 @@
+mod admin;
+mod cli;
+mod config;
+mod db;
 mod log_util;
+mod telemetry;
 pub mod web;
-use log::info;
+use clap::Parser;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info};
+
+use crate::{
+    cli::{Cli, Command, TokensCommand},
+    config::Config,
+};
 
 #[tokio::main]
 async fn main() {
-    pretty_env_logger::init();
-    let router = web::build_app().await;
+    let cli = Cli::parse();
+    // the only command that needs neither configuration nor database
+    if let Some(Command::Openapi(command)) = cli.command {
+        if let Err(e) = admin::openapi(command) {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            // logging isn't set up yet, it's configured too
+            eprintln!("invalid configuration: {e}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = telemetry::init(&config.tracing, &config.log) {
+        eprintln!("couldn't set up tracing: {e}");
+        std::process::exit(1);
+    }
+
+    let result = match cli.command.unwrap_or(Command::Serve { migrate: false }) {
+        Command::Serve { migrate } => serve(config, migrate).await,
+        // the schema is what's being changed, there's nothing to check yet
+        Command::Migrate(command) => {
+            with_database(&config, false, async |pool| {
+                admin::migrate(pool, command).await
+            })
+            .await
+        }
+        Command::User(command) => {
+            with_database(&config, true, async |pool| admin::user(pool, command).await)
+                .await
+        }
+        Command::Tokens(TokensCommand::Revoke { user, .. }) => {
+            with_database(&config, true, async |pool| {
+                admin::revoke_tokens(pool, user).await
+            })
+            .await
+        }
+        // signing one takes the secret, not the database
+        Command::Tokens(TokensCommand::Admin { service, valid_for_secs }) => {
+            admin::admin_token(&config, &service, valid_for_secs).await
+        }
+        // handled before loading the configuration
+        Command::Openapi(_) => unreachable!(),
+    };
+    telemetry::shutdown();
+
+    if let Err(e) = result {
+        error!("{e:#}");
+        std::process::exit(1);
+    }
+}
+
+async fn serve(config: Config, migrate: bool) -> Result<(), anyhow::Error> {
+    let pool = db::connect(&config.database).await?;
+    if migrate || config.database.migrate_on_startup {
+        db::migrate(&pool).await?;
+    }
+    // better not to start at all than to fail on every other query
+    db::check_schema(&pool).await?;
+
+    let bind_address = config.server.bind_address;
+    let app = web::build_app(config, pool).await?;
     info!("users microservice built and running");
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, router).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(bind_address).await?;
+    app.serve(listener).await?;
+
+    Ok(())
+}
+
+/// Runs an administration command against the database, after checking
+/// the models can work with its schema when `check_schema` is set.
+async fn with_database(
+    config: &Config,
+    check_schema: bool,
+    command: impl AsyncFnOnce(&Pool<Postgres>) -> Result<(), anyhow::Error>,
+) -> Result<(), anyhow::Error> {
+    let pool = db::connect(&config.database).await?;
+    if check_schema {
+        // the models only work with the schema they were written for
+        db::check_schema(&pool).await?;
+    }
+
+    let result = command(&pool).await;
+    pool.close().await;
+
+    result
 }
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::TracerProvider as _,
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Sampler, TracerProvider},
+    Resource,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+};
+
+use crate::config::{LogConfig, LogFormat, TracingConfig};
+
+/// Sets up logging and tracing, both filtered by `RUST_LOG`: spans always
+/// get trace ids, so `traceparent` is propagated, but they only leave the
+/// process when there's an OTLP endpoint to export them to.
+pub fn init(
+    config: &TracingConfig,
+    log: &LogConfig,
+) -> Result<(), anyhow::Error> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let trace_config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(
+            Sampler::TraceIdRatioBased(config.sample_ratio),
+        )))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]));
+
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace_config)
+            .install_batch(runtime::Tokio)?
+            .provider()
+            .expect("the pipeline always sets up a provider"),
+        None => TracerProvider::builder().with_config(trace_config).build(),
+    };
+    let tracer = provider.tracer("users");
+    global::set_tracer_provider(provider);
+
+    // a single filter for both: per-layer filters lose spans on the way to
+    // the OpenTelemetry layer depending on what RUST_LOG enables
+    tracing_subscriber::registry()
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or(EnvFilter::new("info")),
+        )
+        // one of the two
+        .with((log.format == LogFormat::Json).then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_span_list(false)
+        }))
+        .with(
+            (log.format == LogFormat::Pretty)
+                .then(tracing_subscriber::fmt::layer),
+        )
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+
+    Ok(())
+}
+
+/// Flushes the spans that haven't been exported yet.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// The trace the caller is in, from its `traceparent` header.
+pub fn remote_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    })
+}
+
+/// Headers that carry the current span over to the service we're calling.
+pub fn propagation_headers() -> HashMap<String, String> {
+    let context = tracing::Span::current().context();
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut headers)
+    });
+
+    headers
+}
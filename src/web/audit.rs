@@ -0,0 +1,23 @@
+//! The security audit log: who did what to which account, and from where.
+
+use tracing::error;
+
+use super::{
+    middlewares::request_id::RequestContext,
+    models::audit_events::NewAuditEvent,
+    repositories::AuditRepository,
+};
+
+/// Appends `event`, along with the address, user agent and id of the
+/// request being handled. Failing to do so is logged, but doesn't fail the
+/// request: whatever it's about already happened.
+pub async fn record(audit: &dyn AuditRepository, event: NewAuditEvent) {
+    let event = match RequestContext::current() {
+        Some(context) => event.from_request(&context),
+        None => event,
+    };
+    let kind = event.kind;
+    if let Err(err) = audit.append(event).await {
+        error!("couldn't record a `{}` audit event: {err:?}", kind.as_str());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::Utc;
+use tracing::info;
+
+use crate::{
+    log_util::LoggableOutcome,
+    web::{
+        dto::{
+            me::notification_settings::NotificationSettings,
+            notification_payload::NotificationPayload,
+        },
+        i18n,
+        locale::{self, Locale},
+        mail::Mailer,
+        push::{self, PushClient, PushMessage},
+        repositories::{NotificationRepository, UserRepository},
+    },
+};
+
+/// Somebody to deliver a notification to, along with the id it was stored
+/// with.
+pub struct DeliveryTarget {
+    pub user_id: String,
+    pub notification_id: String,
+}
+
+/// Delivers a notification on the push and email channels, following the
+/// preferences in `settings` (users missing from it use the defaults), with
+/// text rendered in each recipient's locale and time zone. Without a
+/// `push_client` only emails go out.
+/// Failures are only logged: the notifications are stored anyway.
+pub async fn deliver(
+    users: Arc<dyn UserRepository>,
+    notifications: Arc<dyn NotificationRepository>,
+    push_client: Option<PushClient>,
+    mailer: Option<Mailer>,
+    payload: NotificationPayload,
+    targets: Vec<DeliveryTarget>,
+    settings: HashMap<String, NotificationSettings>,
+) {
+    let sender = match payload.sender_id() {
+        Some(id) => users.by_id(id)
+            .await
+            .log_err_to_warn("couldn't load the notification sender")
+            .ok()
+            .flatten(),
+        None => None,
+    };
+    let user_ids: Vec<String> =
+        targets.iter().map(|t| t.user_id.clone()).collect();
+    let Ok(recipients) = users.recipients(&user_ids)
+        .await
+        .log_err_to_error("couldn't load recipients")
+    else {
+        return;
+    };
+    let tokens = match &push_client {
+        Some(_) => users.fcm_tokens(&user_ids)
+            .await
+            .log_err_to_error("couldn't load fcm tokens")
+            .unwrap_or_default(),
+        None => vec![],
+    };
+
+    let now = Utc::now();
+    let defaults = NotificationSettings::default();
+    for target in targets {
+        let Some(recipient) = recipients.iter().find(|r| r.id == target.user_id)
+        else {
+            continue;
+        };
+        let settings = settings.get(&target.user_id).unwrap_or(&defaults);
+        if settings.is_muted(&payload) {
+            continue;
+        }
+        let locale = Locale::from_code(&recipient.locale);
+        let Some((title, body)) = push::render(
+            &payload,
+            sender.as_ref(),
+            locale,
+            locale::time_zone(&recipient.time_zone),
+            now,
+        ) else {
+            continue;
+        };
+
+        let channels = settings.channels_for(payload.kind());
+        if let (true, Some(client)) = (channels.push, &push_client) {
+            if settings.in_quiet_hours(now, &recipient.time_zone) {
+                // the digest job sends it when quiet hours are over
+                let _ = notifications
+                    .hold_push(&target.user_id, &title, &body)
+                    .await
+                .log_err_to_error("couldn't hold push");
+            } else {
+                let tokens: Vec<String> = tokens
+                    .iter()
+                    .filter(|(user_id, _)| *user_id == target.user_id)
+                    .map(|(_, token)| token.clone())
+                    .collect();
+                push_to(client, &target, tokens, &title, &body).await;
+            }
+        }
+        if let (true, Some(mailer)) = (channels.email, &mailer) {
+            let body = format!("{body}\n\n{}", i18n::t(locale, "mail.footer", &[]));
+            if mailer
+                .send(&recipient.email, &title, &body)
+                .await
+                .log_err_to_warn("email delivery failed")
+                .is_ok()
+            {
+                info!("mailed a notification to {}", target.user_id);
+            }
+        }
+    }
+}
+
+async fn push_to(
+    client: &PushClient,
+    target: &DeliveryTarget,
+    tokens: Vec<String>,
+    title: &str,
+    body: &str,
+) {
+    if tokens.is_empty() {
+        return;
+    }
+
+    let message = PushMessage {
+        tokens,
+        title: title.to_string(),
+        body: body.to_string(),
+        data: HashMap::from([(
+            "notification_id".to_string(),
+            target.notification_id.clone(),
+        )]),
+    };
+    if client
+        .send(&message)
+        .await
+        .log_err_to_warn("push delivery failed")
+        .is_ok()
+    {
+        info!("pushed a notification to {}", target.user_id);
+    }
+}
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::web::models::audit_events::{AuditEvent, AuditFilter, AuditKind};
+
+#[derive(Deserialize, Validate, IntoParams)]
+pub struct AuditEventsQuery {
+    /// the account the events are about
+    pub user_id: Option<String>,
+    /// who did it: a user id, a service or an administrator's name
+    pub actor_id: Option<String>,
+    pub kind: Option<AuditKind>,
+    pub ip: Option<String>,
+    /// RFC 3339, inclusive
+    pub since: Option<DateTime<Utc>>,
+    /// RFC 3339, exclusive
+    pub until: Option<DateTime<Utc>>,
+    /// only events older than this id, for the next page
+    pub before: Option<i64>,
+    /// how many events to return, 50 when left out
+    #[validate(range(min = 1, max = 1000))]
+    pub limit: Option<i64>,
+}
+
+impl From<AuditEventsQuery> for AuditFilter {
+    fn from(query: AuditEventsQuery) -> Self {
+        AuditFilter {
+            user_id: query.user_id,
+            actor_id: query.actor_id,
+            kind: query.kind,
+            ip: query.ip,
+            since: query.since,
+            until: query.until,
+            before: query.before,
+            limit: query.limit.unwrap_or(AuditFilter::DEFAULT_LIMIT),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditEventsResponse {
+    pub success: bool,
+    /// newest first
+    pub events: Vec<AuditEvent>,
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use crate::web::{
+    models::{outbox::OutboxKind, webhooks::WebhookSubscription},
+    outbound::{self, Refused},
+};
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct CreateWebhookRequest {
+    /// where events are POSTed, `http` or `https`, on a public address
+    #[validate(custom = "validate_webhook_url")]
+    #[schema(example = "https://partner.example.com/pieno")]
+    pub url: String,
+    /// the event types to send, every one of them when left out or empty
+    #[serde(default)]
+    pub events: Vec<OutboxKind>,
+    /// signs the payloads; one is generated when left out
+    #[validate(length(min = 16, max = 255))]
+    pub secret: Option<String>,
+    #[validate(length(max = 255))]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateWebhookResponse {
+    pub success: bool,
+    pub webhook: WebhookSubscription,
+    /// the signing secret, never shown again
+    pub secret: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhooksResponse {
+    pub success: bool,
+    /// oldest first
+    pub webhooks: Vec<WebhookSubscription>,
+}
+
+pub fn validate_webhook_url(url: &str) -> Result<(), ValidationError> {
+    let url = reqwest::Url::parse(url)
+        .map_err(|_| ValidationError::new("invalid_url"))?;
+    match outbound::check_url(&url) {
+        Ok(()) => Ok(()),
+        Err(Refused::Scheme) => Err(ValidationError::new("invalid_url")),
+        Err(_) => Err(ValidationError::new("private_url")),
+    }
+}
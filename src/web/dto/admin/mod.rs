@@ -0,0 +1,4 @@
+pub mod audit_events_query;
+pub mod create_webhook_request;
+pub mod update_webhook_request;
+pub mod webhook_deliveries_query;
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::web::{
+    dto::admin::create_webhook_request::validate_webhook_url,
+    models::{
+        outbox::OutboxKind,
+        webhooks::{WebhookSubscription, WebhookUpdate},
+    },
+};
+
+/// Only what's given changes.
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct UpdateWebhookRequest {
+    #[validate(custom = "validate_webhook_url")]
+    pub url: Option<String>,
+    /// every event type when empty
+    pub events: Option<Vec<OutboxKind>>,
+    /// a new signing secret, used from the next attempt on
+    #[validate(length(min = 16, max = 255))]
+    pub secret: Option<String>,
+    #[validate(length(max = 255))]
+    pub description: Option<String>,
+    /// nothing is sent to a disabled webhook, its deliveries wait until it's
+    /// enabled again
+    pub enabled: Option<bool>,
+}
+
+impl From<UpdateWebhookRequest> for WebhookUpdate {
+    fn from(request: UpdateWebhookRequest) -> Self {
+        WebhookUpdate {
+            url: request.url,
+            events: request.events,
+            secret: request.secret,
+            description: request.description,
+            enabled: request.enabled,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookResponse {
+    pub success: bool,
+    pub webhook: WebhookSubscription,
+}
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::web::models::webhooks::{
+    DeliveryFilter, DeliveryState, WebhookDelivery,
+};
+
+#[derive(Deserialize, Validate, IntoParams)]
+pub struct WebhookDeliveriesQuery {
+    pub state: Option<DeliveryState>,
+    /// only deliveries older than this id, for the next page
+    pub before: Option<i64>,
+    /// how many deliveries to return, 50 when left out
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+}
+
+impl From<WebhookDeliveriesQuery> for DeliveryFilter {
+    fn from(query: WebhookDeliveriesQuery) -> Self {
+        DeliveryFilter {
+            state: query.state,
+            before: query.before,
+            limit: query.limit.unwrap_or(DeliveryFilter::DEFAULT_LIMIT),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookDeliveriesResponse {
+    pub success: bool,
+    /// newest first
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookDeliveryResponse {
+    pub success: bool,
+    pub delivery: WebhookDelivery,
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{config::JwtConfig, web::dto::Audience};
+
+/// Claims of the tokens back-office tools call the `/admin` endpoints with.
+/// They're signed with their own secret, so holding the one of service
+/// tokens isn't enough to sign one, and are meant for the `pieno-admin`
+/// audience.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct AdminClaims {
+    /// the tool calling, one of `ADMIN_SERVICES`
+    pub service: String,
+}
+
+impl Audience for AdminClaims {
+    const AUDIENCE: &'static str = "pieno-admin";
+
+    fn secret(jwt: &JwtConfig) -> &str {
+        &jwt.admin_secret
+    }
+}
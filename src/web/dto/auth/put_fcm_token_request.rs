@@ -1,7 +1,9 @@
 use serde::Deserialize;
+use utoipa::ToSchema;
 use validator::Validate;
 
-#[derive(Deserialize, Validate)]
+#[derive(Deserialize, Validate, ToSchema)]
 pub struct PutFcmTokenRequest {
+    /// The Firebase Cloud Messaging token
     pub token: String
 }
\ No newline at end of file
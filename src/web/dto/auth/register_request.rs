@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use validator::Validate;
 
+use crate::web::locale::Locale;
+
 #[derive(Deserialize, Validate, ToSchema)]
 pub struct RegisterRequest {
     #[validate(email)]
@@ -9,7 +11,12 @@ pub struct RegisterRequest {
     pub name: String,
     pub surname: String,
     #[validate(length(min=8))]
-    pub password: String
+    pub password: String,
+    #[serde(default)]
+    pub locale: Locale,
+    /// IANA time zone, defaults to UTC
+    #[validate(custom = "crate::web::locale::validate_time_zone")]
+    pub time_zone: Option<String>,
 }
 
 #[derive(Serialize, ToSchema)]
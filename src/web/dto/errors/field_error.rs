@@ -0,0 +1,17 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+/// Something wrong with a single field of the request.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct FieldError {
+    /// path of the field, e.g. `password`, `quiet_hours.time_zone` or
+    /// `recipients[2]`
+    pub field: String,
+    /// what's wrong with it, e.g. `length` or `invalid_time_zone`
+    pub code: String,
+    pub message: String,
+    /// the constraint that failed, e.g. `{"min": 8}`
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+}
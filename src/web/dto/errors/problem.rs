@@ -0,0 +1,35 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::web::{dto::errors::field_error::FieldError, errors::code::ErrorCode};
+
+/// An RFC 7807 problem, the body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct Problem {
+    /// `urn:pieno:users:error:<code>`
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// path of the request that failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub code: ErrorCode,
+    /// matches the `X-Request-Id` header and our logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// what didn't pass validation, or couldn't be deserialized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldError>>,
+    /// where a malformed JSON body stops making sense
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+}
+
+#[derive(Serialize, ToSchema, Clone, Copy, Debug)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
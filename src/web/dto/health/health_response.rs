@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    pub success: bool,
+    /// check name => its outcome, e.g. `database`, `schema`, `push`, `mail`
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CheckResult {
+    pub ok: bool,
+    /// why it failed: `unavailable`, `timed_out` or `shutting_down`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+impl CheckResult {
+    pub fn passed() -> Self {
+        CheckResult { ok: true, error: None }
+    }
+
+    pub fn failed(code: &'static str) -> Self {
+        CheckResult {
+            ok: false,
+            error: Some(code),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use crate::web::{
+    dto::notification_payload::{
+        CarInvitePayload, NotificationPayload, RefuelPayload,
+    },
+    models::notifications::CreatedNotification,
+};
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct CreateNotificationsRequest {
+    /// ids of the users to notify. Unknown users are skipped.
+    #[validate(length(min = 1, max = 1000))]
+    pub recipients: Vec<String>,
+    #[validate(custom = "validate_payload")]
+    pub payload: NotificationPayload,
+    /// also push the notification to the devices of recipients who want
+    /// pushes for its type; emails follow their settings either way
+    #[serde(default)]
+    pub push: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateNotificationsResponse {
+    pub success: bool,
+    pub notifications: Vec<CreatedNotification>,
+    /// recipients that don't exist
+    pub skipped: Vec<String>,
+    /// whether the idempotency key was already used, meaning (some of) the
+    /// notifications were created by an earlier call
+    pub replayed: bool,
+}
+
+fn validate_payload(
+    payload: &NotificationPayload,
+) -> Result<(), ValidationError> {
+    let valid = match payload {
+        NotificationPayload::Refuel(p) => {
+            p.v == RefuelPayload::VERSION
+                && !p.owner.is_empty()
+                && !p.car_name.is_empty()
+                && [p.tank_size, p.consumption, p.fuel_value]
+                    .iter()
+                    .all(|n| n.is_finite() && *n >= 0.0)
+        }
+        NotificationPayload::CarInvite(p) => {
+            p.v == CarInvitePayload::VERSION
+                && !p.owner.is_empty()
+                && !p.car_id.is_empty()
+                && !p.car_name.is_empty()
+        }
+        // can't be deserialized, but new producers must know what they send
+        NotificationPayload::Unknown(_) => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_payload"))
+    }
+}
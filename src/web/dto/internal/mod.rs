@@ -0,0 +1 @@
+pub mod create_notifications_request;
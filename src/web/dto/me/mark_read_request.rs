@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct MarkAllReadRequest {
+    /// id of the newest notification to mark as read; everything created
+    /// up to it is marked too. When missing, every notification is marked.
+    #[validate(length(min = 1))]
+    pub until: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MarkReadResponse {
+    pub success: bool,
+    pub marked: u64,
+    pub unread: i64,
+}
@@ -1,2 +1,5 @@
+pub mod mark_read_request;
+pub mod notification_settings;
 pub mod notifications;
+pub mod security_events;
 pub mod update_profile_request;
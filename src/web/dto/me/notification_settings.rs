@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use crate::web::{
+    dto::notification_payload::{NotificationPayload, KNOWN_TYPES},
+    locale,
+};
+
+/// Where a notification type gets delivered.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq)]
+pub struct ChannelSettings {
+    pub push: bool,
+    pub email: bool,
+    pub in_app: bool,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            push: true,
+            email: false,
+            in_app: true,
+        }
+    }
+}
+
+/// Window, in the time zone of the user's profile, during which pushes are
+/// held back and later delivered as a single digest. It can wrap around
+/// midnight.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct QuietHours {
+    #[schema(value_type = String, example = "22:00")]
+    pub start: NaiveTime,
+    #[schema(value_type = String, example = "07:30")]
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, now: DateTime<Utc>, time_zone: Tz) -> bool {
+        let local = now.with_timezone(&time_zone).time();
+        if self.start <= self.end {
+            local >= self.start && local < self.end
+        } else {
+            local >= self.start || local < self.end
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate, ToSchema, Clone, Debug, Default)]
+pub struct NotificationSettings {
+    /// channels for each notification type; types that aren't listed use
+    /// push and in-app only
+    #[serde(default)]
+    #[validate(custom = "validate_channels")]
+    pub channels: HashMap<String, ChannelSettings>,
+    /// users whose notifications never get pushed or mailed
+    #[serde(default)]
+    #[validate(length(max = 500))]
+    pub muted_senders: Vec<String>,
+    /// cars whose notifications never get pushed or mailed
+    #[serde(default)]
+    #[validate(length(max = 500))]
+    pub muted_cars: Vec<String>,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl NotificationSettings {
+    pub fn channels_for(&self, kind: &str) -> ChannelSettings {
+        self.channels.get(kind).copied().unwrap_or_default()
+    }
+
+    /// Muted notifications are still stored, they just don't reach the user
+    /// on any other channel.
+    pub fn is_muted(&self, payload: &NotificationPayload) -> bool {
+        let muted_sender = payload
+            .sender_id()
+            .is_some_and(|id| self.muted_senders.iter().any(|m| m == id));
+        let muted_car = payload
+            .car_id()
+            .is_some_and(|id| self.muted_cars.iter().any(|m| m == id));
+
+        muted_sender || muted_car
+    }
+
+    /// `time_zone` is the one of the user's profile.
+    pub fn in_quiet_hours(&self, now: DateTime<Utc>, time_zone: &str) -> bool {
+        self.quiet_hours
+            .as_ref()
+            .is_some_and(|q| q.contains(now, locale::time_zone(time_zone)))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct NotificationSettingsResponse {
+    pub success: bool,
+    pub settings: NotificationSettings,
+}
+
+fn validate_channels(
+    channels: &HashMap<String, ChannelSettings>,
+) -> Result<(), ValidationError> {
+    if channels.keys().all(|kind| KNOWN_TYPES.contains(&kind.as_str())) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("unknown_notification_type"))
+    }
+}
@@ -1,6 +1,14 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use sqlx::types::Json;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::web::{
+    dto::notification_payload::NotificationPayload,
+    models::users::PublicUserModel,
+};
 
 #[derive(Serialize, ToSchema)]
 pub struct NotificationResponse {
@@ -8,19 +16,105 @@ pub struct NotificationResponse {
     pub notifications: Vec<Notification>,
 }
 
-#[derive(sqlx::FromRow, Serialize, Deserialize, ToSchema, Debug)]
+#[derive(Deserialize, Validate, IntoParams)]
+pub struct NotificationsQuery {
+    /// list archived notifications instead of the active ones
+    #[serde(default)]
+    pub archived: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UnreadCountResponse {
+    pub success: bool,
+    pub unread: i64,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
 pub struct Notification {
     id: String,
     to_user: String,
-    created_at: String,
-    data: Json<NotificationData>,
+    created_at: DateTime<Utc>,
+    read_at: Option<DateTime<Utc>>,
+    data: NotificationPayload,
+    /// public profile of whoever triggered the notification, when they
+    /// still have an account
+    sender: Option<PublicUserModel>,
+    /// the payload as it's stored, before it was decoded
+    #[serde(skip)]
+    stored: Value,
 }
 
-#[derive(sqlx::Decode, Serialize, Deserialize, ToSchema, Debug)]
-pub struct NotificationData {
-    owner: String,
-    car_name: String,
-    tank_size: String,
-    consumption: String,
-    fuel_value: String,
+impl Notification {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The shape served before `/v1`, `None` for anything but refuels,
+    /// which were all there was: amounts as the strings they were stored as
+    /// and the creation time as Postgres' `dd-mm-yyyy HH24:MI:SS (utc)`.
+    pub fn legacy(&self) -> Option<Value> {
+        let NotificationPayload::Refuel(refuel) = &self.data else {
+            return None;
+        };
+        let amount = |field: &str, decoded: f64| match &self.stored[field] {
+            Value::String(amount) => amount.clone(),
+            Value::Number(amount) => amount.to_string(),
+            _ => decoded.to_string(),
+        };
+
+        Some(json!({
+            "id": self.id,
+            "to_user": self.to_user,
+            "created_at": self
+                .created_at
+                .format("%d-%m-%Y %H:%M:%S (utc)")
+                .to_string(),
+            "data": {
+                "owner": refuel.owner,
+                "car_name": refuel.car_name,
+                "tank_size": amount("tank_size", refuel.tank_size),
+                "consumption": amount("consumption", refuel.consumption),
+                "fuel_value": amount("fuel_value", refuel.fuel_value),
+            },
+        }))
+    }
+}
+
+/// A notification as it's read from the database, joined with its sender.
+#[derive(sqlx::FromRow)]
+pub struct NotificationRow {
+    pub id: String,
+    pub to_user: String,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+    pub data: Json<Value>,
+    pub sender_id: Option<String>,
+    pub sender_name: Option<String>,
+    pub sender_surname: Option<String>,
+    pub sender_propic_url: Option<String>,
+}
+
+impl From<NotificationRow> for Notification {
+    fn from(row: NotificationRow) -> Self {
+        let sender = match (row.sender_id, row.sender_name, row.sender_surname)
+        {
+            (Some(id), Some(name), Some(surname)) => Some(PublicUserModel {
+                id,
+                name,
+                surname,
+                propic_url: row.sender_propic_url,
+            }),
+            _ => None,
+        };
+
+        Notification {
+            id: row.id,
+            to_user: row.to_user,
+            created_at: row.created_at,
+            read_at: row.read_at,
+            data: NotificationPayload::from_value(row.data.0.clone()),
+            sender,
+            stored: row.data.0,
+        }
+    }
 }
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::web::models::audit_events::AuditEvent;
+
+#[derive(Deserialize, Validate, IntoParams)]
+pub struct SecurityEventsQuery {
+    /// only events older than this id, for the next page
+    pub before: Option<i64>,
+    /// how many events to return, 50 when left out
+    #[validate(range(min = 1, max = 200))]
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SecurityEventsResponse {
+    pub success: bool,
+    /// newest first
+    pub events: Vec<AuditEvent>,
+}
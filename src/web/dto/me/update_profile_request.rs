@@ -1 +1,14 @@
-pub struct UpdateProfileRequest {}
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::web::locale::Locale;
+
+#[derive(Deserialize, Validate, ToSchema)]
+pub struct UpdateProfileRequest {
+    /// language of emails, pushes and anything else rendered server side
+    pub locale: Option<Locale>,
+    /// IANA time zone, e.g. `Europe/Rome`
+    #[validate(custom = "crate::web::locale::validate_time_zone")]
+    pub time_zone: Option<String>,
+}
@@ -1,34 +1,62 @@
-use std::{env, time::{SystemTime, UNIX_EPOCH}};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
+use crate::config::JwtConfig;
+
+pub mod admin;
+pub mod admin_claims;
 pub mod auth;
+pub mod errors;
+pub mod health;
+pub mod internal;
+pub mod notification_payload;
+pub mod service_claims;
 pub mod user_claims;
 pub mod me;
 
+/// Who a kind of token is for, and the secret it's signed with: tokens of
+/// one kind are never accepted as another.
+pub trait Audience {
+    const AUDIENCE: &'static str;
+    /// whether tokens without an `aud` are refused
+    const REQUIRES_AUDIENCE: bool = true;
+
+    fn secret(jwt: &JwtConfig) -> &str;
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Claim<C> 
     where C: Send + Serialize + 'static
 {
     exp: usize,
+    // always set on new tokens, see `Audience::REQUIRES_AUDIENCE`
+    #[serde(default)]
+    aud: Option<String>,
     data: C
 }
 
 impl<C> Claim<C>
-    where C: Send + Serialize + 'static
+    where C: Audience + Send + Serialize + 'static
 {
 
-    pub fn from(item: C) -> Claim<C> {
+    pub fn from(item: C, timeout: Duration) -> Claim<C> {
 
-        let jwt_timeout: Result<u64, _> = env::var("JWT_TIMEOUT_SECS").unwrap_or("7200".to_string()).parse(); // or 2h
-        let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + jwt_timeout.ok().unwrap(); // we have the default
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let exp = now + timeout.as_secs();
 
         Claim {
             exp: exp as usize, // suck it 32-bit computers...
+            aud: Some(C::AUDIENCE.to_string()),
             data: item
         }
 
     }
+}
+
+impl<C> Claim<C>
+    where C: Send + Serialize + 'static
+{
 
     pub fn exp(&self) -> usize {
         self.exp
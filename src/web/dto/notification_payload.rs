@@ -0,0 +1,286 @@
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::log_util::LoggableOutcome;
+
+/// Type of the payloads written before they were tagged: back then the only
+/// notifications around were refuels.
+pub const LEGACY_TYPE: &str = "refuel";
+
+/// Every `type` this version of the service can decode.
+pub const KNOWN_TYPES: &[&str] = &["refuel", "car_invite"];
+
+/// What a notification is about, tagged by `type`. Every kind carries the
+/// version of its own schema in `v`, so it can evolve without breaking the
+/// rows that are already stored.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationPayload {
+    Refuel(RefuelPayload),
+    CarInvite(CarInvitePayload),
+    /// A payload this version of the service can't decode, passed through
+    /// as is so a single odd row doesn't break the whole list.
+    #[serde(skip_deserializing)]
+    Unknown(UnknownPayload),
+}
+
+/// Someone refueled a car the user shares.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct RefuelPayload {
+    #[serde(default = "first_version")]
+    pub v: u32,
+    /// id of the user who refueled
+    pub owner: String,
+    pub car_id: Option<String>,
+    pub car_name: String,
+    /// liters
+    #[serde(deserialize_with = "number_or_string")]
+    pub tank_size: f64,
+    /// liters per 100 km
+    #[serde(deserialize_with = "number_or_string")]
+    pub consumption: f64,
+    /// liters put in the tank
+    #[serde(deserialize_with = "number_or_string")]
+    pub fuel_value: f64,
+}
+
+impl RefuelPayload {
+    pub const VERSION: u32 = 1;
+}
+
+/// The user has been invited to share a car.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct CarInvitePayload {
+    #[serde(default = "first_version")]
+    pub v: u32,
+    /// id of the user who sent the invite
+    pub owner: String,
+    pub car_id: String,
+    pub car_name: String,
+}
+
+impl CarInvitePayload {
+    pub const VERSION: u32 = 1;
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone, PartialEq)]
+pub struct UnknownPayload {
+    /// the `type` found in the payload, if any
+    pub original_type: Option<String>,
+    #[schema(value_type = Object)]
+    pub raw: Value,
+}
+
+impl NotificationPayload {
+    /// Decodes a stored payload without ever failing: payloads without a
+    /// type are legacy refuels, while unknown types or versions newer than
+    /// the ones we know end up in [`NotificationPayload::Unknown`].
+    pub fn from_value(raw: Value) -> NotificationPayload {
+        let mut tagged = raw.clone();
+        if let Value::Object(map) = &mut tagged {
+            map.entry("type").or_insert(LEGACY_TYPE.into());
+        }
+
+        match serde_json::from_value::<NotificationPayload>(tagged)
+            .log_err_to_debug("undecodable notification payload")
+        {
+            Ok(payload) if payload.is_supported() => payload,
+            _ => NotificationPayload::Unknown(UnknownPayload {
+                original_type: raw
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                raw,
+            }),
+        }
+    }
+
+    fn is_supported(&self) -> bool {
+        match self {
+            NotificationPayload::Refuel(p) => p.v <= RefuelPayload::VERSION,
+            NotificationPayload::CarInvite(p) => {
+                p.v <= CarInvitePayload::VERSION
+            }
+            NotificationPayload::Unknown(_) => false,
+        }
+    }
+
+    /// The `type` tag, as stored.
+    pub fn kind(&self) -> &str {
+        match self {
+            NotificationPayload::Refuel(_) => "refuel",
+            NotificationPayload::CarInvite(_) => "car_invite",
+            NotificationPayload::Unknown(p) => {
+                p.original_type.as_deref().unwrap_or("unknown")
+            }
+        }
+    }
+
+    /// id of the car the notification is about, if any
+    pub fn car_id(&self) -> Option<&str> {
+        match self {
+            NotificationPayload::Refuel(p) => p.car_id.as_deref(),
+            NotificationPayload::CarInvite(p) => Some(&p.car_id),
+            NotificationPayload::Unknown(p) => {
+                p.raw.get("car_id").and_then(Value::as_str)
+            }
+        }
+    }
+
+    /// id of the user who triggered the notification
+    pub fn sender_id(&self) -> Option<&str> {
+        match self {
+            NotificationPayload::Refuel(p) => Some(&p.owner),
+            NotificationPayload::CarInvite(p) => Some(&p.owner),
+            NotificationPayload::Unknown(p) => {
+                p.raw.get("owner").and_then(Value::as_str)
+            }
+        }
+    }
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// Older producers wrote every number as a string (sometimes with a decimal
+/// comma), so both forms are accepted.
+fn number_or_string<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NumberOrString {
+        Number(f64),
+        String(String),
+    }
+
+    match NumberOrString::deserialize(deserializer)? {
+        NumberOrString::Number(n) => Ok(n),
+        NumberOrString::String(s) => {
+            s.trim().replace(',', ".").parse().map_err(D::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn refuel(
+        v: u32,
+        tank_size: f64,
+        consumption: f64,
+        fuel_value: f64,
+    ) -> NotificationPayload {
+        NotificationPayload::Refuel(RefuelPayload {
+            v,
+            owner: "mario".to_string(),
+            car_id: None,
+            car_name: "Panda".to_string(),
+            tank_size,
+            consumption,
+            fuel_value,
+        })
+    }
+
+    fn unknown(original_type: Option<&str>, raw: Value) -> NotificationPayload {
+        NotificationPayload::Unknown(UnknownPayload {
+            original_type: original_type.map(str::to_string),
+            raw,
+        })
+    }
+
+    #[test]
+    fn payloads_are_decoded() {
+        let legacy = json!({
+            "owner": "mario",
+            "car_name": "Panda",
+            "tank_size": "35.0",
+            "consumption": "5,2",
+            "fuel_value": " 20 ",
+        });
+        let newer_refuel = json!({
+            "type": "refuel",
+            "v": 2,
+            "owner": "mario",
+            "car_name": "Panda",
+            "tank_size": 35,
+            "consumption": 5,
+            "fuel_value": 20,
+        });
+        let newer_invite = json!({
+            "type": "car_invite",
+            "v": 2,
+            "owner": "mario",
+            "car_id": "car-1",
+            "car_name": "Panda",
+        });
+        let promo = json!({"type": "promo", "v": 1, "owner": "mario"});
+        let broken =
+            json!({"type": "refuel", "owner": "mario", "tank_size": "a lot"});
+        let cases = [
+            // written before payloads were typed: a refuel, numbers as
+            // strings, some with a decimal comma
+            (legacy, refuel(1, 35.0, 5.2, 20.0)),
+            (
+                json!({
+                    "type": "refuel",
+                    "owner": "mario",
+                    "car_name": "Panda",
+                    "tank_size": 35.5,
+                    "consumption": "5.1",
+                    "fuel_value": 20,
+                }),
+                refuel(1, 35.5, 5.1, 20.0),
+            ),
+            (
+                json!({
+                    "type": "car_invite",
+                    "v": 1,
+                    "owner": "mario",
+                    "car_id": "car-1",
+                    "car_name": "Panda",
+                }),
+                NotificationPayload::CarInvite(CarInvitePayload {
+                    v: 1,
+                    owner: "mario".to_string(),
+                    car_id: "car-1".to_string(),
+                    car_name: "Panda".to_string(),
+                }),
+            ),
+            // versions from the future, unknown types and broken rows are
+            // passed through as they are
+            (newer_refuel.clone(), unknown(Some("refuel"), newer_refuel)),
+            (newer_invite.clone(), unknown(Some("car_invite"), newer_invite)),
+            (promo.clone(), unknown(Some("promo"), promo)),
+            (broken.clone(), unknown(Some("refuel"), broken)),
+            (json!("refuel"), unknown(None, json!("refuel"))),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(
+                NotificationPayload::from_value(raw.clone()),
+                expected,
+                "{raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_payloads_keep_what_can_be_told() {
+        let payload = NotificationPayload::from_value(
+            json!({"type": "promo", "owner": "mario", "car_id": "car-1"}),
+        );
+        assert_eq!(payload.kind(), "promo");
+        assert_eq!(payload.sender_id(), Some("mario"));
+        assert_eq!(payload.car_id(), Some("car-1"));
+
+        let payload = NotificationPayload::from_value(json!(["refuel"]));
+        assert_eq!(payload.kind(), "unknown");
+        assert_eq!(payload.sender_id(), None);
+    }
+}
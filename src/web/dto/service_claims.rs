@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{config::JwtConfig, web::dto::Audience};
+
+/// Claims of the tokens other PIENO services use to call the internal
+/// endpoints. They're signed with the service secret, never the one of user
+/// tokens, and are meant for the `pieno-services` audience.
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct ServiceClaims {
+    pub service: String,
+}
+
+impl Audience for ServiceClaims {
+    const AUDIENCE: &'static str = "pieno-services";
+
+    fn secret(jwt: &JwtConfig) -> &str {
+        &jwt.service_secret
+    }
+}
@@ -1,9 +1,30 @@
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+
+use crate::{config::JwtConfig, web::dto::Audience};
+
 #[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct UserClaims {
     pub user_id: String,
     pub name: String,
     pub surname: String,   
-    pub propic_url: Option<String>
-}
\ No newline at end of file
+    pub propic_url: Option<String>,
+    /// the user's token generation when this was issued, see
+    /// `User::token_generation`. tokens from before it existed have none
+    #[serde(default)]
+    pub generation: i32,
+}
+
+impl Audience for UserClaims {
+    const AUDIENCE: &'static str = "pieno-users";
+    // user tokens issued before audiences existed have none, and logging
+    // everyone out for it isn't worth it: they're all expired once
+    // `JWT_TIMEOUT` has passed since, and new ones always have one. Service
+    // and admin tokens are signed with their own secrets, so a token
+    // without an `aud` that checks out is a user token either way
+    const REQUIRES_AUDIENCE: bool = false;
+
+    fn secret(jwt: &JwtConfig) -> &str {
+        &jwt.secret
+    }
+}
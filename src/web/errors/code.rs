@@ -0,0 +1,180 @@
+use axum::http::StatusCode;
+use serde::{Serialize, Serializer};
+use strum::VariantArray;
+use utoipa::{
+    openapi::{ObjectBuilder, RefOr, Schema, SchemaType},
+    ToSchema,
+};
+
+use crate::web::{i18n, locale::Locale};
+
+/// Every error the API can answer with. Codes are part of the contract:
+/// new ones can be added, but existing ones never change meaning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, VariantArray)]
+pub enum ErrorCode {
+    /// the body isn't valid JSON, or doesn't have the expected shape
+    InvalidBody,
+    /// some fields didn't pass validation
+    InvalidFields,
+    /// the query string doesn't have the expected shape
+    InvalidQuery,
+    /// the notification payload isn't one we can store
+    InvalidPayload,
+    /// no `Authorization` header (nor, on streams, an `access_token` parameter)
+    NoAuthHeader,
+    /// the `Authorization` header isn't a bearer token
+    NoBearerSpecified,
+    /// the `Authorization` header isn't valid ASCII
+    InvalidAuthHeader,
+    /// the token is fine, but the account it belongs to is gone
+    AccountUnavailable,
+    /// wrong email or password, or a token of an account that's gone
+    InvalidCredentials,
+    /// the token is malformed, expired or not signed by us
+    InvalidToken,
+    /// the token was issued before a logout everywhere
+    TokenRevoked,
+    /// the account has been disabled by an administrator
+    AccountDisabled,
+    /// the admin token isn't of one of `ADMIN_SERVICES`
+    AdminOnly,
+    UserNotFound,
+    NotificationNotFound,
+    WebhookNotFound,
+    WebhookDeliveryNotFound,
+    /// something that has to be unique, like an email, is already there
+    DuplicateRow,
+    /// the `Idempotency-Key` header is empty, too long or not printable
+    InvalidIdempotencyKey,
+    /// a call with the same idempotency key is still being handled
+    IdempotencyKeyInProgress,
+    /// the idempotency key was already used with a different payload
+    IdempotencyKeyReused,
+    /// the database failed us; details are only in the logs
+    DatabaseError,
+    InternalServerError,
+}
+
+impl ErrorCode {
+    /// Derived, so a new code can't be left out of the docs and catalogs.
+    pub const ALL: &'static [ErrorCode] = <ErrorCode as VariantArray>::VARIANTS;
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidBody
+            | ErrorCode::InvalidFields
+            | ErrorCode::InvalidQuery
+            | ErrorCode::InvalidPayload
+            | ErrorCode::NoAuthHeader
+            | ErrorCode::NoBearerSpecified
+            | ErrorCode::InvalidAuthHeader
+            | ErrorCode::AccountUnavailable
+            | ErrorCode::InvalidIdempotencyKey => StatusCode::BAD_REQUEST,
+            ErrorCode::InvalidCredentials
+            | ErrorCode::InvalidToken
+            | ErrorCode::TokenRevoked => StatusCode::UNAUTHORIZED,
+            ErrorCode::AccountDisabled | ErrorCode::AdminOnly => {
+                StatusCode::FORBIDDEN
+            }
+            ErrorCode::UserNotFound
+            | ErrorCode::NotificationNotFound
+            | ErrorCode::WebhookNotFound
+            | ErrorCode::WebhookDeliveryNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::DuplicateRow | ErrorCode::IdempotencyKeyInProgress => {
+                StatusCode::CONFLICT
+            }
+            ErrorCode::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::DatabaseError | ErrorCode::InternalServerError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Short summary, the same for every occurrence, from the message
+    /// catalog of `locale`.
+    pub fn title(self, locale: Locale) -> String {
+        i18n::t(locale, &format!("error.{}.title", self.as_str()), &[])
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::InvalidBody => "invalid_body",
+            ErrorCode::InvalidFields => "invalid_fields",
+            ErrorCode::InvalidQuery => "invalid_query",
+            ErrorCode::InvalidPayload => "invalid_payload",
+            ErrorCode::NoAuthHeader => "no_auth_header",
+            ErrorCode::NoBearerSpecified => "no_bearer_specified",
+            ErrorCode::InvalidAuthHeader => "invalid_auth_header",
+            ErrorCode::AccountUnavailable => "account_unavailable",
+            ErrorCode::InvalidCredentials => "invalid_credentials",
+            ErrorCode::InvalidToken => "invalid_token",
+            ErrorCode::TokenRevoked => "token_revoked",
+            ErrorCode::AccountDisabled => "account_disabled",
+            ErrorCode::AdminOnly => "admin_only",
+            ErrorCode::UserNotFound => "user_not_found",
+            ErrorCode::NotificationNotFound => "notification_not_found",
+            ErrorCode::WebhookNotFound => "webhook_not_found",
+            ErrorCode::WebhookDeliveryNotFound => "webhook_delivery_not_found",
+            ErrorCode::DuplicateRow => "duplicate_row",
+            ErrorCode::InvalidIdempotencyKey => "invalid_idempotency_key",
+            ErrorCode::IdempotencyKeyInProgress => "idempotency_key_in_progress",
+            ErrorCode::IdempotencyKeyReused => "idempotency_key_reused",
+            ErrorCode::DatabaseError => "database_error",
+            ErrorCode::InternalServerError => "internal_server_error",
+        }
+    }
+
+    /// The problem `type`: a URN rather than a URL, since there's no page
+    /// to point to.
+    pub fn type_uri(self) -> String {
+        format!("urn:pieno:users:error:{}", self.as_str())
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+// written by hand so the catalog in the docs comes from the code above
+impl<'s> ToSchema<'s> for ErrorCode {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let catalog: Vec<String> = ErrorCode::ALL
+            .iter()
+            .map(|code| {
+                format!(
+                    "- `{}` ({}): {}",
+                    code.as_str(),
+                    code.status().as_u16(),
+                    code.title(Locale::En)
+                )
+            })
+            .collect();
+
+        (
+            "ErrorCode",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .enum_values(Some(ErrorCode::ALL.iter().map(|c| c.as_str())))
+                .description(Some(format!(
+                    "Stable error codes, along with their HTTP status:\n{}",
+                    catalog.join("\n")
+                )))
+                .into(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn codes_are_unique() {
+        let codes: HashSet<&str> = ErrorCode::ALL.iter().map(|c| c.as_str()).collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+    }
+}
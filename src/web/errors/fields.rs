@@ -0,0 +1,114 @@
+use std::{borrow::Cow, collections::HashMap, fmt::Display};
+
+use serde_json::{Map, Value};
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::web::{dto::errors::field_error::FieldError, i18n, locale::Locale};
+
+/// Every failed check, with nested structs and lists flattened into paths
+/// like `quiet_hours.time_zone` or `items[0].name`, and messages written
+/// in `locale`.
+pub fn field_errors(errors: ValidationErrors, locale: Locale) -> Vec<FieldError> {
+    let mut flattened = vec![];
+    flatten("", errors, locale, &mut flattened);
+    flattened.sort_by(|a, b| a.field.cmp(&b.field));
+
+    flattened
+}
+
+fn flatten(
+    prefix: &str,
+    errors: ValidationErrors,
+    locale: Locale,
+    into: &mut Vec<FieldError>,
+) {
+    for (field, kind) in errors.into_errors() {
+        let path = match (prefix, field) {
+            // struct-level checks are about the struct itself
+            (_, "__all__") => prefix.to_string(),
+            ("", field) => field.to_string(),
+            (prefix, field) => format!("{prefix}.{field}"),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                into.extend(errors.into_iter().map(|e| field_error(&path, e, locale)))
+            }
+            ValidationErrorsKind::Struct(errors) => {
+                flatten(&path, *errors, locale, into)
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    flatten(&format!("{path}[{index}]"), *errors, locale, into);
+                }
+            }
+        }
+    }
+}
+
+fn field_error(path: &str, error: ValidationError, locale: Locale) -> FieldError {
+    let message = match &error.message {
+        Some(message) => message.to_string(),
+        None => default_message(&error.code, &error.params, locale),
+    };
+    let params: Map<String, Value> = error
+        .params
+        .into_iter()
+        // the rejected value itself, which could well be a password
+        .filter(|(name, _)| name != "value")
+        .map(|(name, value)| (name.into_owned(), value))
+        .collect();
+
+    FieldError {
+        field: path.to_string(),
+        code: error.code.into_owned(),
+        message,
+        params,
+    }
+}
+
+fn default_message(
+    code: &str,
+    params: &HashMap<Cow<str>, Value>,
+    locale: Locale,
+) -> String {
+    let param = |name: &str| params.get(name).map(Value::to_string);
+    let unit = match params.get("value") {
+        Some(Value::String(_)) => i18n::t(locale, "validation.unit.characters", &[]),
+        Some(Value::Array(_)) => i18n::t(locale, "validation.unit.items", &[]),
+        _ => String::new(),
+    };
+    let t = |key: &str, args: &[(&str, &dyn Display)]| i18n::t(locale, key, args);
+
+    match code {
+        "length" => match (param("min"), param("max"), param("equal")) {
+            (_, _, Some(equal)) => t(
+                "validation.length.equal",
+                &[("equal", &equal), ("unit", &unit)],
+            ),
+            (Some(min), Some(max), _) => t(
+                "validation.length.between",
+                &[("min", &min), ("max", &max), ("unit", &unit)],
+            ),
+            (Some(min), None, _) => {
+                t("validation.length.min", &[("min", &min), ("unit", &unit)])
+            }
+            (None, Some(max), _) => {
+                t("validation.length.max", &[("max", &max), ("unit", &unit)])
+            }
+            _ => t("validation.length.other", &[]),
+        },
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => {
+                t("validation.range.between", &[("min", &min), ("max", &max)])
+            }
+            (Some(min), None) => t("validation.range.min", &[("min", &min)]),
+            (None, Some(max)) => t("validation.range.max", &[("max", &max)]),
+            _ => t("validation.range.other", &[]),
+        },
+        // custom checks only need an entry in the catalogs
+        other => match i18n::lookup(locale, &format!("validation.{other}")) {
+            Some(message) => message.to_string(),
+            None => other.replace('_', " "),
+        },
+    }
+}
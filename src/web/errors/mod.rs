@@ -1,71 +1,198 @@
-use std::collections::HashMap;
-
 use axum::{
-    extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::header::{CONTENT_LANGUAGE, CONTENT_TYPE},
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use serde_json::{json, Map};
 use tokio::task::JoinError;
-use validator::{ValidationErrors, ValidationErrorsKind};
+use tracing::error;
+use validator::ValidationErrors;
+
+use crate::config::ErrorFormat;
+
+use self::code::ErrorCode;
+use super::{
+    dto::errors::{
+        field_error::FieldError,
+        problem::{Location, Problem},
+    },
+    i18n,
+    middlewares::request_id::RequestContext,
+    repositories::RepositoryError,
+};
+
+pub mod code;
+mod fields;
 
 pub enum HttpError {
     DbError(sqlx::Error),
-    ParsingError(String, StatusCode),
-    InvalidFieldsError(HashMap<&'static str, ValidationErrorsKind>),
-    Simple(StatusCode, String),
+    ParsingError(ParsingError),
+    InvalidFieldsError(Vec<FieldError>),
+    Simple(ErrorCode),
+}
+
+/// A request that couldn't be deserialized.
+pub struct ParsingError {
+    /// `invalid_body` or `invalid_query`
+    pub code: ErrorCode,
+    pub message: String,
+    /// the field it stumbled upon, if it got that far
+    pub field: Option<String>,
+    pub location: Option<Location>,
+}
+
+impl ParsingError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ParsingError {
+        ParsingError {
+            code,
+            message: message.into(),
+            field: None,
+            location: None,
+        }
+    }
+
+    pub fn json(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let field = field_path(err.path());
+        let err = err.into_inner();
+        // serde_json appends the location to every message
+        let message = err.to_string();
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_string(),
+            None => message,
+        };
+
+        ParsingError {
+            code: ErrorCode::InvalidBody,
+            message,
+            // syntax errors don't have much to do with the field they're in
+            field: field.filter(|_| err.is_data()),
+            location: (err.line() > 0).then_some(Location {
+                line: err.line(),
+                column: err.column(),
+            }),
+        }
+    }
+
+    pub fn urlencoded(
+        code: ErrorCode,
+        err: serde_path_to_error::Error<serde_urlencoded::de::Error>,
+    ) -> Self {
+        ParsingError {
+            code,
+            field: field_path(err.path()),
+            message: err.into_inner().to_string(),
+            location: None,
+        }
+    }
+}
+
+fn field_path(path: &serde_path_to_error::Path) -> Option<String> {
+    Some(path.to_string()).filter(|path| path != ".")
 }
 
 impl IntoResponse for HttpError {
     fn into_response(self) -> Response {
-        let tuple_response = match self {
-            HttpError::ParsingError(text, _) => (
-                StatusCode::BAD_REQUEST,
-                Json(json!({"success": false, "error": text})),
-            ),
+        let context = RequestContext::current();
+        let locale = context.as_ref().map(|c| c.locale()).unwrap_or_default();
+        let mut location = None;
+        let (code, detail, fields) = match self {
+            HttpError::ParsingError(err) => {
+                location = err.location;
+                let fields = err.field.map(|field| {
+                    vec![FieldError {
+                        field,
+                        code: "invalid_value".to_string(),
+                        message: err.message.clone(),
+                        params: Map::new(),
+                    }]
+                });
+                (err.code, Some(err.message), fields)
+            }
             HttpError::DbError(err) => {
-                // log db error
-                let mut status = StatusCode::INTERNAL_SERVER_ERROR;
-                let err_string = match err {
-                    sqlx_core::Error::Database(db_err) => {
-                        if let Some(code) = db_err.code() {
-                            if code == "23505" {
-                                status = StatusCode::CONFLICT;
-                                "duplicate_row".to_owned()
-                            } else {
-                                format!("err_code_{}", code)
-                            }
-                        } else {
-                            "generic_db_error".to_owned()
-                        }
+                let code = match &err {
+                    sqlx_core::Error::Database(db_err)
+                        if db_err.code().as_deref() == Some("23505") =>
+                    {
+                        ErrorCode::DuplicateRow
                     }
-                    e => {
-                        dbg!(e);
-                        "internal_server_error".to_owned()
+                    _ => {
+                        error!("database error: {err:?}");
+                        ErrorCode::DatabaseError
                     }
                 };
-                (
-                    status,
-                    Json(json!({"success": false, "error": err_string })),
-                )
+                (code, None, None)
             }
-            HttpError::InvalidFieldsError(err) => {
-                let invalid_fields: Vec<&str> =
-                    err.into_keys().map(|i| i).collect();
-                (
-                    StatusCode::BAD_REQUEST,
-                    Json(
-                        json!({"success": false, "error": "invalid_fields", "fields": invalid_fields}),
-                    ),
-                )
+            HttpError::InvalidFieldsError(fields) => {
+                let names: Vec<&str> =
+                    fields.iter().map(|f| f.field.as_str()).collect();
+                let detail = i18n::t(
+                    locale,
+                    "error.invalid_fields.detail",
+                    &[("fields", &names.join(", "))],
+                );
+                (ErrorCode::InvalidFields, Some(detail), Some(fields))
             }
-            HttpError::Simple(code, msg) => {
-                (code, Json(json!({ "success": false, "error": msg })))
+            HttpError::Simple(code) => (code, None, None),
+        };
+
+        let format = context
+            .as_ref()
+            .map_or(ErrorFormat::Problem, |c| c.error_format);
+        // so whoever reports the error can be matched with our logs
+        let request_id = context.as_ref().map(|c| c.request_id.clone());
+        let status = code.status();
+
+        if format == ErrorFormat::Legacy {
+            let mut body = json!({"success": false, "error": code.as_str()});
+            if let (ErrorCode::InvalidFields, Some(fields)) = (code, fields) {
+                // only ever had the top-level names
+                let mut names: Vec<String> = fields
+                    .into_iter()
+                    .map(|f| f.field.split(['.', '[']).next().unwrap().to_string())
+                    .collect();
+                names.sort();
+                names.dedup();
+                body["fields"] = json!(names);
+            }
+            if let Some(request_id) = request_id {
+                body["request_id"] = json!(request_id);
             }
+            return (status, Json(body)).into_response();
+        }
+
+        let problem = Problem {
+            type_uri: code.type_uri(),
+            title: code.title(locale),
+            status: status.as_u16(),
+            detail,
+            instance: context.map(|c| c.path),
+            code,
+            request_id,
+            fields,
+            location,
         };
+        (
+            status,
+            [
+                (CONTENT_TYPE, "application/problem+json"),
+                (CONTENT_LANGUAGE, locale.code()),
+            ],
+            Json(problem),
+        )
+            .into_response()
+    }
+}
 
-        tuple_response.into_response()
+impl From<RepositoryError> for HttpError {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::Conflict => Self::Simple(ErrorCode::DuplicateRow),
+            RepositoryError::Database(err) => Self::DbError(err),
+            RepositoryError::Encode(err) => {
+                error!("couldn't encode: {err}");
+                Self::Simple(ErrorCode::InternalServerError)
+            }
+        }
     }
 }
 
@@ -76,33 +203,33 @@ impl From<sqlx::Error> for HttpError {
     }
 }
 
-impl From<JsonRejection> for HttpError {
-    // error while parsing invalid json
-    fn from(err: JsonRejection) -> Self {
-        Self::ParsingError("invalid_body".to_owned(), err.status())
+impl From<ParsingError> for HttpError {
+    fn from(err: ParsingError) -> Self {
+        Self::ParsingError(err)
     }
 }
 
 impl From<ValidationErrors> for HttpError {
     // error when validating structs
     fn from(err: ValidationErrors) -> Self {
-        Self::InvalidFieldsError(err.into_errors())
+        Self::InvalidFieldsError(fields::field_errors(err, i18n::current()))
     }
 }
 impl From<JoinError> for HttpError {
     // this is tokio's blocking task error
     fn from(_: JoinError) -> Self {
-        Self::Simple(StatusCode::INTERNAL_SERVER_ERROR, "async_error".to_string())
+        Self::Simple(ErrorCode::InternalServerError)
     }
 }
 impl From<jsonwebtoken::errors::Error> for HttpError {
-    fn from(e: jsonwebtoken::errors::Error) -> Self {
-        Self::Simple(StatusCode::INTERNAL_SERVER_ERROR, "bad_jwt".to_string()) // ErrorKind implements Display!
+    fn from(_: jsonwebtoken::errors::Error) -> Self {
+        // decoding maps its errors on its own, so this is about signing
+        Self::Simple(ErrorCode::InternalServerError)
     }
 }
 
 impl From<argon2::password_hash::Error> for HttpError {
-    fn from(e: argon2::password_hash::Error) -> Self {
-        Self::Simple(StatusCode::INTERNAL_SERVER_ERROR, "internal_server_error".to_string()) // ErrorKind implements Display!
+    fn from(_: argon2::password_hash::Error) -> Self {
+        Self::Simple(ErrorCode::InternalServerError)
     }
 }
\ No newline at end of file
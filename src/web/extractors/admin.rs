@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+
+use crate::{
+    config::Config,
+    web::{
+        dto::{admin_claims::AdminClaims, Claim},
+        errors::{code::ErrorCode, HttpError},
+        extractors::token::{bearer_token, Token},
+    },
+};
+
+/// The name of a service listed in `ADMIN_SERVICES`, calling with a token
+/// signed with `ADMIN_JWT_SECRET`: the only ones allowed on the `/admin`
+/// endpoints.
+pub struct Admin(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let str_token = bearer_token(parts)?
+            .ok_or(HttpError::Simple(ErrorCode::NoAuthHeader))?;
+        let Token(claims) =
+            Token::<Claim<AdminClaims>>::from(&str_token, &config.jwt).await?;
+        let service = &claims.data().service;
+        if !config.server.admin_services.contains(service) {
+            return Err(HttpError::Simple(ErrorCode::AdminOnly));
+        }
+
+        Ok(Admin(service.clone()))
+    }
+}
@@ -1,2 +1,4 @@
+pub mod admin;
 pub mod validate_body;
-pub mod token;
\ No newline at end of file
+pub mod validate_query;
+pub mod token;
@@ -1,45 +1,59 @@
-use std::env;
+use std::sync::Arc;
 
 use async_trait::async_trait;
-use axum::{extract::FromRequestParts, http::{header::AUTHORIZATION, request::Parts, StatusCode}};
+use axum::{extract::{FromRef, FromRequestParts, Query}, http::{header::AUTHORIZATION, request::Parts}};
 use jsonwebtoken::{encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::info_span;
 
-use crate::web::{dto::{user_claims::UserClaims, Claim}, errors::HttpError};
+use crate::{config::{Config, JwtConfig}, web::{dto::{service_claims::ServiceClaims, user_claims::UserClaims, Audience, Claim}, errors::{code::ErrorCode, HttpError}, locale::Locale, middlewares::request_id::{set_user_id, set_user_locale}, repositories::UserRepository}};
 
 pub struct Token<T: Send + Serialize + 'static>(pub T);
 
-impl<T> Token<T> 
-    where T: DeserializeOwned + Send + Serialize + 'static // essentially, T must not have lifetimes inside, because it needs
-                                                           // to be moved!
+impl<C> Token<Claim<C>>
+    where C: Audience + DeserializeOwned + Send + Serialize + 'static // essentially, C must not have lifetimes inside,
+                                                                      // because it needs to be moved!
 {
-    pub async fn generate(item: T) -> Result<String, HttpError> {
-        let key = env::var("JWT_SECRET").unwrap();  
-        
+    pub async fn generate(item: Claim<C>, jwt: &JwtConfig) -> Result<String, HttpError> {
+        let key = C::secret(jwt).to_string();
+        let span = info_span!("jwt.encode");
+
         tokio::task::spawn_blocking(move || {
+            let _span = span.enter();
             Ok(
                 encode(
                     &Header::default(),
                     &item, 
-                    &EncodingKey::from_secret(&key.as_bytes())
+                    &EncodingKey::from_secret(key.as_bytes())
                 )?
             )
         }).await?
 
     }
-    pub async fn from(token: &str) -> Result<Token<T>, HttpError> {
-        let key = env::var("JWT_SECRET").unwrap();
-        let validation = Validation::new(Algorithm::HS256);
+    pub async fn from(token: &str, jwt: &JwtConfig) -> Result<Token<Claim<C>>, HttpError> {
+        let key = C::secret(jwt).to_string();
+        let mut validation = Validation::new(Algorithm::HS256);
+        // the secrets differ already, this keeps a token from being taken
+        // for another kind even if they ever didn't
+        validation.set_audience(&[C::AUDIENCE]);
+        if C::REQUIRES_AUDIENCE {
+            validation.set_required_spec_claims(&["exp", "aud"]);
+        }
         let token = token.to_string(); // we need the owned version because spawn_blocking moves 
+        let span = info_span!("jwt.decode");
 
         tokio::task::spawn_blocking(move || {
+            let _span = span.enter();
             Ok(
                 Token(
-                    jsonwebtoken::decode::<T>(
+                    jsonwebtoken::decode::<Claim<C>>(
                             &token, 
-                            &DecodingKey::from_secret(&key.as_bytes()),
+                            &DecodingKey::from_secret(key.as_bytes()),
                             &validation
-                    )?.claims
+                    )
+                    // expired, tampered with, meant for someone else or just garbage: the caller's fault
+                    .map_err(|_| HttpError::Simple(ErrorCode::InvalidToken))?
+                    .claims
                 )
             )
         }).await?
@@ -52,26 +66,114 @@ impl<T> Token<T>
 impl<S> FromRequestParts<S> for Token<Claim<UserClaims>>
 where
     S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+    Arc<dyn UserRepository>: FromRef<S>,
 {
     type Rejection = HttpError;
 
-    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        if let Some(auth_header) = parts.headers.get(AUTHORIZATION) {
-            if let Ok(str_header) = auth_header
-                .to_str() {
-                    let pieces: Vec<&str> = str_header.split("Bearer ").collect();
-                    if pieces.len() < 2 {
-                        return Err(HttpError::Simple(StatusCode::BAD_REQUEST, "no_bearer_specified".to_string()))
-                    }
-                    let str_token = pieces[1];
-                    Ok(
-                        Token::from(str_token).await?
-                    )
-                } else {
-                    Err(HttpError::Simple(StatusCode::BAD_REQUEST, "invalid_auth_header".to_string()))
-                }
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(str_token) = bearer_token(parts)? else {
+            return Err(HttpError::Simple(ErrorCode::NoAuthHeader));
+        };
+        user_token(&str_token, state).await
+    }
+}
+
+/// A user token that can also come in the `access_token` query parameter,
+/// because browsers' EventSource can't set headers (RFC 6750, section 2.3).
+/// Only for streams: anywhere else the token would end up in access logs
+/// and browser history for nothing.
+pub struct StreamToken(pub Claim<UserClaims>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for StreamToken
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+    Arc<dyn UserRepository>: FromRef<S>,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(str_token) = bearer_token(parts)?.or_else(|| access_token_param(parts)) else {
+            return Err(HttpError::Simple(ErrorCode::NoAuthHeader));
+        };
+        let Token(claims) = user_token(&str_token, state).await?;
+        Ok(StreamToken(claims))
+    }
+}
+
+async fn user_token<S>(str_token: &str, state: &S) -> Result<Token<Claim<UserClaims>>, HttpError>
+where
+    Arc<Config>: FromRef<S>,
+    Arc<dyn UserRepository>: FromRef<S>,
+{
+    let config = Arc::<Config>::from_ref(state);
+    let token: Token<Claim<UserClaims>> = Token::from(str_token, &config.jwt).await?;
+    set_user_id(&token.0.data().user_id);
+
+    // a valid signature isn't enough: the user could have been disabled, or
+    // logged out everywhere, since. users that are gone are up to the handlers
+    let users = Arc::<dyn UserRepository>::from_ref(state);
+    if let Some(user) = users.by_id(&token.0.data().user_id).await? {
+        set_user_locale(Locale::from_code(&user.locale));
+        if user.disabled_at.is_some() {
+            return Err(HttpError::Simple(ErrorCode::AccountDisabled));
+        }
+        if !user.accepts_token_of_generation(token.0.data().generation) {
+            return Err(HttpError::Simple(ErrorCode::TokenRevoked));
+        }
+    }
+
+    Ok(token)
+}
+
+// other services only ever talk to us with the header
+#[async_trait]
+impl<S> FromRequestParts<S> for Token<Claim<ServiceClaims>>
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        if let Some(str_token) = bearer_token(parts)? {
+            Ok(
+                Token::from(&str_token, &config.jwt).await?
+            )
         } else {
-            Err(HttpError::Simple(StatusCode::BAD_REQUEST, "no_auth_header".to_string()))
+            Err(HttpError::Simple(ErrorCode::NoAuthHeader))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Reads the token out of the `Authorization: Bearer ...` header, if there's one.
+pub(crate) fn bearer_token(parts: &Parts) -> Result<Option<String>, HttpError> {
+    if let Some(auth_header) = parts.headers.get(AUTHORIZATION) {
+        if let Ok(str_header) = auth_header
+            .to_str() {
+                let pieces: Vec<&str> = str_header.split("Bearer ").collect();
+                if pieces.len() < 2 {
+                    return Err(HttpError::Simple(ErrorCode::NoBearerSpecified))
+                }
+                Ok(Some(pieces[1].to_string()))
+            } else {
+                Err(HttpError::Simple(ErrorCode::InvalidAuthHeader))
+            }
+    } else {
+        Ok(None)
+    }
+}
+
+#[derive(Deserialize)]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+fn access_token_param(parts: &Parts) -> Option<String> {
+    Query::<AccessTokenQuery>::try_from_uri(&parts.uri)
+        .ok()
+        .and_then(|Query(q)| q.access_token)
+}
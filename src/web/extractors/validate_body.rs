@@ -1,22 +1,62 @@
 use async_trait::async_trait;
 use axum::{
-    extract::{rejection::JsonRejection, FromRequest},
-    http::{Request, StatusCode},
-    Json, body::Body,
+    body::{Body, Bytes},
+    extract::FromRequest,
+    http::{header::CONTENT_TYPE, HeaderMap, Request},
 };
-use serde::Serialize;
+use serde::de::DeserializeOwned;
 use validator::Validate;
 
-use crate::web::errors::HttpError;
+use crate::web::{
+    errors::{code::ErrorCode, HttpError, ParsingError},
+    i18n,
+};
+
+use super::validate_query::from_urlencoded;
 
+/// A JSON body, deserialized and validated: failures point at the field
+/// (and for malformed JSON, the line and column) that's wrong.
 pub struct ValidatedJson<T>(pub T);
 
 #[async_trait]
 impl<S, T> FromRequest<S> for ValidatedJson<T>
 where
-    axum::Json<T>: FromRequest<S, Rejection = JsonRejection>,
     S: Send + Sync,
-    T: Validate,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = HttpError;
+
+    async fn from_request(
+        req: Request<Body>,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        if !has_content_type(req.headers(), is_json) {
+            return Err(ParsingError::new(
+                ErrorCode::InvalidBody,
+                i18n::t(i18n::current(), "error.invalid_body.expected_json", &[]),
+            )
+            .into());
+        }
+        let body = body(req, state).await?;
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(&body);
+        let value: T = serde_path_to_error::deserialize(deserializer)
+            .map_err(ParsingError::json)?;
+        value.validate()?;
+
+        Ok(Self(value))
+    }
+}
+
+/// The same as [`ValidatedJson`], for `application/x-www-form-urlencoded`
+/// bodies.
+pub struct ValidatedForm<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for ValidatedForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
 {
     type Rejection = HttpError;
 
@@ -24,20 +64,43 @@ where
         req: Request<Body>,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let (parts, body) = req.into_parts();
-        let rebuilt_request = Request::from_parts(parts, body); // we need to rebuild the request since the Json extractor
-                                                                // already consumed the body!
-        let Json(j) = axum::Json::<T>::from_request(rebuilt_request, state)
-            .await
-            .map_err(|_| {
-                HttpError::ParsingError(
-                    "invalid_body".to_owned(),
-                    StatusCode::BAD_REQUEST,
-                )
-            })?;
-        
-        j.validate()?;
-
-        Ok(Self(j))
+        if !has_content_type(req.headers(), |mime| {
+            mime == "application/x-www-form-urlencoded"
+        }) {
+            return Err(ParsingError::new(
+                ErrorCode::InvalidBody,
+                i18n::t(i18n::current(), "error.invalid_body.expected_form", &[]),
+            )
+            .into());
+        }
+        let body = body(req, state).await?;
+
+        let value: T = from_urlencoded(&body, ErrorCode::InvalidBody)?;
+        value.validate()?;
+
+        Ok(Self(value))
     }
-}
\ No newline at end of file
+}
+
+async fn body<S: Send + Sync>(
+    req: Request<Body>,
+    state: &S,
+) -> Result<Bytes, HttpError> {
+    Bytes::from_request(req, state).await.map_err(|err| {
+        ParsingError::new(ErrorCode::InvalidBody, err.body_text()).into()
+    })
+}
+
+fn has_content_type(headers: &HeaderMap, accepts: fn(&str) -> bool) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| accepts(&mime.trim().to_ascii_lowercase()))
+}
+
+// `application/json`, or any `application/*+json`
+fn is_json(mime: &str) -> bool {
+    mime.strip_prefix("application/")
+        .is_some_and(|subtype| subtype == "json" || subtype.ends_with("+json"))
+}
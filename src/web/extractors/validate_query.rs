@@ -0,0 +1,44 @@
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::web::errors::{code::ErrorCode, HttpError, ParsingError};
+
+/// The query string, deserialized and validated like [`ValidatedJson`]
+/// does with bodies.
+///
+/// [`ValidatedJson`]: super::validate_body::ValidatedJson
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait]
+impl<S, T> FromRequestParts<S> for ValidatedQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = HttpError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let value: T = from_urlencoded(query.as_bytes(), ErrorCode::InvalidQuery)?;
+        value.validate()?;
+
+        Ok(Self(value))
+    }
+}
+
+/// Deserializes query strings and forms, failing with `code`.
+pub(super) fn from_urlencoded<T: DeserializeOwned>(
+    input: &[u8],
+    code: ErrorCode,
+) -> Result<T, ParsingError> {
+    let deserializer = serde_urlencoded::Deserializer::new(
+        form_urlencoded::parse(input),
+    );
+    serde_path_to_error::deserialize(deserializer)
+        .map_err(|err| ParsingError::urlencoded(code, err))
+}
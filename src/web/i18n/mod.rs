@@ -0,0 +1,135 @@
+use std::{collections::HashMap, fmt::Display};
+
+use once_cell::sync::Lazy;
+
+use super::{locale::Locale, middlewares::request_id::RequestContext};
+
+/// Flattened keys, like `error.invalid_token.title`, to their text.
+type Catalog = HashMap<String, String>;
+
+static CATALOGS: Lazy<HashMap<Locale, Catalog>> = Lazy::new(|| {
+    Locale::ALL
+        .iter()
+        .map(|&locale| (locale, parse(source(locale))))
+        .collect()
+});
+
+fn source(locale: Locale) -> &'static str {
+    match locale {
+        Locale::En => include_str!("en.toml"),
+        Locale::It => include_str!("it.toml"),
+    }
+}
+
+fn parse(source: &str) -> Catalog {
+    // both catalogs are embedded, and tests parse them all
+    let table: toml::Table = source.parse().expect("invalid message catalog");
+    let mut catalog = Catalog::new();
+    flatten("", table, &mut catalog);
+
+    catalog
+}
+
+fn flatten(prefix: &str, table: toml::Table, into: &mut Catalog) {
+    for (key, value) in table {
+        let key = match prefix {
+            "" => key,
+            prefix => format!("{prefix}.{key}"),
+        };
+        match value {
+            toml::Value::String(text) => {
+                into.insert(key, text);
+            }
+            toml::Value::Table(table) => flatten(&key, table, into),
+            other => panic!("{key} in a message catalog is a {}", other.type_str()),
+        }
+    }
+}
+
+/// The text of `key` in `locale`, falling back to English when the catalog
+/// misses it.
+pub fn lookup(locale: Locale, key: &str) -> Option<&'static str> {
+    [locale, Locale::En]
+        .iter()
+        .find_map(|locale| CATALOGS[locale].get(key))
+        .map(String::as_str)
+}
+
+/// Like [`lookup`], with the `{name}` placeholders filled in from `args`.
+/// Keys that aren't in any catalog come back as they are, so a typo shows
+/// up instead of an empty string.
+pub fn t(locale: Locale, key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let Some(text) = lookup(locale, key) else {
+        return key.to_string();
+    };
+
+    args.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{name}}}"), &value.to_string())
+    })
+}
+
+/// The locale the request being handled should be answered in.
+pub fn current() -> Locale {
+    RequestContext::current()
+        .map(|context| context.locale())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::web::errors::code::ErrorCode;
+
+    fn placeholders(text: &str) -> BTreeSet<&str> {
+        text.split('{')
+            .skip(1)
+            .filter_map(|rest| rest.split_once('}'))
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn every_key_is_in_every_language() {
+        let english = &CATALOGS[&Locale::En];
+        for locale in Locale::ALL {
+            let catalog = &CATALOGS[locale];
+            for (key, text) in english {
+                let Some(translated) = catalog.get(key) else {
+                    panic!("{key} is missing in {}", locale.code());
+                };
+                assert_eq!(
+                    placeholders(text),
+                    placeholders(translated),
+                    "{key} has different placeholders in {}",
+                    locale.code()
+                );
+            }
+            for key in catalog.keys() {
+                assert!(
+                    english.contains_key(key),
+                    "{key} is only in {}",
+                    locale.code()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_error_has_a_title() {
+        for code in ErrorCode::ALL {
+            let key = format!("error.{}.title", code.as_str());
+            assert!(CATALOGS[&Locale::En].contains_key(&key), "{key} is missing");
+        }
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        assert_eq!(
+            t(Locale::It, "push.digest.title", &[("count", &3)]),
+            "3 nuove notifiche"
+        );
+        assert_eq!(t(Locale::It, "no.such.key", &[]), "no.such.key");
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::Utc;
+use tracing::info;
+use sqlx::{Pool, Postgres};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    log_util::LoggableOutcome,
+    web::{
+        i18n,
+        locale::Locale,
+        models::{notification_settings, notifications, users::User},
+        push::{PushClient, PushMessage},
+    },
+};
+
+/// Sends the pushes held back during quiet hours, one digest per user, as
+/// soon as their quiet hours are over.
+pub async fn run(
+    pool: Pool<Postgres>,
+    client: PushClient,
+    shutdown: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+        let Ok(users) = notification_settings::users_with_held_pushes(&pool)
+            .await
+            .log_err_to_error("couldn't load held pushes")
+        else {
+            continue;
+        };
+
+        let now = Utc::now();
+        for (user_id, settings, time_zone) in users {
+            if settings.in_quiet_hours(now, &time_zone) {
+                continue;
+            }
+            let _ = send_digest(&pool, &client, &user_id)
+                .await
+                .log_err_to_warn("push digest failed");
+        }
+    }
+}
+
+/// How long a replica has to send a digest before another one may. Sends
+/// give up well before it runs out, so a digest is only sent twice if its
+/// replica dies between sending it and deleting its pushes.
+const LEASE: Duration = Duration::from_secs(5 * 60);
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+async fn send_digest(
+    pool: &Pool<Postgres>,
+    client: &PushClient,
+    user_id: &str,
+) -> Result<(), anyhow::Error> {
+    // the job runs on every replica: the lease makes sure only one of them
+    // sends a digest, and no transaction is open while it does
+    let held =
+        notification_settings::lease_held_pushes(pool, user_id, LEASE).await?;
+    if held.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i64> = held.iter().map(|(id, _, _)| *id).collect();
+
+    match deliver(pool, client, user_id, &held).await {
+        Ok(()) => {
+            notification_settings::delete_held_pushes(pool, &ids).await?;
+            Ok(())
+        }
+        Err(e) => {
+            notification_settings::release_held_pushes(pool, &ids).await?;
+            Err(e)
+        }
+    }
+}
+
+async fn deliver(
+    pool: &Pool<Postgres>,
+    client: &PushClient,
+    user_id: &str,
+    held: &[(i64, String, String)],
+) -> Result<(), anyhow::Error> {
+    let tokens: Vec<String> = notifications::fcm_tokens(pool, &[user_id.to_string()])
+        .await?
+        .into_iter()
+        .map(|(_, token)| token)
+        .collect();
+    let locale = User::from_id(pool, user_id)
+        .await?
+        .map(|user| Locale::from_code(&user.locale))
+        .unwrap_or_default();
+
+    let messages: Vec<(String, String)> = held
+        .iter()
+        .map(|(_, title, body)| (title.clone(), body.clone()))
+        .collect();
+    let Some((title, body)) = digest(&messages, locale) else {
+        return Ok(());
+    };
+    if tokens.is_empty() {
+        return Ok(());
+    }
+    let message = PushMessage {
+        tokens,
+        title,
+        body,
+        data: HashMap::new(),
+    };
+    tokio::time::timeout(SEND_TIMEOUT, client.send(&message)).await??;
+    info!("sent a digest of {} pushes to {user_id}", held.len());
+
+    Ok(())
+}
+
+fn digest(
+    held: &[(String, String)],
+    locale: Locale,
+) -> Option<(String, String)> {
+    match held {
+        [] => None,
+        [single] => Some(single.clone()),
+        many => Some((
+            i18n::t(locale, "push.digest.title", &[("count", &many.len())]),
+            many.iter()
+                .map(|(title, _)| title.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )),
+    }
+}
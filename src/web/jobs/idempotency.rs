@@ -0,0 +1,33 @@
+use sqlx::{Pool, Postgres};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    config::IdempotencyConfig, log_util::LoggableOutcome,
+    web::models::idempotency_keys,
+};
+
+/// Deletes the idempotency keys that can't be replayed anymore. They'd be
+/// reused anyway once expired: this only keeps the table small.
+pub async fn run(
+    pool: Pool<Postgres>,
+    config: IdempotencyConfig,
+    shutdown: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(config.cleanup_interval);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+        if let Ok(deleted) = idempotency_keys::purge(&pool, config.ttl)
+            .await
+            .log_err_to_error("idempotency keys cleanup failed")
+        {
+            if deleted > 0 {
+                info!("deleted {deleted} expired idempotency keys");
+            }
+        }
+    }
+}
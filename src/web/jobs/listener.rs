@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use tracing::{error, info};
+use serde::Deserialize;
+use sqlx::{postgres::PgListener, Pool, Postgres};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::log_util::LoggableOutcome;
+
+/// Channel the `notifications_inserted` trigger publishes to.
+const CHANNEL: &str = "notifications_inserted";
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct NotificationEvent {
+    pub id: String,
+    pub to_user: String,
+}
+
+/// Fans out every notification inserted in the database to the streams
+/// opened on this replica.
+#[derive(Clone)]
+pub struct NotificationHub {
+    sender: broadcast::Sender<NotificationEvent>,
+}
+
+impl NotificationHub {
+    pub fn new(capacity: usize) -> NotificationHub {
+        let (sender, _) = broadcast::channel(capacity);
+        NotificationHub { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NotificationEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Hands `event` to every open stream.
+    pub fn publish(&self, event: NotificationEvent) {
+        // sending only fails when nobody is listening, which is fine
+        let _ = self.sender.send(event);
+    }
+}
+
+pub async fn run(
+    pool: Pool<Postgres>,
+    hub: NotificationHub,
+    shutdown: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            Err(e) = listen(&pool, &hub) => {
+                error!("notification listener stopped: {e:?}, restarting...");
+            }
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+        }
+    }
+    info!("notification listener stopped");
+}
+
+async fn listen(
+    pool: &Pool<Postgres>,
+    hub: &NotificationHub,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANNEL).await?;
+    info!("listening for new notifications on `{CHANNEL}`");
+
+    loop {
+        // recv reconnects by itself; whatever gets lost in the meantime is
+        // recovered by the clients resuming with `Last-Event-ID`
+        let notification = listener.recv().await?;
+        if let Ok(event) = serde_json::from_str(notification.payload())
+            .log_err_to_warn("malformed notification event")
+        {
+            hub.publish(event);
+        }
+    }
+}
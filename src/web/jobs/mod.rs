@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::web::{sinks, AppState};
+
+pub mod digest;
+pub mod idempotency;
+pub mod listener;
+pub mod outbox;
+pub mod retention;
+pub mod webhooks;
+
+/// Spawns the background jobs running next to the web server. They all stop
+/// at the first chance they get once `state.shutdown` is cancelled.
+pub fn spawn_jobs(state: &AppState) -> Result<(), anyhow::Error> {
+    state.tasks.spawn(listener::run(
+        state.pool.clone(),
+        state.hub.clone(),
+        state.shutdown.clone(),
+    ));
+    if let Some(client) = &state.push {
+        state.tasks.spawn(digest::run(
+            state.pool.clone(),
+            client.clone(),
+            state.shutdown.clone(),
+        ));
+    }
+
+    state.tasks.spawn(idempotency::run(
+        state.pool.clone(),
+        state.config.idempotency.clone(),
+        state.shutdown.clone(),
+    ));
+
+    state.tasks.spawn(outbox::run(
+        state.outbox.clone(),
+        state.config.outbox.clone(),
+        sinks::from_config(&state.config.outbox.sink),
+        state.shutdown.clone(),
+    ));
+
+    state.tasks.spawn(webhooks::run(
+        state.webhooks.clone(),
+        state.config.webhooks.clone(),
+        state.shutdown.clone(),
+    ));
+
+    if let Some(policy) = &state.config.retention {
+        state.tasks.spawn(retention::run(
+            state.notifications.clone(),
+            policy.clone(),
+            state.shutdown.clone(),
+        ));
+    } else {
+        info!("notification retention is disabled");
+    }
+
+    Ok(())
+}
+
+/// How long to wait after `attempts` failures: `first`, then twice as long
+/// every time, up to `max`.
+fn backoff(attempts: i32, first: Duration, max: Duration) -> Duration {
+    first.saturating_mul(1 << attempts.clamp(0, 20)).min(max)
+}
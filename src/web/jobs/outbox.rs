@@ -0,0 +1,108 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    config::OutboxConfig,
+    log_util::LoggableOutcome,
+    web::{
+        jobs::backoff,
+        metrics::OUTBOX_EVENTS,
+        repositories::{OutboxRepository, RepositoryResult},
+        sinks::Sink,
+    },
+};
+
+/// how often published events are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Publishes the user events written to the outbox. An event is only marked
+/// as published once the sink took it, so a crash in between means it's
+/// published again: delivery is at least once. Those of a user go out in
+/// the order they were written, one after the other.
+pub async fn run(
+    outbox: Arc<dyn OutboxRepository>,
+    config: OutboxConfig,
+    sink: Arc<dyn Sink>,
+    shutdown: CancellationToken,
+) {
+    info!("publishing user events to {}", config.sink);
+    let mut interval = tokio::time::interval(config.poll_interval);
+    let mut purge = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = purge.tick() => {
+                if let Ok(deleted) = outbox
+                    .purge_published(config.keep_published)
+                    .await
+                    .log_err_to_error("outbox cleanup failed")
+                {
+                    if deleted > 0 {
+                        info!("deleted {deleted} published user events");
+                    }
+                }
+                continue;
+            }
+            _ = interval.tick() => {}
+        }
+        // until there's nothing due: each round only gets the next event
+        // of every user
+        while !shutdown.is_cancelled() {
+            match relay(&*outbox, &config, &*sink)
+                .await
+                .log_err_to_error("outbox relay failed")
+            {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+/// Publishes one batch of events, returning how many were tried. They're
+/// leased for `config.lease` rather than locked, so no transaction is held
+/// open while the sink is slow: if the lease runs out first, another relay
+/// publishes them too.
+pub async fn relay(
+    outbox: &dyn OutboxRepository,
+    config: &OutboxConfig,
+    sink: &dyn Sink,
+) -> RepositoryResult<usize> {
+    let events = outbox
+        .claim(config.batch_size as i64, config.lease)
+        .await?;
+    for event in &events {
+        match sink.publish(event).await {
+            Ok(()) => {
+                outbox.mark_published(event.id).await?;
+                OUTBOX_EVENTS
+                    .with_label_values(&[&event.kind, "published"])
+                    .inc();
+            }
+            Err(e) => {
+                let retry_in = backoff(
+                    event.attempts,
+                    Duration::from_secs(1),
+                    config.max_backoff,
+                );
+                warn!(
+                    "couldn't publish user event {} ({}), retrying in {}s: {e:#}",
+                    event.id,
+                    event.kind,
+                    retry_in.as_secs()
+                );
+                outbox
+                    .mark_failed(event.id, &format!("{e:#}"), retry_in)
+                    .await?;
+                OUTBOX_EVENTS
+                    .with_label_values(&[&event.kind, "failed"])
+                    .inc();
+            }
+        }
+    }
+
+    Ok(events.len())
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context};
+use tracing::info;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    log_util::LoggableOutcome,
+    web::{
+        dto::notification_payload::KNOWN_TYPES,
+        metrics::NOTIFICATIONS_PRUNED,
+        repositories::{NotificationRepository, RepositoryResult},
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetentionAction {
+    Delete,
+    Archive,
+}
+
+impl FromStr for RetentionAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delete" => Ok(Self::Delete),
+            "archive" => Ok(Self::Archive),
+            other => Err(anyhow!(
+                "unknown retention action `{other}`, expected `delete` or `archive`"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetentionRule {
+    pub days: u32,
+    pub action: RetentionAction,
+}
+
+/// What to do with old notifications. `default` applies to every type that
+/// has no entry in `overrides`; when it's missing those types are kept
+/// forever.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    pub default: Option<RetentionRule>,
+    pub overrides: HashMap<String, RetentionRule>,
+    pub interval: Duration,
+    /// how many notifications a single statement gets rid of
+    pub batch_size: u32,
+}
+
+impl RetentionRule {
+    /// Parses `days[:action]`, e.g. `30:archive`.
+    pub fn parse(
+        rule: &str,
+        default_action: RetentionAction,
+    ) -> Result<RetentionRule, anyhow::Error> {
+        let (days, action) = match rule.trim().split_once(':') {
+            Some((days, action)) => (days, action.parse()?),
+            None => (rule.trim(), default_action),
+        };
+
+        Ok(RetentionRule {
+            days: days.parse().context("days must be a number")?,
+            action,
+        })
+    }
+}
+
+/// How many notifications a single run got rid of.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PruneReport {
+    pub deleted: u64,
+    pub archived: u64,
+}
+
+impl PruneReport {
+    fn add(&mut self, action: RetentionAction, rows: u64) {
+        match action {
+            RetentionAction::Delete => self.deleted += rows,
+            RetentionAction::Archive => self.archived += rows,
+        }
+    }
+}
+
+pub async fn run(
+    notifications: Arc<dyn NotificationRepository>,
+    policy: RetentionPolicy,
+    shutdown: CancellationToken,
+) {
+    info!(
+        "notification retention enabled, running every {}s",
+        policy.interval.as_secs()
+    );
+    let mut interval = tokio::time::interval(policy.interval);
+    let mut totals = PruneReport::default();
+
+    loop {
+        // a run that already started is left to finish
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+        if let Ok(report) = prune(notifications.as_ref(), &policy)
+            .await
+            .log_err_to_error("notification retention run failed")
+        {
+            totals.deleted += report.deleted;
+            totals.archived += report.archived;
+            info!(
+                "notification retention: deleted {}, archived {} (since startup: deleted {}, archived {})",
+                report.deleted, report.archived, totals.deleted, totals.archived
+            );
+        }
+    }
+}
+
+pub async fn prune(
+    notifications: &dyn NotificationRepository,
+    policy: &RetentionPolicy,
+) -> RepositoryResult<PruneReport> {
+    let mut report = PruneReport::default();
+    let overridden: Vec<&str> =
+        policy.overrides.keys().map(String::as_str).collect();
+
+    for (kind, rule) in &policy.overrides {
+        let rows =
+            prune_rule(notifications, Some(kind), &overridden, rule, policy.batch_size)
+                .await?;
+        info!(
+            "notification retention: {:?} {rows} `{kind}` notifications older than {} days",
+            rule.action, rule.days
+        );
+        report.add(rule.action, rows);
+    }
+    if let Some(rule) = &policy.default {
+        let rows =
+            prune_rule(notifications, None, &overridden, rule, policy.batch_size)
+                .await?;
+        info!(
+            "notification retention: {:?} {rows} notifications older than {} days",
+            rule.action, rule.days
+        );
+        report.add(rule.action, rows);
+    }
+
+    Ok(report)
+}
+
+/// Prunes notifications of type `kind`, or of every type but the
+/// `overridden` ones when `kind` is `None`, counting them by type in
+/// `notifications_pruned_total`. They go `batch_size` at a time, so that no
+/// statement locks a whole year of notifications at once.
+async fn prune_rule(
+    notifications: &dyn NotificationRepository,
+    kind: Option<&str>,
+    overridden: &[&str],
+    rule: &RetentionRule,
+    batch_size: u32,
+) -> RepositoryResult<u64> {
+    let action = match rule.action {
+        RetentionAction::Delete => "delete",
+        RetentionAction::Archive => "archive",
+    };
+    let mut rows = 0;
+    loop {
+        let counts = notifications
+            .prune(kind, overridden, rule, batch_size.into())
+            .await?;
+        let mut batch = 0;
+        for (kind, count) in counts {
+            NOTIFICATIONS_PRUNED
+                .with_label_values(&[type_label(&kind), action])
+                .inc_by(count);
+            batch += count;
+        }
+        rows += batch;
+        if batch < u64::from(batch_size) {
+            return Ok(rows);
+        }
+    }
+}
+
+/// The type as a metric label: the ones producers make up are `unknown`,
+/// so that they can't add a time series each.
+fn type_label(kind: &str) -> &str {
+    match KNOWN_TYPES.contains(&kind) {
+        true => kind,
+        false => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_are_parsed() {
+        let cases = [
+            ("30", RetentionAction::Archive, 30, RetentionAction::Archive),
+            (" 90 ", RetentionAction::Delete, 90, RetentionAction::Delete),
+            ("30:delete", RetentionAction::Archive, 30, RetentionAction::Delete),
+            ("7:archive", RetentionAction::Delete, 7, RetentionAction::Archive),
+            ("0:archive", RetentionAction::Delete, 0, RetentionAction::Archive),
+        ];
+        for (rule, default_action, days, action) in cases {
+            assert_eq!(
+                RetentionRule::parse(rule, default_action).unwrap(),
+                RetentionRule { days, action },
+                "{rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_rules_are_refused() {
+        for rule in ["", "thirty", "-1", "30:", "30:shred", ":archive", "30:archive:delete"] {
+            assert!(
+                RetentionRule::parse(rule, RetentionAction::Delete).is_err(),
+                "{rule}"
+            );
+        }
+    }
+
+    #[test]
+    fn made_up_types_share_a_label() {
+        assert_eq!(type_label("refuel"), "refuel");
+        assert_eq!(type_label("car_invite"), "car_invite");
+        assert_eq!(type_label("promo-2026-10"), "unknown");
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, Url};
+use sha2::Sha256;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{
+    config::WebhooksConfig,
+    log_util::LoggableOutcome,
+    web::{
+        jobs::backoff,
+        metrics::WEBHOOK_DELIVERIES,
+        models::webhooks::{DueDelivery, Outcome, WebhookAttempt},
+        outbound::{self, PublicResolver},
+        repositories::{RepositoryResult, WebhookRepository},
+    },
+};
+
+/// `t=<unix timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`
+pub const SIGNATURE: &str = "x-pieno-signature";
+/// the event type, e.g. `user.registered`
+pub const EVENT: &str = "x-pieno-event";
+/// the same on every attempt, for partners to drop duplicates
+pub const DELIVERY: &str = "x-pieno-delivery";
+
+/// deliveries sent per round at most
+const BATCH_SIZE: i64 = 50;
+/// the wait after the first failure, doubling every time
+const FIRST_RETRY: Duration = Duration::from_secs(30);
+/// how often finished deliveries are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Sends the deliveries queued for webhook subscriptions along with user
+/// events. Failed ones are retried with an exponential backoff, until they
+/// run out of attempts and are dead.
+pub async fn run(
+    webhooks: Arc<dyn WebhookRepository>,
+    config: WebhooksConfig,
+    shutdown: CancellationToken,
+) {
+    let sender = HttpSender::new(config.timeout);
+    let mut interval = tokio::time::interval(config.poll_interval);
+    let mut purge = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = purge.tick() => {
+                if let Ok(deleted) = webhooks
+                    .purge(config.keep_deliveries)
+                    .await
+                    .log_err_to_error("webhook deliveries cleanup failed")
+                {
+                    if deleted > 0 {
+                        info!("deleted {deleted} finished webhook deliveries");
+                    }
+                }
+                continue;
+            }
+            _ = interval.tick() => {}
+        }
+        while !shutdown.is_cancelled() {
+            match dispatch(&*webhooks, &config, &sender)
+                .await
+                .log_err_to_error("webhook dispatch failed")
+            {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+/// Sends one batch of deliveries, returning how many were tried. They're
+/// leased for twice the time partners have to answer rather than locked,
+/// so no transaction is held open while waiting for them.
+pub async fn dispatch(
+    webhooks: &dyn WebhookRepository,
+    config: &WebhooksConfig,
+    sender: &dyn Sender,
+) -> RepositoryResult<usize> {
+    let due = webhooks.claim(BATCH_SIZE, config.timeout * 2).await?;
+    // partners don't wait for each other
+    let attempts = join_all(due.iter().map(|due| sender.send(due))).await;
+    for (due, attempt) in due.iter().zip(attempts) {
+        let delivery = &due.delivery;
+        let outcome = if attempt.error.is_none() {
+            Outcome::Delivered
+        } else if delivery.attempts + 1 >= config.max_attempts as i32 {
+            warn!(
+                "webhook delivery {} to {} is dead after {} attempts",
+                delivery.id, due.url, config.max_attempts
+            );
+            Outcome::Dead
+        } else {
+            Outcome::Retry(backoff(delivery.attempts, FIRST_RETRY, config.max_backoff))
+        };
+        let label = match outcome {
+            Outcome::Delivered => "delivered",
+            Outcome::Retry(_) => "failed",
+            Outcome::Dead => "dead",
+        };
+        WEBHOOK_DELIVERIES.with_label_values(&[label]).inc();
+        webhooks.record(&attempt, outcome).await?;
+    }
+
+    Ok(due.len())
+}
+
+/// Where deliveries are sent. An attempt without an `error` is delivered.
+#[async_trait]
+pub trait Sender: Send + Sync {
+    async fn send(&self, due: &DueDelivery) -> WebhookAttempt;
+}
+
+/// POSTs the events to the partners, which have to answer with a 2xx.
+pub struct HttpSender {
+    http: reqwest::Client,
+}
+
+impl HttpSender {
+    pub fn new(timeout: Duration) -> HttpSender {
+        // a redirect would get the payload to somewhere nobody subscribed,
+        // and names only ever resolve to public addresses
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()
+            .unwrap_or_default();
+
+        HttpSender { http }
+    }
+}
+
+#[async_trait]
+impl Sender for HttpSender {
+    async fn send(&self, due: &DueDelivery) -> WebhookAttempt {
+        let delivery = &due.delivery;
+        let body =
+            serde_json::to_vec(&delivery.event().envelope()).unwrap_or_default();
+        let attempted_at = Utc::now();
+        let started = Instant::now();
+        // the URL was checked when it was set, but maybe not by this version
+        let refused = Url::parse(&due.url)
+            .map_err(|e| e.to_string())
+            .and_then(|url| outbound::check_url(&url).map_err(|e| e.to_string()));
+        let (status_code, error) = match refused {
+            Err(e) => (None, Some(format!("not sent: {e}"))),
+            Ok(()) => {
+                let result = self
+                    .http
+                    .post(&due.url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .header(
+                        SIGNATURE,
+                        signature(&due.secret, attempted_at.timestamp(), &body),
+                    )
+                    .header(EVENT, &delivery.kind)
+                    .header(DELIVERY, delivery.id.to_string())
+                    .body(body)
+                    .send()
+                    .await;
+                match result {
+                    Ok(response) if response.status().is_success() => {
+                        (Some(response.status()), None)
+                    }
+                    Ok(response) => (
+                        Some(response.status()),
+                        Some(format!("the partner answered {}", response.status())),
+                    ),
+                    Err(e) => (e.status(), Some(e.to_string())),
+                }
+            }
+        };
+
+        WebhookAttempt {
+            delivery_id: delivery.id,
+            attempted_at,
+            status_code: status_code.map(|status| status.as_u16() as i16),
+            duration_ms: started.elapsed().as_millis() as i32,
+            error,
+        }
+    }
+}
+
+/// What goes in the `X-Pieno-Signature` header. Partners compute the HMAC
+/// of `<t>.<body>` with their secret, compare it to `v1` and refuse the
+/// timestamps too far in the past, so that captured requests can't be
+/// replayed.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!("t={timestamp},v1={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,100 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::ValidationError;
+
+/// Languages the app ships in.
+#[derive(
+    Serialize, Deserialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq, Hash,
+    Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    It,
+}
+
+impl Locale {
+    pub const ALL: &'static [Locale] = &[Locale::En, Locale::It];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::It => "it",
+        }
+    }
+
+    /// Parses a stored locale, falling back to the default one.
+    pub fn from_code(code: &str) -> Locale {
+        code.parse().unwrap_or_default()
+    }
+
+    /// The language we ship in that the caller prefers the most, going by
+    /// the quality values of an `Accept-Language` header.
+    pub fn from_accept_language(header: &str) -> Option<Locale> {
+        let mut ranges: Vec<(f32, Locale)> = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let locale = parts.next()?.trim().parse().ok()?;
+                let quality = match parts.find_map(|p| p.trim().strip_prefix("q=")) {
+                    Some(quality) => quality.parse().ok()?,
+                    None => 1.0,
+                };
+                Some((quality, locale))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // stable, so ties go to whichever came first
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        ranges.first().map(|(_, locale)| *locale)
+    }
+
+    pub fn format_datetime(&self, at: DateTime<Utc>, time_zone: Tz) -> String {
+        let (format, locale) = match self {
+            Locale::En => ("%B %-d, %Y %-I:%M %p", chrono::Locale::en_US),
+            Locale::It => ("%-d %B %Y %H:%M", chrono::Locale::it_IT),
+        };
+        at.with_timezone(&time_zone)
+            .format_localized(format, locale)
+            .to_string()
+    }
+
+    pub fn format_number(&self, n: f64) -> String {
+        let formatted = format!("{}", (n * 100.0).round() / 100.0);
+        match self {
+            Locale::En => formatted,
+            Locale::It => formatted.replace('.', ","),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // region subtags (`it-IT`) don't change anything for us
+        match s.split(['-', '_']).next().map(str::to_lowercase).as_deref() {
+            Some("en") => Ok(Locale::En),
+            Some("it") => Ok(Locale::It),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parses a stored IANA time zone, falling back to UTC.
+pub fn time_zone(name: &str) -> Tz {
+    name.parse().unwrap_or(Tz::UTC)
+}
+
+pub fn validate_time_zone(time_zone: &str) -> Result<(), ValidationError> {
+    time_zone
+        .parse::<Tz>()
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("invalid_time_zone"))
+}
@@ -0,0 +1,83 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use lettre::{
+    message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message,
+    Tokio1Executor,
+};
+use tokio::sync::Mutex;
+
+use crate::config::MailConfig;
+
+/// Sends plain text emails through SMTP.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    check_interval: Duration,
+    last_check: Arc<Mutex<Option<LastCheck>>>,
+}
+
+/// When the SMTP server was last checked, and how that went.
+type LastCheck = (Instant, Result<(), String>);
+
+impl Mailer {
+    /// `None` when there's no SMTP server to send emails through.
+    pub fn from_config(
+        config: &MailConfig,
+    ) -> Result<Option<Mailer>, anyhow::Error> {
+        let Some(url) = &config.smtp_url else {
+            return Ok(None);
+        };
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+            .context("invalid SMTP_URL")?
+            .build();
+
+        Ok(Some(Mailer {
+            transport,
+            from: config.from.clone(),
+            check_interval: config.check_interval,
+            last_check: Arc::new(Mutex::new(None)),
+        }))
+    }
+
+    pub async fn send(
+        &self,
+        to: &str,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), anyhow::Error> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body.to_string())?;
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+
+    /// Connects to the SMTP server, without sending anything. The outcome is
+    /// kept for `check_interval`, probes come far more often than that.
+    pub async fn check(&self) -> Result<(), anyhow::Error> {
+        // held while connecting, so concurrent probes wait for one check
+        let mut last_check = self.last_check.lock().await;
+        if let Some((at, result)) = last_check.as_ref() {
+            if at.elapsed() < self.check_interval {
+                return result.clone().map_err(anyhow::Error::msg);
+            }
+        }
+
+        let result = match self.transport.test_connection().await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("the SMTP server refused the connection".to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        *last_check = Some((Instant::now(), result.clone()));
+
+        result.map_err(anyhow::Error::msg)
+    }
+}
@@ -0,0 +1,144 @@
+//! Prometheus metrics, served on `/metrics`.
+//!
+//! Labels only ever take a handful of values (route templates, status codes,
+//! outcomes), never ids or anything else coming from the requests.
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    exponential_buckets, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry, Encoder, HistogramVec, IntCounterVec,
+    IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+use sqlx::{Pool, Postgres};
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "http_requests_total",
+        "Requests handled, by route template and status",
+        &["method", "route", "status"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "http_request_duration_seconds",
+        "Time to produce a response, by route template and status",
+        &["method", "route", "status"],
+        // 1ms to ~16s
+        exponential_buckets(0.001, 2.0, 15).unwrap(),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        "db_pool_connections",
+        "Connections in the database pool, by state (idle, in_use, max)",
+        &["state"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static PASSWORD_HASHING_QUEUE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "password_hashing_queue_depth",
+        "Argon2 operations waiting for a blocking thread",
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static PASSWORD_HASHING_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        "password_hashing_duration_seconds",
+        "Time spent hashing or verifying a password, queueing excluded",
+        &["operation"],
+        exponential_buckets(0.005, 2.0, 10).unwrap(),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static LOGINS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "logins_total",
+        "Login attempts, by outcome (success, failure)",
+        &["outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static TOKENS_ISSUED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "tokens_issued_total",
+        "Access tokens issued, by reason (login, register)",
+        &["reason"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static NOTIFICATIONS_SERVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "notifications_served_total",
+        "Notifications sent to clients, by how (list, stream)",
+        &["via"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static NOTIFICATIONS_PRUNED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "notifications_pruned_total",
+        "Notifications removed by retention, by type and action (delete, archive)",
+        &["type", "action"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static OUTBOX_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "outbox_events_total",
+        "Attempts at publishing user events, by type and outcome (published, failed)",
+        &["type", "outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+pub static WEBHOOK_DELIVERIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "webhook_delivery_attempts_total",
+        "Attempts at delivering webhooks, by outcome (delivered, failed, dead)",
+        &["outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Everything in the Prometheus text format, gauges sampled right now.
+pub fn render(pool: &Pool<Postgres>) -> Result<String, anyhow::Error> {
+    let idle = pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set(pool.size() as i64 - idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["max"])
+        .set(pool.options().get_max_connections() as i64);
+
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+
+    Ok(String::from_utf8(buffer)?)
+}
@@ -0,0 +1,38 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::info;
+
+use super::request_id::RequestContext;
+
+/// One `access_log` line per request, after the response is ready.
+pub async fn access_log(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or("unmatched".to_string());
+    let method = request.method().to_string();
+    let context = request.extensions().get::<RequestContext>().cloned();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let user_id = context.as_ref().and_then(RequestContext::user_id);
+    info!(
+        target: "access_log",
+        method,
+        route,
+        status = response.status().as_u16(),
+        latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+        user_id,
+        request_id = context.map(|c| c.request_id),
+        "request handled"
+    );
+
+    response
+}
@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::LINK, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+
+use crate::web::versions::ApiVersion;
+
+pub static DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+pub static SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// What callers of a deprecated version are told.
+#[derive(Clone, Copy)]
+pub struct Deprecation {
+    pub since: DateTime<Utc>,
+    /// when it goes away, if that's been decided
+    pub sunset: Option<DateTime<Utc>>,
+}
+
+/// Marks every response as deprecated (RFC 9745), with its `Sunset`
+/// (RFC 8594) and a link to the same route in the latest version.
+pub async fn deprecation(
+    State(deprecation): State<Deprecation>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        ApiVersion::LATEST.prefix(),
+        request.uri().path()
+    );
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    let since = format!("@{}", deprecation.since.timestamp());
+    let sunset = deprecation
+        .sunset
+        .map(|at| at.format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+    for (name, value) in [
+        (&DEPRECATION, Some(since)),
+        (&SUNSET, sunset),
+        (&LINK, Some(successor)),
+    ] {
+        if let Some(Ok(value)) = value.map(|v| HeaderValue::from_str(&v)) {
+            headers.append(name.clone(), value);
+        }
+    }
+
+    response
+}
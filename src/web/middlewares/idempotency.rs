@@ -0,0 +1,226 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, request::Parts, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::error;
+
+use crate::{
+    log_util::LoggableOutcome,
+    web::{
+        dto::{user_claims::UserClaims, Claim},
+        errors::{code::ErrorCode, HttpError},
+        extractors::token::{bearer_token, Token},
+        middlewares::request_id::RequestContext,
+        models::idempotency_keys::{Reservation, StoredResponse},
+        AppState,
+    },
+};
+
+pub static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+/// set on responses that were stored, rather than handled again
+pub static IDEMPOTENT_REPLAYED: HeaderName =
+    HeaderName::from_static("idempotent-replayed");
+
+/// the same as axum's default for extractors
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+/// what `IDEMPOTENCY_SECRET` is narrowed down to, so hashing and encrypting
+/// never share a key
+const HASH_KEY: &[u8] = b"idempotency request hash";
+const ENCRYPTION_KEY: &[u8] = b"idempotency response encryption";
+
+/// Handles each `Idempotency-Key` once: the first response is stored and
+/// replayed to the retries carrying the same body, while retries with a
+/// different one are refused. Keys belong to the user, when the call is
+/// authenticated, or to its address otherwise. Neither the bodies of the
+/// calls nor the responses are stored as they are: they can carry passwords
+/// and tokens.
+pub async fn idempotency(
+    State(s): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let Some(key) = request.headers().get(&IDEMPOTENCY_KEY) else {
+        return Ok(next.run(request).await);
+    };
+    let key = key
+        .to_str()
+        .ok()
+        .filter(|key| {
+            !key.is_empty()
+                && key.len() <= 255
+                && key.chars().all(|c| c.is_ascii_graphic())
+        })
+        .ok_or(HttpError::Simple(ErrorCode::InvalidIdempotencyKey))?
+        .to_string();
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_BODY_SIZE)
+        .await
+        .map_err(|_| HttpError::Simple(ErrorCode::InvalidBody))?;
+    let scope = scope(&s, &parts).await;
+    let reservation = s
+        .idempotency
+        .reserve(
+            &scope,
+            &key,
+            &request_hash(&s.config.idempotency.secret, &parts, &body),
+            s.config.idempotency.ttl,
+            s.config.idempotency.abandoned_after,
+        )
+        .await?;
+    match reservation {
+        Reservation::Reserved => {}
+        Reservation::InProgress => {
+            return Err(HttpError::Simple(ErrorCode::IdempotencyKeyInProgress))
+        }
+        Reservation::Mismatch => {
+            return Err(HttpError::Simple(ErrorCode::IdempotencyKeyReused))
+        }
+        Reservation::Completed(stored) => {
+            return replay(&s.config.idempotency.secret, &scope, &key, stored)
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    // the retry deserves another chance
+    if response.status().is_server_error() {
+        let _ = s
+            .idempotency
+            .release(&scope, &key)
+            .await
+            .log_err_to_error("couldn't release an idempotency key");
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(body) = to_bytes(body, usize::MAX).await else {
+        let _ = s
+            .idempotency
+            .release(&scope, &key)
+            .await
+            .log_err_to_error("couldn't release an idempotency key");
+        return Err(HttpError::Simple(ErrorCode::InternalServerError));
+    };
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        body: seal(&s.config.idempotency.secret, &scope, &key, &body),
+    };
+    // the call went through: failing to store it only costs the replay
+    let _ = s
+        .idempotency
+        .complete(&scope, &key, &stored)
+        .await
+        .log_err_to_error("couldn't store an idempotent response");
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// `user:<id>` for calls with a valid token, `ip:<address>` otherwise.
+async fn scope(s: &AppState, parts: &Parts) -> String {
+    if let Some(token) = bearer_token(parts).ok().flatten() {
+        if let Ok(Token(claims)) =
+            Token::<Claim<UserClaims>>::from(&token, &s.config.jwt).await
+        {
+            return format!("user:{}", claims.data().user_id);
+        }
+    }
+
+    match RequestContext::current().and_then(|context| context.client_ip) {
+        Some(ip) => format!("ip:{ip}"),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// Tells calls apart: the same key can't be used on another endpoint.
+fn request_hash(secret: &str, parts: &Parts, body: &Bytes) -> String {
+    let mut mac = mac(&subkey(secret, HASH_KEY));
+    mac.update(parts.method.as_str().as_bytes());
+    mac.update(b" ");
+    mac.update(parts.uri.path().as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Encrypts a response for `key` alone: its nonce, then the ciphertext.
+fn seal(secret: &str, scope: &str, key: &str, body: &[u8]) -> Vec<u8> {
+    let cipher = cipher(secret);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let aad = format!("{scope}\n{key}");
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: body, aad: aad.as_bytes() })
+        // only fails for bodies of gigabytes
+        .expect("couldn't encrypt a response");
+
+    [nonce.as_slice(), &ciphertext].concat()
+}
+
+/// What [`seal`] encrypted, unless it was stored for another key, tampered
+/// with or sealed with another secret.
+fn open(secret: &str, scope: &str, key: &str, sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < 12 {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(12);
+    let aad = format!("{scope}\n{key}");
+    cipher(secret)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload { msg: ciphertext, aad: aad.as_bytes() },
+        )
+        .ok()
+}
+
+fn cipher(secret: &str) -> Aes256Gcm {
+    Aes256Gcm::new(subkey(secret, ENCRYPTION_KEY).as_slice().into())
+}
+
+fn subkey(secret: &str, purpose: &[u8]) -> Vec<u8> {
+    let mut mac = mac(secret.as_bytes());
+    mac.update(purpose);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn mac(key: &[u8]) -> Hmac<Sha256> {
+    <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .expect("HMAC takes keys of any length")
+}
+
+fn replay(
+    secret: &str,
+    scope: &str,
+    key: &str,
+    stored: StoredResponse,
+) -> Result<Response, HttpError> {
+    let Some(body) = open(secret, scope, key, &stored.body) else {
+        error!("couldn't decrypt the response stored for an idempotency key");
+        return Err(HttpError::Simple(ErrorCode::InternalServerError));
+    };
+    let status =
+        StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut response = (status, body).into_response();
+    let headers = response.headers_mut();
+    match stored.content_type.and_then(|c| HeaderValue::from_str(&c).ok()) {
+        Some(content_type) => headers.insert(CONTENT_TYPE, content_type),
+        None => headers.remove(CONTENT_TYPE),
+    };
+    headers.insert(IDEMPOTENT_REPLAYED.clone(), HeaderValue::from_static("true"));
+
+    Ok(response)
+}
@@ -1 +1,7 @@
-
+pub mod access_log;
+pub mod deprecation;
+pub mod idempotency;
+pub mod metrics;
+pub mod request_id;
+pub mod security_headers;
+pub mod trace;
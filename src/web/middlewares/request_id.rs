@@ -0,0 +1,163 @@
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{ACCEPT_LANGUAGE, USER_AGENT},
+        HeaderName, HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    config::{Config, ErrorFormat},
+    web::locale::Locale,
+};
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub static X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// What's known about the request being handled, available anywhere down
+/// the call chain of its handler (spawned tasks need
+/// [`with_current_context`]).
+#[derive(Clone)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub path: String,
+    /// how errors are rendered, from the config
+    pub error_format: ErrorFormat,
+    /// the best match for `Accept-Language`, if the caller sent one
+    pub accept_language: Option<Locale>,
+    /// who's on the other end, see [`client_ip`]
+    pub client_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    // set later on, by the token extractor
+    user_id: Arc<Mutex<Option<String>>>,
+    user_locale: Arc<Mutex<Option<Locale>>>,
+}
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+impl RequestContext {
+    /// The context of the request being handled, if any.
+    pub fn current() -> Option<RequestContext> {
+        CONTEXT.try_with(RequestContext::clone).ok()
+    }
+
+    /// Id of the authenticated user, if the request carried a valid token.
+    pub fn user_id(&self) -> Option<String> {
+        self.user_id.lock().unwrap().clone()
+    }
+
+    /// What to answer in: `Accept-Language` first, then the locale the
+    /// authenticated user saved, then the default one.
+    pub fn locale(&self) -> Locale {
+        self.accept_language
+            .or(*self.user_locale.lock().unwrap())
+            .unwrap_or_default()
+    }
+}
+
+/// Runs `f` with the context of the request being handled now, for tasks
+/// spawned on its behalf: their logs and outgoing calls (`X-Request-Id`)
+/// still belong to it.
+pub fn with_current_context<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    // taken now: by the time the task runs, it's somewhere else
+    let context = RequestContext::current();
+    async move {
+        match context {
+            Some(context) => CONTEXT.scope(context, f).await,
+            None => f.await,
+        }
+    }
+}
+
+/// Remembers who's making the request, for the access log.
+pub fn set_user_id(user_id: &str) {
+    let _ = CONTEXT.try_with(|context| {
+        *context.user_id.lock().unwrap() = Some(user_id.to_string());
+    });
+}
+
+/// Remembers the locale the authenticated user saved, for when the request
+/// doesn't say which language it wants.
+pub fn set_user_locale(locale: Locale) {
+    let _ = CONTEXT.try_with(|context| {
+        *context.user_locale.lock().unwrap() = Some(locale);
+    });
+}
+
+/// Keeps the `X-Request-Id` the caller sent, or makes one up, and echoes it
+/// back in the response.
+pub async fn request_id(
+    State(config): State<Arc<Config>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        // it ends up in our logs, so only sane ids are trusted
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 128
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| nanoid::nanoid!());
+
+    let context = RequestContext {
+        request_id: request_id.clone(),
+        path: request.uri().path().to_string(),
+        error_format: config.server.error_format,
+        accept_language: request
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::from_accept_language),
+        client_ip: client_ip(&request, config.server.trust_forwarded_for),
+        user_agent: request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            // it's only kept for the audit log
+            .map(|agent| agent.chars().take(512).collect()),
+        user_id: Arc::new(Mutex::new(None)),
+        user_locale: Arc::new(Mutex::new(None)),
+    };
+    request.extensions_mut().insert(context.clone());
+
+    let mut response = CONTEXT.scope(context, next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+
+    response
+}
+
+/// The address of the caller: the last one in `X-Forwarded-For`, added by
+/// the proxy in front of us, when it's trusted, or the peer's otherwise.
+/// `None` when served without connection info, like in tests.
+fn client_ip(request: &Request, trust_forwarded_for: bool) -> Option<IpAddr> {
+    let forwarded = trust_forwarded_for
+        .then(|| request.headers().get_all(&X_FORWARDED_FOR).iter().next_back())
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+
+    forwarded.or_else(|| {
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| peer.ip())
+    })
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{
+            ACCEPT_LANGUAGE, AUTHORIZATION, CONTENT_LANGUAGE, CONTENT_TYPE, LINK,
+        },
+        HeaderName, HeaderValue, Method,
+    },
+    middleware::Next,
+    response::Response,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+
+use super::{
+    deprecation::{DEPRECATION, SUNSET},
+    idempotency::{IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED},
+    request_id::X_REQUEST_ID,
+};
+
+/// Set on responses that already got the headers of their route group, so
+/// the defaults around every group don't add back what it left out.
+#[derive(Clone, Copy)]
+struct Secured;
+
+/// Adds the security headers of a route group to its responses, unless the
+/// handler set them already. Around the whole router it adds the defaults to
+/// what no group answered, such as 404s.
+pub async fn security_headers(
+    State(headers): State<Arc<Vec<(HeaderName, HeaderValue)>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if response.extensions().get::<Secured>().is_some() {
+        return response;
+    }
+    for (name, value) in headers.iter() {
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response.extensions_mut().insert(Secured);
+
+    response
+}
+
+/// What browsers on other origins may do, or `None` when no origin is
+/// allowed at all.
+pub fn cors(config: &CorsConfig) -> Option<CorsLayer> {
+    let origins = match config.allowed_origins.as_slice() {
+        [] => return None,
+        [any] if any == "*" => AllowOrigin::any(),
+        // checked when loading the config
+        origins => AllowOrigin::list(origins.iter().filter_map(|o| o.parse().ok())),
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers([
+                AUTHORIZATION,
+                CONTENT_TYPE,
+                ACCEPT_LANGUAGE,
+                X_REQUEST_ID.clone(),
+                IDEMPOTENCY_KEY.clone(),
+                HeaderName::from_static("last-event-id"),
+            ])
+            .expose_headers([
+                X_REQUEST_ID.clone(),
+                CONTENT_LANGUAGE,
+                DEPRECATION.clone(),
+                SUNSET.clone(),
+                LINK,
+                IDEMPOTENT_REPLAYED.clone(),
+            ])
+            .allow_credentials(config.allow_credentials)
+            .max_age(config.max_age),
+    )
+}
@@ -1,94 +1,369 @@
+mod audit;
 mod errors;
 pub mod extractors;
+pub mod jobs;
 pub mod middlewares;
+mod delivery;
+mod i18n;
+pub mod locale;
+mod mail;
+mod metrics;
+pub mod outbound;
+mod push;
+pub mod repositories;
 mod routes;
+mod shutdown;
+mod sinks;
+#[cfg(test)]
+mod tests;
 pub mod dto;
 pub mod models;
-mod util;
+pub mod util;
+pub mod versions;
 
-use std::{env, time::Duration};
+use std::{io, net::SocketAddr, sync::Arc};
 
-use axum::{extract::FromRef, http::HeaderMap, response::IntoResponse, routing::get, Router};
-use log::info;
-use sqlx::postgres::{PgPoolOptions, Postgres};
-use utoipa::{openapi::security::{Http, HttpAuthScheme, SecurityScheme}, Modify, OpenApi};
+use axum::{extract::FromRef, http::StatusCode, middleware, Extension, Router};
+use tracing::{info, warn};
+use sqlx::{Pool, Postgres};
+use tokio::net::TcpListener;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use utoipa::{openapi::{security::{Http, HttpAuthScheme, SecurityScheme}, Content, Deprecated, Ref, RefOr, Response}, Modify, OpenApi};
+use utoipa_swagger_ui::{SwaggerUi, Url};
 
-use crate::web::{dto::{auth::{logged_user_response::LoggedUserResponse, login_request::{LoginRequest, LoginResponse}, register_request::{RegisterRequest, RegisterResponse}}, me::notifications::NotificationResponse, user_claims::UserClaims}, routes::{auth::auth_routes, me::me_routes}};
+use crate::{config::{Config, RouteGroup}, web::{jobs::listener::NotificationHub, dto::{auth::{logged_user_response::LoggedUserResponse, login_request::{LoginRequest, LoginResponse}, put_fcm_token_request::PutFcmTokenRequest, register_request::{RegisterRequest, RegisterResponse}}, me::{update_profile_request::UpdateProfileRequest, notification_settings::{ChannelSettings, NotificationSettings, NotificationSettingsResponse, QuietHours}, mark_read_request::{MarkAllReadRequest, MarkReadResponse}, notifications::{Notification, NotificationResponse, UnreadCountResponse}, security_events::SecurityEventsResponse}, admin::{audit_events_query::AuditEventsResponse, create_webhook_request::{CreateWebhookRequest, CreateWebhookResponse, WebhooksResponse}, update_webhook_request::{UpdateWebhookRequest, WebhookResponse}, webhook_deliveries_query::{WebhookDeliveriesResponse, WebhookDeliveryResponse}}, notification_payload::{CarInvitePayload, NotificationPayload, RefuelPayload, UnknownPayload}, user_claims::UserClaims}, routes::{admin::admin_routes, auth::auth_routes, health::health_routes, internal::internal_routes, main::main_routes, me::{me_routes, unversioned_me_routes}, metrics::metrics_routes, table::RouteTable}, versions::ApiVersion, middlewares::{access_log::access_log, deprecation::{deprecation, Deprecation}, metrics::track_metrics, request_id::request_id, security_headers, trace::trace_requests}, models::{audit_events::{AuditEvent, AuditKind}, outbox::OutboxKind, webhooks::{DeliveryState, WebhookAttempt, WebhookDelivery, WebhookSubscription}, notifications::CreatedNotification, users::PublicUserModel}, push::PushClient, mail::Mailer, locale::Locale, repositories::{postgres::{PgAuditRepository, PgIdempotencyRepository, PgNotificationRepository, PgOutboxRepository, PgUserRepository, PgWebhookRepository}, AuditRepository, IdempotencyRepository, NotificationRepository, OutboxRepository, UserRepository, WebhookRepository}, dto::internal::create_notifications_request::{CreateNotificationsRequest, CreateNotificationsResponse}, dto::health::health_response::{CheckResult, HealthResponse}, dto::errors::{field_error::FieldError, problem::{Location, Problem}}, errors::code::ErrorCode}};
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pool: sqlx::Pool<Postgres>,
+    config: Arc<Config>,
+    users: Arc<dyn UserRepository>,
+    notifications: Arc<dyn NotificationRepository>,
+    idempotency: Arc<dyn IdempotencyRepository>,
+    audit: Arc<dyn AuditRepository>,
+    outbox: Arc<dyn OutboxRepository>,
+    webhooks: Arc<dyn WebhookRepository>,
+    hub: NotificationHub,
+    push: Option<PushClient>,
+    mailer: Option<Mailer>,
+    /// cancelled as soon as the service starts shutting down
+    shutdown: CancellationToken,
+    /// background work to wait for before exiting
+    tasks: TaskTracker,
 }
 impl AppState {
-    pub async fn new() -> Result<AppState, anyhow::Error> {
-        info!("acquiring pool...");
+    pub fn new(config: Config, pool: Pool<Postgres>) -> Result<AppState, anyhow::Error> {
+        Ok(AppState {
+            users: Arc::new(PgUserRepository::new(pool.clone())),
+            notifications: Arc::new(PgNotificationRepository::new(pool.clone())),
+            idempotency: Arc::new(PgIdempotencyRepository::new(pool.clone())),
+            audit: Arc::new(PgAuditRepository::new(pool.clone())),
+            outbox: Arc::new(PgOutboxRepository::new(pool.clone())),
+            webhooks: Arc::new(PgWebhookRepository::new(pool.clone())),
+            pool,
+            hub: NotificationHub::new(1024),
+            push: config.push.fcm_service_url.as_deref().map(PushClient::new),
+            mailer: Mailer::from_config(&config.mail)?,
+            config: Arc::new(config),
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        })
+    }
+}
+
+/// The router along with what's needed to shut it down cleanly.
+pub struct App {
+    router: Router,
+    state: AppState,
+}
+
+impl App {
+    /// Serves requests until SIGINT or SIGTERM, then drains in-flight
+    /// requests and background jobs for up to `shutdown_timeout`.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        let App { router, state } = self;
+        let server = &state.config.server;
 
-        let pool = PgPoolOptions::new()
-            .acquire_timeout(Duration::from_secs(3))
-            .max_connections(1)
-            .connect(&env::var("CONN_URI").unwrap())
+        let stopping = state.shutdown.clone();
+        let grace = server.shutdown_grace;
+        // the peer's address, for when `X-Forwarded-For` isn't trusted
+        let router = router.into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(listener, router)
+            .with_graceful_shutdown(async move {
+                shutdown::signal().await;
+                // readiness fails from here on, and streams get closed
+                stopping.cancel();
+                if !grace.is_zero() {
+                    info!("shutting down in {}s...", grace.as_secs());
+                    tokio::time::sleep(grace).await;
+                }
+                info!("not accepting connections anymore, draining...");
+            })
             .await?;
 
-        Ok(AppState { pool })
+        state.tasks.close();
+        if tokio::time::timeout(server.shutdown_timeout, state.tasks.wait())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} background tasks still running after {}s, giving up on them",
+                state.tasks.len(),
+                server.shutdown_timeout.as_secs()
+            );
+        }
+        state.pool.close().await;
+        info!("shutdown complete");
+
+        Ok(())
     }
 }
 
-pub async fn build_app() -> Router {
-    let state = AppState::new().await.unwrap();
-    info!("state ok");
+/// Schemas and security schemes of the OpenAPI document, paths come from the
+/// route tables: see [`api_doc`] for what's actually served.
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Users endpoints"),
+    modifiers(&SecurityAddon),
+    components(
+    schemas(
+            LoginRequest,
+            LoginResponse,
+            RegisterRequest,
+            RegisterResponse,
+            PutFcmTokenRequest,
+            LoggedUserResponse,
+            UserClaims,
+            NotificationResponse,
+            Notification,
+            NotificationPayload,
+            RefuelPayload,
+            CarInvitePayload,
+            UnknownPayload,
+            PublicUserModel,
+            CreateNotificationsRequest,
+            CreateNotificationsResponse,
+            CreatedNotification,
+            NotificationSettings,
+            NotificationSettingsResponse,
+            ChannelSettings,
+            QuietHours,
+            UpdateProfileRequest,
+            Locale,
+            UnreadCountResponse,
+            SecurityEventsResponse,
+            AuditEventsResponse,
+            AuditEvent,
+            AuditKind,
+            WebhooksResponse,
+            WebhookSubscription,
+            CreateWebhookRequest,
+            CreateWebhookResponse,
+            UpdateWebhookRequest,
+            WebhookResponse,
+            WebhookDeliveriesResponse,
+            WebhookDeliveryResponse,
+            WebhookDelivery,
+            WebhookAttempt,
+            DeliveryState,
+            OutboxKind,
+            MarkAllReadRequest,
+            MarkReadResponse,
+            HealthResponse,
+            CheckResult,
+            Problem,
+            FieldError,
+            Location,
+            ErrorCode
+        )
+    )
+)]
+pub struct ApiDoc;
 
-    #[derive(OpenApi)]
-    #[openapi(
-        info(description = "Users endpoints"),
-        paths(
-            routes::main::root::index,
-            routes::auth::root::login,
-            routes::auth::root::index,
-            routes::auth::root::register,
-            routes::auth::root::add_fcm_token,
-            routes::me::root::get_me_notifications,
-        ), 
-        modifiers(&SecurityAddon),
-        components(
-        schemas(
-                LoginRequest,
-                LoginResponse,
-                RegisterRequest,
-                RegisterResponse,
-                LoggedUserResponse,
-                UserClaims,
-                NotificationResponse
-            )
+/// The OpenAPI document of `version`, served on its `schema_path` and
+/// browsable on `/docs`.
+pub fn api_doc(version: ApiVersion) -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.paths.paths = api_routes(RouteTable::docs(), version)
+        .into_paths()
+        .paths
+        .into_iter()
+        .map(|(path, mut item)| {
+            if version.is_deprecated() {
+                for operation in item.operations.values_mut() {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+            (format!("{}{path}", version.prefix()), item)
+        })
+        .collect();
+    if version.is_deprecated() {
+        doc.info.description = Some(format!(
+            "Users endpoints. Deprecated: use the `{}` ones instead",
+            ApiVersion::LATEST.prefix()
+        ));
+    }
+    doc.paths
+        .paths
+        .extend(service_routes(RouteTable::docs()).into_paths().paths);
+    ProblemsAddon.modify(&mut doc);
+
+    doc
+}
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components: &mut utoipa::openapi::Components = openapi.components.as_mut().unwrap(); // we can unwrap safely since there already is components registered.
+        components.add_security_scheme(
+            "bearerAuth",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        // tokens minted by other PIENO services, carrying `ServiceClaims`
+        components.add_security_scheme(
+            "serviceAuth",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        // tokens of the back-office tools, carrying `AdminClaims`
+        components.add_security_scheme(
+            "adminAuth",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
         )
-    )]
-    struct ApiDoc;
-    struct SecurityAddon;
-    
-    impl Modify for SecurityAddon {
-        fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
-            let components: &mut utoipa::openapi::Components = openapi.components.as_mut().unwrap(); // we can unwrap safely since there already is components registered.
-            components.add_security_scheme(
-                "bearerAuth",
-                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
-            )
+    }
+}
+
+/// Every error is a `Problem`, whatever the route, and any route can fail
+/// unexpectedly: no need to repeat either on every `#[utoipa::path]`.
+struct ProblemsAddon;
+
+impl Modify for ProblemsAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let operations = openapi
+            .paths
+            .paths
+            .values_mut()
+            .flat_map(|item| item.operations.values_mut());
+        for operation in operations {
+            let responses = &mut operation.responses.responses;
+            responses
+                .entry("500".to_string())
+                .or_insert_with(|| Response::new("Something failed on our side, details are only in the logs").into());
+
+            for (status, response) in responses.iter_mut() {
+                let RefOr::T(response) = response else {
+                    continue;
+                };
+                // the readiness check answers 503 with its own body
+                if status.starts_with(['4', '5']) && response.content.is_empty() {
+                    response.content.insert(
+                        "application/problem+json".to_string(),
+                        Content::new(Ref::from_schema_name("Problem")),
+                    );
+                }
+            }
         }
     }
+}
+
+pub async fn build_app(config: Config, pool: Pool<Postgres>) -> Result<App, anyhow::Error> {
+    let state = AppState::new(config, pool)?;
+    info!("state ok");
+    jobs::spawn_jobs(&state)?;
+
+    Ok(App { router: router(&state), state })
+}
+
+fn router(state: &AppState) -> Router {
+    // the latest first, since it's the one the UI opens
+    let docs = ApiVersion::ALL.iter().rev().fold(
+        SwaggerUi::new("/docs"),
+        |docs, &version| {
+            docs.url(Url::new(version.name(), version.schema_path()), api_doc(version))
+        },
+    );
+
+    let service = service_routes(RouteTable::new(state)).into_router();
+    let mut api = Router::new();
+    for &version in ApiVersion::ALL {
+        api = api.merge(versioned_routes(state, version));
+    }
+
+    let mut router = Router::new()
+        .merge(with_security_headers(state, RouteGroup::Service, service))
+        .merge(with_security_headers(state, RouteGroup::Docs, docs.into()))
+        .merge(with_security_headers(state, RouteGroup::Api, api))
+        // otherwise it's the one of the last group merged, with its headers
+        .fallback(|| async { StatusCode::NOT_FOUND })
+        // for whatever no group answered, such as the fallback
+        .layer(middleware::from_fn_with_state(
+            Arc::new(state.config.security_headers.for_unmatched()),
+            security_headers::security_headers,
+        ));
+    if let Some(cors) = security_headers::cors(&state.config.cors) {
+        // inside the others, so preflights get a request id and a log line too
+        router = router.layer(cors);
+    }
 
-    async fn build_json_schema() -> impl IntoResponse {
+    router
+        // the last one added runs first
+        .layer(middleware::from_fn(track_metrics))
+        .layer(middleware::from_fn(access_log))
+        .layer(middleware::from_fn(trace_requests))
+        .layer(middleware::from_fn_with_state(state.config.clone(), request_id))
+}
 
-        let str_docs = ApiDoc::openapi().to_pretty_json().unwrap();
-        let mut headers = HeaderMap::new();
-        headers.insert("Content-Type", "application/json".parse().unwrap());
+fn with_security_headers(
+    state: &AppState,
+    group: RouteGroup,
+    routes: Router,
+) -> Router {
+    let headers = Arc::new(state.config.security_headers.for_group(group));
+    routes.layer(middleware::from_fn_with_state(
+        headers,
+        security_headers::security_headers,
+    ))
+}
+
+/// Endpoints outside of the versions, for probes and scrapers.
+fn service_routes(table: RouteTable) -> RouteTable {
+    let table = main_routes(table);
+    let table = health_routes(table);
+    metrics_routes(table)
+}
 
-        (headers, str_docs)
-    
+/// The routes of `version`, without its prefix. The unversioned root only
+/// keeps the ones it had before versions: new endpoints are only in `/v1`.
+fn api_routes(table: RouteTable, version: ApiVersion) -> RouteTable {
+    let table = auth_routes(table);
+    match version {
+        ApiVersion::Unversioned => unversioned_me_routes(table),
+        ApiVersion::V1 => {
+            let table = me_routes(table);
+            let table = internal_routes(table);
+            admin_routes(table)
+        }
     }
+}
 
-    let app = Router::new()
-        .with_state(state.clone())
-        .route("/", get(routes::main::root::index))
-        .route("/json-schema", get(build_json_schema))
-        .merge(auth_routes(&state))
-        .merge(me_routes(&state));
-    app
+/// The routes of `version`, mounted under its prefix.
+fn versioned_routes(state: &AppState, version: ApiVersion) -> Router {
+    let routes = api_routes(RouteTable::new(state), version)
+        .into_router()
+        .layer(Extension(version));
+    let routes = match version.deprecated_at(&state.config.server) {
+        Some(since) => routes.layer(middleware::from_fn_with_state(
+            Deprecation {
+                since,
+                sunset: state.config.server.unversioned_sunset,
+            },
+            deprecation,
+        )),
+        None => routes,
+    };
+
+    match version.prefix() {
+        "" => routes,
+        prefix => Router::new().nest(prefix, routes),
+    }
 }
\ No newline at end of file
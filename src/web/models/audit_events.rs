@@ -0,0 +1,245 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{types::Json, PgExecutor};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::web::middlewares::request_id::RequestContext;
+
+/// What can end up in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum AuditKind {
+    #[serde(rename = "login.success")]
+    LoginSuccess,
+    /// wrong password, unknown email or disabled account, see `reason`
+    #[serde(rename = "login.failure")]
+    LoginFailure,
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+    #[serde(rename = "fcm_token.added")]
+    FcmTokenAdded,
+    /// logged out everywhere
+    #[serde(rename = "tokens.revoked")]
+    TokensRevoked,
+    /// set by an administrator, which revokes tokens too
+    #[serde(rename = "password.reset")]
+    PasswordReset,
+    #[serde(rename = "user.disabled")]
+    UserDisabled,
+    #[serde(rename = "user.enabled")]
+    UserEnabled,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+    #[serde(rename = "webhook.created")]
+    WebhookCreated,
+    #[serde(rename = "webhook.updated")]
+    WebhookUpdated,
+    #[serde(rename = "webhook.deleted")]
+    WebhookDeleted,
+    /// a delivery queued to be sent again
+    #[serde(rename = "webhook.redelivered")]
+    WebhookRedelivered,
+}
+
+impl AuditKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditKind::LoginSuccess => "login.success",
+            AuditKind::LoginFailure => "login.failure",
+            AuditKind::UserRegistered => "user.registered",
+            AuditKind::FcmTokenAdded => "fcm_token.added",
+            AuditKind::TokensRevoked => "tokens.revoked",
+            AuditKind::PasswordReset => "password.reset",
+            AuditKind::UserDisabled => "user.disabled",
+            AuditKind::UserEnabled => "user.enabled",
+            AuditKind::UserDeleted => "user.deleted",
+            AuditKind::WebhookCreated => "webhook.created",
+            AuditKind::WebhookUpdated => "webhook.updated",
+            AuditKind::WebhookDeleted => "webhook.deleted",
+            AuditKind::WebhookRedelivered => "webhook.redelivered",
+        }
+    }
+}
+
+/// Who did it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Actor {
+    User(String),
+    /// another PIENO service, by name
+    Service(String),
+    /// someone running the administration commands, by login name when
+    /// it's known
+    Admin(Option<String>),
+    /// somebody that couldn't tell who they were, like a failed login
+    Anonymous,
+}
+
+impl Actor {
+    fn columns(&self) -> (&'static str, Option<&str>) {
+        match self {
+            Actor::User(id) => ("user", Some(id)),
+            Actor::Service(name) => ("service", Some(name)),
+            Actor::Admin(name) => ("admin", name.as_deref()),
+            Actor::Anonymous => ("anonymous", None),
+        }
+    }
+}
+
+/// An event about to be appended.
+#[derive(Clone, Debug)]
+pub struct NewAuditEvent {
+    pub kind: AuditKind,
+    pub actor: Actor,
+    /// the account it's about, if any
+    pub user_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub details: Value,
+}
+
+impl NewAuditEvent {
+    pub fn new(kind: AuditKind, actor: Actor) -> NewAuditEvent {
+        NewAuditEvent {
+            kind,
+            actor,
+            user_id: None,
+            ip: None,
+            user_agent: None,
+            request_id: None,
+            details: json!({}),
+        }
+    }
+
+    pub fn user(mut self, user_id: &str) -> NewAuditEvent {
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> NewAuditEvent {
+        self.details = details;
+        self
+    }
+
+    /// Where the request that caused it came from.
+    pub fn from_request(mut self, context: &RequestContext) -> NewAuditEvent {
+        self.ip = context.client_ip.map(|ip| ip.to_string());
+        self.user_agent = context.user_agent.clone();
+        self.request_id = Some(context.request_id.clone());
+        self
+    }
+}
+
+/// An entry of the audit log.
+#[derive(Serialize, ToSchema, Debug, Clone, sqlx::FromRow)]
+pub struct AuditEvent {
+    pub id: i64,
+    /// one of the `AuditKind`s, e.g. `login.failure`
+    #[schema(example = "login.failure")]
+    pub kind: String,
+    /// `user`, `service`, `admin` or `anonymous`
+    pub actor_type: String,
+    pub actor_id: Option<String>,
+    /// the account it's about
+    pub user_id: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    #[schema(value_type = Object)]
+    pub details: Json<Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Which events to look for: every condition that's set has to hold.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    pub user_id: Option<String>,
+    pub actor_id: Option<String>,
+    pub kind: Option<AuditKind>,
+    pub ip: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// only events older than this one, to page through them
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+impl AuditFilter {
+    pub const DEFAULT_LIMIT: i64 = 50;
+
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        let equal = |filter: &Option<String>, value: &Option<String>| {
+            filter.is_none() || filter == value
+        };
+
+        equal(&self.user_id, &event.user_id)
+            && equal(&self.actor_id, &event.actor_id)
+            && equal(&self.ip, &event.ip)
+            && self.kind.iter().all(|kind| kind.as_str() == event.kind)
+            && self.since.iter().all(|since| event.created_at >= *since)
+            && self.until.iter().all(|until| event.created_at < *until)
+            && self.before.iter().all(|before| event.id < *before)
+    }
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn append(
+    e: impl PgExecutor<'_>,
+    event: &NewAuditEvent,
+) -> Result<(), sqlx_core::Error> {
+    let (actor_type, actor_id) = event.actor.columns();
+    sqlx::query(
+        "
+            insert into audit_events (
+                kind, actor_type, actor_id, user_id, ip, user_agent,
+                request_id, details
+            )
+            values ($1, $2, $3, $4, $5, $6, $7, $8)
+        ",
+    )
+    .bind(event.kind.as_str())
+    .bind(actor_type)
+    .bind(actor_id)
+    .bind(&event.user_id)
+    .bind(&event.ip)
+    .bind(&event.user_agent)
+    .bind(&event.request_id)
+    .bind(Json(&event.details))
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+/// Newest first.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn list(
+    e: impl PgExecutor<'_>,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditEvent>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            select * from audit_events
+            where ($1::text is null or user_id = $1)
+            and ($2::text is null or actor_id = $2)
+            and ($3::text is null or kind = $3)
+            and ($4::text is null or ip = $4)
+            and ($5::timestamptz is null or created_at >= $5)
+            and ($6::timestamptz is null or created_at < $6)
+            and ($7::bigint is null or id < $7)
+            order by id desc
+            limit $8
+        ",
+    )
+    .bind(&filter.user_id)
+    .bind(&filter.actor_id)
+    .bind(filter.kind.map(AuditKind::as_str))
+    .bind(&filter.ip)
+    .bind(filter.since)
+    .bind(filter.until)
+    .bind(filter.before)
+    .bind(filter.limit)
+    .fetch_all(e)
+    .await
+}
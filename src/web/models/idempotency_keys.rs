@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use sqlx::PgExecutor;
+use tracing::instrument;
+
+/// A response kept for the retries of the call that got it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What happened when trying to claim a key for a new call.
+#[derive(Debug, PartialEq)]
+pub enum Reservation {
+    /// the key is ours: handle the call, then complete or release it
+    Reserved,
+    /// a call with the same key is still being handled
+    InProgress,
+    /// the key was used for a different call
+    Mismatch,
+    /// the call was already handled, this is its response
+    Completed(StoredResponse),
+}
+
+#[derive(sqlx::FromRow)]
+struct KeyRow {
+    request_hash: String,
+    status: Option<i16>,
+    content_type: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+/// Claims `key` in `scope` for the call hashing to `request_hash`. Keys
+/// older than `ttl` are as good as new, and so are the ones whose first
+/// call never finished within `abandoned_after`, e.g. because we crashed.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn reserve(
+    e: impl PgExecutor<'_> + Copy,
+    scope: &str,
+    key: &str,
+    request_hash: &str,
+    ttl: Duration,
+    abandoned_after: Duration,
+) -> Result<Reservation, sqlx_core::Error> {
+    let reserved = sqlx::query(
+        "
+            insert into idempotency_keys (scope, key, request_hash)
+            values ($1, $2, $3)
+            on conflict (scope, key) do update set
+                request_hash = excluded.request_hash,
+                status = null,
+                content_type = null,
+                body = null,
+                created_at = now()
+            where idempotency_keys.created_at < now() - make_interval(secs => $4)
+                or (
+                    idempotency_keys.status is null
+                    and idempotency_keys.created_at < now() - make_interval(secs => $5)
+                )
+        ",
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(request_hash)
+    .bind(ttl.as_secs_f64())
+    .bind(abandoned_after.as_secs_f64())
+    .execute(e)
+    .await?
+    .rows_affected()
+        > 0;
+    if reserved {
+        return Ok(Reservation::Reserved);
+    }
+
+    let row: Option<KeyRow> = sqlx::query_as(
+        "
+            select request_hash, status, content_type, body
+            from idempotency_keys where scope = $1 and key = $2
+        ",
+    )
+    .bind(scope)
+    .bind(key)
+    .fetch_optional(e)
+    .await?;
+
+    Ok(match row {
+        Some(row) if row.request_hash != request_hash => Reservation::Mismatch,
+        Some(KeyRow {
+            status: Some(status),
+            content_type,
+            body,
+            ..
+        }) => Reservation::Completed(StoredResponse {
+            status: status as u16,
+            content_type,
+            body: body.unwrap_or_default(),
+        }),
+        // or purged in the meantime: the caller can just try again
+        _ => Reservation::InProgress,
+    })
+}
+
+/// Keeps `response` for the retries of the call that reserved `key`.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn complete(
+    e: impl PgExecutor<'_>,
+    scope: &str,
+    key: &str,
+    response: &StoredResponse,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query(
+        "
+            update idempotency_keys set status = $3, content_type = $4, body = $5
+            where scope = $1 and key = $2
+        ",
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(response.status as i16)
+    .bind(&response.content_type)
+    .bind(&response.body)
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+/// Gives `key` back, so the next retry is handled from scratch.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn release(
+    e: impl PgExecutor<'_>,
+    scope: &str,
+    key: &str,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query("delete from idempotency_keys where scope = $1 and key = $2")
+        .bind(scope)
+        .bind(key)
+        .execute(e)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes the keys older than `ttl`, returning how many there were.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn purge(
+    e: impl PgExecutor<'_>,
+    ttl: Duration,
+) -> Result<u64, sqlx_core::Error> {
+    let result = sqlx::query(
+        "
+            delete from idempotency_keys
+            where created_at < now() - make_interval(secs => $1)
+        ",
+    )
+    .bind(ttl.as_secs_f64())
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -1 +1,7 @@
-pub mod users;
\ No newline at end of file
+pub mod audit_events;
+pub mod idempotency_keys;
+pub mod notification_settings;
+pub mod notifications;
+pub mod outbox;
+pub mod users;
+pub mod webhooks;
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::NaiveTime;
+use sqlx::{types::Json, PgExecutor};
+use tracing::instrument;
+
+use crate::web::dto::me::notification_settings::{
+    ChannelSettings, NotificationSettings, QuietHours,
+};
+
+#[derive(sqlx::FromRow)]
+struct SettingsRow {
+    user_id: String,
+    channels: Json<HashMap<String, ChannelSettings>>,
+    muted_senders: Vec<String>,
+    muted_cars: Vec<String>,
+    quiet_hours_start: Option<NaiveTime>,
+    quiet_hours_end: Option<NaiveTime>,
+}
+
+impl From<SettingsRow> for NotificationSettings {
+    fn from(row: SettingsRow) -> Self {
+        let quiet_hours = match (row.quiet_hours_start, row.quiet_hours_end) {
+            (Some(start), Some(end)) => Some(QuietHours { start, end }),
+            _ => None,
+        };
+
+        NotificationSettings {
+            channels: row.channels.0,
+            muted_senders: row.muted_senders,
+            muted_cars: row.muted_cars,
+            quiet_hours,
+        }
+    }
+}
+
+/// Settings of `user_id`, or the defaults if they never changed them.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn get(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+) -> Result<NotificationSettings, sqlx_core::Error> {
+    let row: Option<SettingsRow> = sqlx::query_as(
+        "
+            select * from notification_settings where user_id = $1
+        ",
+    )
+    .bind(user_id)
+    .fetch_optional(e)
+    .await?;
+
+    Ok(row.map(NotificationSettings::from).unwrap_or_default())
+}
+
+/// Settings of every user in `user_ids` that changed them; everybody else
+/// uses the defaults.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn for_users(
+    e: impl PgExecutor<'_>,
+    user_ids: &[String],
+) -> Result<HashMap<String, NotificationSettings>, sqlx_core::Error> {
+    let rows: Vec<SettingsRow> = sqlx::query_as(
+        "
+            select * from notification_settings where user_id = any($1)
+        ",
+    )
+    .bind(user_ids)
+    .fetch_all(e)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.user_id.clone(), NotificationSettings::from(row)))
+        .collect())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn save(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    settings: &NotificationSettings,
+) -> Result<(), sqlx_core::Error> {
+    let quiet_hours = settings.quiet_hours.as_ref();
+    sqlx::query(
+        "
+            insert into notification_settings (
+                user_id, channels, muted_senders, muted_cars,
+                quiet_hours_start, quiet_hours_end, updated_at
+            ) values ($1, $2, $3, $4, $5, $6, now())
+            on conflict (user_id) do update set
+                channels = excluded.channels,
+                muted_senders = excluded.muted_senders,
+                muted_cars = excluded.muted_cars,
+                quiet_hours_start = excluded.quiet_hours_start,
+                quiet_hours_end = excluded.quiet_hours_end,
+                updated_at = excluded.updated_at
+        ",
+    )
+    .bind(user_id)
+    .bind(Json(&settings.channels))
+    .bind(&settings.muted_senders)
+    .bind(&settings.muted_cars)
+    .bind(quiet_hours.map(|q| q.start))
+    .bind(quiet_hours.map(|q| q.end))
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn hold_push(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    title: &str,
+    body: &str,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query(
+        "
+            insert into held_pushes (user_id, title, body) values ($1, $2, $3)
+        ",
+    )
+    .bind(user_id)
+    .bind(title)
+    .bind(body)
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct HeldRow {
+    #[sqlx(flatten)]
+    settings: SettingsRow,
+    time_zone: String,
+}
+
+/// Users that have pushes on hold, along with their settings and the time
+/// zone of their profile.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn users_with_held_pushes(
+    e: impl PgExecutor<'_>,
+) -> Result<Vec<(String, NotificationSettings, String)>, sqlx_core::Error> {
+    let rows: Vec<HeldRow> = sqlx::query_as(
+        "
+            select
+            h.user_id,
+            coalesce(s.channels, '{}') channels,
+            coalesce(s.muted_senders, '{}') muted_senders,
+            coalesce(s.muted_cars, '{}') muted_cars,
+            s.quiet_hours_start, s.quiet_hours_end,
+            u.time_zone
+            from (select distinct user_id from held_pushes) h
+            join users u on u.id = h.user_id
+            left join notification_settings s on s.user_id = h.user_id
+        ",
+    )
+    .fetch_all(e)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            (
+                row.settings.user_id.clone(),
+                NotificationSettings::from(row.settings),
+                row.time_zone,
+            )
+        })
+        .collect())
+}
+
+/// Leases every push held for `user_id` that no one else is sending, for
+/// `lease`: returns their `(id, title, body)`, oldest first. They're deleted
+/// once the digest is out, see [`delete_held_pushes`], and whoever leased
+/// them is presumed dead when the lease expires.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn lease_held_pushes(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    lease: Duration,
+) -> Result<Vec<(i64, String, String)>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            with leased as (
+                update held_pushes set
+                locked_until = now() + make_interval(secs => $2)
+                where id in (
+                    select id from held_pushes
+                    where user_id = $1
+                    and (locked_until is null or locked_until <= now())
+                    for update skip locked
+                )
+                returning id, title, body
+            )
+            select id, title, body from leased order by id
+        ",
+    )
+    .bind(user_id)
+    .bind(lease.as_secs_f64())
+    .fetch_all(e)
+    .await
+}
+
+/// Drops the held pushes whose digest is out.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn delete_held_pushes(
+    e: impl PgExecutor<'_>,
+    ids: &[i64],
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query("delete from held_pushes where id = any($1)")
+        .bind(ids)
+        .execute(e)
+        .await?;
+
+    Ok(())
+}
+
+/// Gives up the lease on held pushes whose digest couldn't be sent, so that
+/// the next round tries again.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn release_held_pushes(
+    e: impl PgExecutor<'_>,
+    ids: &[i64],
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query("update held_pushes set locked_until = null where id = any($1)")
+        .bind(ids)
+        .execute(e)
+        .await?;
+
+    Ok(())
+}
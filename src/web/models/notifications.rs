@@ -0,0 +1,427 @@
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{types::Json, PgExecutor};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::web::{
+    dto::me::notifications::{Notification, NotificationRow},
+    jobs::retention::{RetentionAction, RetentionRule},
+};
+
+// notifications are always read together with whoever sent them
+const NOTIFICATION_SELECT: &str = "
+    select
+    n.id, n.to_user, n.data,
+    n.created_at, n.read_at,
+    s.id sender_id, s.name sender_name, s.surname sender_surname,
+    s.propic_url sender_propic_url
+    from notifications n
+    left join users s on s.id = n.data->>'owner'
+";
+
+// legacy payloads carry no type at all, and they're all refuel notifications
+const NOTIFICATION_TYPE: &str = "coalesce(data->>'type', 'refuel')";
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug, Clone)]
+pub struct CreatedNotification {
+    pub id: String,
+    pub to_user: String,
+}
+
+/// What marking notifications as read came to.
+pub struct MarkedRead {
+    pub marked: u64,
+    /// what's left unread, counted right after marking
+    pub unread: i64,
+}
+
+/// What storing a batch came to: the new notifications, and the ones a
+/// previous call with the same idempotency key already stored.
+pub struct InsertedBatch {
+    pub created: Vec<CreatedNotification>,
+    pub existing: Vec<CreatedNotification>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct StoredNotification {
+    pub id: String,
+    pub to_user: String,
+    pub same_payload: bool,
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn list(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    archived: bool,
+) -> Result<Vec<Notification>, sqlx_core::Error> {
+    let results: Vec<NotificationRow> = sqlx::query_as(&format!(
+        "
+            {NOTIFICATION_SELECT}
+            where n.to_user = $1 and (n.archived_at is not null) = $2
+            order by n.created_at desc
+        "
+    ))
+    .bind(user_id)
+    .bind(archived)
+    .fetch_all(e)
+    .await?;
+
+    Ok(results.into_iter().map(Notification::from).collect())
+}
+
+/// A notification that hasn't been archived.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn get(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    notification_id: &str,
+) -> Result<Option<Notification>, sqlx_core::Error> {
+    let result: Option<NotificationRow> = sqlx::query_as(&format!(
+        "
+            {NOTIFICATION_SELECT}
+            where n.id = $1 and n.to_user = $2 and n.archived_at is null
+        "
+    ))
+    .bind(notification_id)
+    .bind(user_id)
+    .fetch_optional(e)
+    .await?;
+
+    Ok(result.map(Notification::from))
+}
+
+/// Active notifications created after `after_id`, oldest first. Used to
+/// resume streams, so it's empty when `after_id` doesn't exist anymore.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn list_after(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    after_id: &str,
+) -> Result<Vec<Notification>, sqlx_core::Error> {
+    let results: Vec<NotificationRow> = sqlx::query_as(&format!(
+        "
+            {NOTIFICATION_SELECT}
+            where n.to_user = $1 and n.archived_at is null
+            and (n.created_at, n.id) > (
+                select created_at, id from notifications
+                where id = $2 and to_user = $1
+            )
+            order by n.created_at, n.id
+        "
+    ))
+    .bind(user_id)
+    .bind(after_id)
+    .fetch_all(e)
+    .await?;
+
+    Ok(results.into_iter().map(Notification::from).collect())
+}
+
+/// Active notifications created at or after `since`, oldest first, for
+/// streams that lost track of what they sent before sending anything.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn list_since(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    since: DateTime<Utc>,
+) -> Result<Vec<Notification>, sqlx_core::Error> {
+    let results: Vec<NotificationRow> = sqlx::query_as(&format!(
+        "
+            {NOTIFICATION_SELECT}
+            where n.to_user = $1 and n.archived_at is null and n.created_at >= $2
+            order by n.created_at, n.id
+        "
+    ))
+    .bind(user_id)
+    .bind(since)
+    .fetch_all(e)
+    .await?;
+
+    Ok(results.into_iter().map(Notification::from).collect())
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn count_unread(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+) -> Result<i64, sqlx_core::Error> {
+    // backed by the partial index on unread rows, so this stays cheap
+    let (unread,): (i64,) = sqlx::query_as(
+        "
+            select count(*) from notifications
+            where to_user = $1 and read_at is null and archived_at is null
+        ",
+    )
+    .bind(user_id)
+    .fetch_one(e)
+    .await?;
+
+    Ok(unread)
+}
+
+/// Marks a single notification as read, keeping the original `read_at`
+/// if it was already read. Returns whether this call flipped it: when it
+/// didn't, the notification was either read already or not theirs at all,
+/// see [`exists`].
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_read(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    notification_id: &str,
+) -> Result<bool, sqlx_core::Error> {
+    // `read_at is null` makes concurrent calls agree on which one flipped it
+    let result = sqlx::query(
+        "
+            update notifications set read_at = now()
+            where id = $1 and to_user = $2 and read_at is null
+        ",
+    )
+    .bind(notification_id)
+    .bind(user_id)
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks every unread notification created up to (and including) the
+/// `until` notification as read, or all of them when there's no cursor.
+/// Returns how many rows were actually flipped.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_read_until(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    until: Option<&str>,
+) -> Result<u64, sqlx_core::Error> {
+    // `read_at is null` makes concurrent calls touch disjoint rows,
+    // so the same notification is never counted twice
+    let result = sqlx::query(
+        "
+            update notifications set read_at = now()
+            where to_user = $1 and read_at is null
+            and (
+                $2::text is null or created_at <= (
+                    select created_at from notifications
+                    where id = $2 and to_user = $1
+                )
+            )
+        ",
+    )
+    .bind(user_id)
+    .bind(until)
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Returns `false` when the notification doesn't exist or belongs to
+/// someone else.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn delete(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    notification_id: &str,
+) -> Result<bool, sqlx_core::Error> {
+    let result = sqlx::query(
+        "
+            delete from notifications where id = $1 and to_user = $2
+        ",
+    )
+    .bind(notification_id)
+    .bind(user_id)
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Archives (or restores, when `archived` is `false`) a notification.
+/// Archived notifications are hidden from the default listing and from
+/// the unread counter. Returns `false` when the notification doesn't
+/// exist or belongs to someone else.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn set_archived(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    notification_id: &str,
+    archived: bool,
+) -> Result<bool, sqlx_core::Error> {
+    let result = sqlx::query(
+        "
+            update notifications set archived_at = case
+                when $3 then coalesce(archived_at, now())
+                else null
+            end
+            where id = $1 and to_user = $2
+        ",
+    )
+    .bind(notification_id)
+    .bind(user_id)
+    .bind(archived)
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn exists(
+    e: impl PgExecutor<'_>,
+    user_id: &str,
+    notification_id: &str,
+) -> Result<bool, sqlx_core::Error> {
+    let (exists,): (bool,) = sqlx::query_as(
+        "
+            select exists(
+                select 1 from notifications where id = $1 and to_user = $2
+            )
+        ",
+    )
+    .bind(notification_id)
+    .bind(user_id)
+    .fetch_one(e)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Notifications already stored with `idempotency_key`, telling whether
+/// they carry `payload` too.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn find_by_idempotency_key(
+    e: impl PgExecutor<'_>,
+    idempotency_key: &str,
+    payload: &Value,
+) -> Result<Vec<StoredNotification>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            select id, to_user, data = $2 same_payload
+            from notifications where idempotency_key = $1
+        ",
+    )
+    .bind(idempotency_key)
+    .bind(Json(payload))
+    .fetch_all(e)
+    .await
+}
+
+/// Makes whoever else uses `idempotency_key` wait for the end of this
+/// transaction, the unique index alone would have them skip every
+/// recipient without noticing what was stored.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn lock_idempotency_key(
+    e: impl PgExecutor<'_>,
+    idempotency_key: &str,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query("select pg_advisory_xact_lock(hashtext($1))")
+        .bind(idempotency_key)
+        .execute(e)
+        .await?;
+
+    Ok(())
+}
+
+/// Stores the same payload for every recipient in a single statement, the
+/// `archived` ones straight into the archive. Recipients that don't exist
+/// are skipped, and so are the ones that already got a notification with
+/// the same `idempotency_key`.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn insert_batch(
+    e: impl PgExecutor<'_>,
+    recipients: &[String],
+    archived: &[String],
+    payload: &Value,
+    idempotency_key: Option<&str>,
+) -> Result<Vec<CreatedNotification>, sqlx_core::Error> {
+    let ids: Vec<String> = recipients.iter().map(|_| nanoid!(32)).collect();
+
+    sqlx::query_as(
+        "
+            insert into notifications (
+                id, to_user, data, idempotency_key, created_at, archived_at
+            )
+            select
+            r.id, r.to_user, $3, $4, now(),
+            case when r.to_user = any($5) then now() end
+            from unnest($1::text[], $2::text[]) as r(id, to_user)
+            join users u on u.id = r.to_user
+            on conflict (idempotency_key, to_user)
+                where idempotency_key is not null do nothing
+            returning id, to_user
+        ",
+    )
+    .bind(&ids)
+    .bind(recipients)
+    .bind(Json(payload))
+    .bind(idempotency_key)
+    .bind(archived)
+    .fetch_all(e)
+    .await
+}
+
+/// FCM tokens of every user in `user_ids`, as `(user_id, token)` pairs.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn fcm_tokens(
+    e: impl PgExecutor<'_>,
+    user_ids: &[String],
+) -> Result<Vec<(String, String)>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            select user_id, token from fcm_tokens where user_id = any($1)
+        ",
+    )
+    .bind(user_ids)
+    .fetch_all(e)
+    .await
+}
+
+/// Deletes or archives, as `rule` says, up to `limit` notifications older
+/// than its days: of type `kind`, or of every type but the `overridden` ones
+/// when `kind` is `None`. Returns how many of each type.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn prune(
+    e: impl PgExecutor<'_>,
+    kind: Option<&str>,
+    overridden: &[&str],
+    rule: &RetentionRule,
+    limit: i64,
+) -> Result<Vec<(String, i64)>, sqlx_core::Error> {
+    let (pruned, archived) = match rule.action {
+        RetentionAction::Delete => ("delete from notifications", ""),
+        RetentionAction::Archive => (
+            "update notifications set archived_at = now()",
+            "and archived_at is null",
+        ),
+    };
+    let query = format!(
+        "
+            with pruned as (
+                {pruned} where id in (
+                    select id from notifications
+                    where created_at < now() - make_interval(days => $1)
+                    {archived}
+                    and case
+                        when $2::text is null then not ({NOTIFICATION_TYPE} = any($3))
+                        else {NOTIFICATION_TYPE} = $2
+                    end
+                    limit $4
+                )
+                returning {NOTIFICATION_TYPE} as kind
+            )
+            select kind, count(*) from pruned group by kind
+        "
+    );
+
+    sqlx::query_as(&query)
+        .bind(rule.days as i32)
+        .bind(kind)
+        .bind(overridden)
+        .bind(limit)
+        .fetch_all(e)
+        .await
+}
@@ -0,0 +1,260 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{types::Json, PgExecutor};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::web::models::users::{User, UserModel};
+
+/// What the rest of PIENO gets told about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum OutboxKind {
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+    /// the locale or time zone changed
+    #[serde(rename = "user.updated")]
+    UserUpdated,
+    #[serde(rename = "user.deleted")]
+    UserDeleted,
+    #[serde(rename = "fcm_token.added")]
+    FcmTokenAdded,
+}
+
+impl OutboxKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OutboxKind::UserRegistered => "user.registered",
+            OutboxKind::UserUpdated => "user.updated",
+            OutboxKind::UserDeleted => "user.deleted",
+            OutboxKind::FcmTokenAdded => "fcm_token.added",
+        }
+    }
+}
+
+/// An event to write along with the change it's about.
+#[derive(Clone, Debug)]
+pub struct NewOutboxEvent {
+    pub kind: OutboxKind,
+    pub user_id: String,
+    pub payload: Value,
+}
+
+impl NewOutboxEvent {
+    pub fn user_registered(
+        user: UserModel,
+    ) -> serde_json::Result<NewOutboxEvent> {
+        NewOutboxEvent::user(OutboxKind::UserRegistered, user, false)
+    }
+
+    pub fn user_updated(user: User) -> serde_json::Result<NewOutboxEvent> {
+        let disabled = user.disabled_at.is_some();
+        NewOutboxEvent::user(OutboxKind::UserUpdated, user.into(), disabled)
+    }
+
+    pub fn user_deleted(user_id: &str) -> NewOutboxEvent {
+        NewOutboxEvent {
+            kind: OutboxKind::UserDeleted,
+            user_id: user_id.to_string(),
+            payload: json!({"id": user_id}),
+        }
+    }
+
+    /// Only the fingerprint of the token goes out: whoever holds the token
+    /// itself can push to the device.
+    pub fn fcm_token_added(user_id: &str, token: &str) -> NewOutboxEvent {
+        NewOutboxEvent {
+            kind: OutboxKind::FcmTokenAdded,
+            user_id: user_id.to_string(),
+            payload: json!({
+                "user_id": user_id,
+                "token_sha256": hex::encode(Sha256::digest(token.as_bytes())),
+            }),
+        }
+    }
+
+    // the public profile, never the password hash. Failing to encode it
+    // fails the write it goes along with, rather than publishing nothing.
+    fn user(
+        kind: OutboxKind,
+        user: UserModel,
+        disabled: bool,
+    ) -> serde_json::Result<NewOutboxEvent> {
+        let user_id = user.id.clone();
+        let mut payload = serde_json::to_value(user)?;
+        payload["disabled"] = json!(disabled);
+
+        Ok(NewOutboxEvent {
+            kind,
+            user_id,
+            payload,
+        })
+    }
+}
+
+/// An event waiting to be published.
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct OutboxEvent {
+    pub id: i64,
+    pub kind: String,
+    pub user_id: String,
+    pub payload: Json<Value>,
+    pub created_at: DateTime<Utc>,
+    pub attempts: i32,
+}
+
+impl OutboxEvent {
+    /// What gets published. Delivery is at least once: consumers tell
+    /// duplicates apart by `id`.
+    pub fn envelope(&self) -> Value {
+        json!({
+            "id": self.id,
+            "type": self.kind,
+            "user_id": self.user_id,
+            "occurred_at": self.created_at,
+            "data": self.payload.0,
+        })
+    }
+}
+
+/// Also queues a delivery for every webhook subscribed to the event.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn enqueue(
+    e: impl PgExecutor<'_>,
+    event: &NewOutboxEvent,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query(
+        "
+            with event as (
+                insert into outbox_events (kind, user_id, payload)
+                values ($1, $2, $3)
+                returning id, kind, user_id, payload, created_at
+            )
+            insert into webhook_deliveries (
+                subscription_id, event_id, kind, user_id, payload, occurred_at
+            )
+            select s.id, event.id, event.kind, event.user_id, event.payload,
+                event.created_at
+            from event join webhook_subscriptions s
+            on s.disabled_at is null
+            and (cardinality(s.events) = 0 or event.kind = any(s.events))
+        ",
+    )
+    .bind(event.kind.as_str())
+    .bind(&event.user_id)
+    .bind(Json(&event.payload))
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+/// Leases up to `limit` events that are due, oldest first, for `lease`:
+/// until then, or until they're marked, no other relay takes them. Only the
+/// oldest pending event of each user is taken, so theirs are published in
+/// order even when one keeps failing or another relay is running.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn claim(
+    e: impl PgExecutor<'_>,
+    limit: i64,
+    lease: Duration,
+) -> Result<Vec<OutboxEvent>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            with claimed as (
+                update outbox_events set
+                locked_until = now() + make_interval(secs => $2)
+                where id in (
+                    select id from outbox_events e
+                    where published_at is null
+                    and next_attempt_at <= now()
+                    and (locked_until is null or locked_until <= now())
+                    and not exists (
+                        select 1 from outbox_events earlier
+                        where earlier.user_id = e.user_id
+                        and earlier.published_at is null
+                        and earlier.id < e.id
+                    )
+                    order by id
+                    limit $1
+                    for update skip locked
+                )
+                returning id, kind, user_id, payload, created_at, attempts
+            )
+            select * from claimed order by id
+        ",
+    )
+    .bind(limit)
+    .bind(lease.as_secs_f64())
+    .fetch_all(e)
+    .await
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_published(
+    e: impl PgExecutor<'_>,
+    id: i64,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query(
+        "
+            update outbox_events set
+            published_at = now(),
+            locked_until = null
+            where id = $1
+        ",
+    )
+    .bind(id)
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+/// Puts the event back for another attempt in `retry_in`.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn mark_failed(
+    e: impl PgExecutor<'_>,
+    id: i64,
+    error: &str,
+    retry_in: Duration,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query(
+        "
+            update outbox_events set
+            attempts = attempts + 1,
+            last_error = $2,
+            next_attempt_at = now() + make_interval(secs => $3),
+            locked_until = null
+            where id = $1
+        ",
+    )
+    .bind(id)
+    .bind(error)
+    .bind(retry_in.as_secs_f64())
+    .execute(e)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the events published more than `older_than` ago.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn purge_published(
+    e: impl PgExecutor<'_>,
+    older_than: Duration,
+) -> Result<u64, sqlx_core::Error> {
+    let result = sqlx::query(
+        "
+            delete from outbox_events
+            where published_at < now() - make_interval(secs => $1)
+        ",
+    )
+    .bind(older_than.as_secs_f64())
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected())
+}
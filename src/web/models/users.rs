@@ -1,10 +1,13 @@
+use chrono::{DateTime, Utc};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
-use sqlx::{PgExecutor, Row};
+use sqlx::PgExecutor;
+use tracing::instrument;
 use utoipa::ToSchema;
 
-use crate::web::dto::me::notifications::Notification;
+use crate::web::locale::Locale;
 
+#[derive(sqlx::FromRow, Clone)]
 pub struct User {
     pub email: String,
     pub name: String,
@@ -12,6 +15,23 @@ pub struct User {
     pub id: String,
     pub password: String,
     pub propic_url: Option<String>,
+    pub locale: String,
+    pub time_zone: String,
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// bumped every time the user's tokens are revoked, only tokens carrying
+    /// the current one are accepted
+    pub token_generation: i32,
+}
+
+pub struct NewUser {
+    /// see `User::new_id`
+    pub id: String,
+    pub email: String,
+    pub name: String,
+    pub surname: String,
+    pub password_hash: String,
+    pub locale: Locale,
+    pub time_zone: String,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -21,128 +41,257 @@ pub struct UserModel {
     pub surname: String,
     pub id: String,
     pub propic_url: Option<String>,
+    pub locale: Locale,
+    /// IANA time zone, e.g. `Europe/Rome`
+    pub time_zone: String,
+}
+
+impl From<User> for UserModel {
+    fn from(user: User) -> Self {
+        UserModel {
+            locale: Locale::from_code(&user.locale),
+            email: user.email,
+            name: user.name,
+            surname: user.surname,
+            id: user.id,
+            propic_url: user.propic_url,
+            time_zone: user.time_zone,
+        }
+    }
+}
+
+/// Where and how to reach a user when delivering notifications.
+#[derive(sqlx::FromRow)]
+pub struct RecipientModel {
+    pub id: String,
+    pub email: String,
+    pub locale: String,
+    pub time_zone: String,
+}
+
+/// What anybody else is allowed to see about a user.
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct PublicUserModel {
+    pub id: String,
+    pub name: String,
+    pub surname: String,
+    pub propic_url: Option<String>,
 }
 
 impl User {
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
     pub async fn from_email(
         e: impl PgExecutor<'_>,
         id: &str,
     ) -> Result<Option<User>, sqlx_core::Error> {
-        let query = sqlx::query(
+        sqlx::query_as(
             "
                 select * from users where email = $1
             ",
         )
-        .bind(&id)
+        .bind(id)
         .fetch_optional(e)
+        .await
+    }
+
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn add_fcm_token(
+        e: impl PgExecutor<'_>,
+        user_id: &str,
+        token: &str,
+    ) -> Result<(), sqlx_core::Error> {
+        sqlx::query(
+            "
+                insert into fcm_tokens values ($1, $2)
+            ",
+        )
+        .bind(token)
+        .bind(user_id)
+        .execute(e)
         .await?;
 
-        if let Some(user) = query {
-            Ok(Some(User {
-                id: user.get("id"),
-                name: user.get("name"),
-                surname: user.get("surname"),
-                email: user.get("email"),
-                password: user.get("password"),
-                propic_url: user.get("propic_url"),
-            }))
-        } else {
-            Ok(None)
-        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn from_id(
+        e: impl PgExecutor<'_>,
+        id: &str,
+    ) -> Result<Option<User>, sqlx_core::Error> {
+        sqlx::query_as(
+            "
+                select * from users where id = $1
+            ",
+        )
+        .bind(id)
+        .fetch_optional(e)
+        .await
     }
 
-    pub async fn get_notifications(
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn recipients(
+        e: impl PgExecutor<'_>,
+        ids: &[String],
+    ) -> Result<Vec<RecipientModel>, sqlx_core::Error> {
+        sqlx::query_as(
+            "
+                select id, email, locale, time_zone from users where id = any($1)
+            ",
+        )
+        .bind(ids)
+        .fetch_all(e)
+        .await
+    }
+
+    /// Updates the locale and/or time zone, leaving out what's `None`.
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn update_preferences(
+        &mut self,
+        e: impl PgExecutor<'_>,
+        locale: Option<Locale>,
+        time_zone: Option<&str>,
+    ) -> Result<(), sqlx_core::Error> {
+        let locale = locale.map(|l| l.code()).unwrap_or(&self.locale).to_string();
+        let time_zone = time_zone.unwrap_or(&self.time_zone).to_string();
+        sqlx::query(
+            "
+                update users set locale = $2, time_zone = $3 where id = $1
+            ",
+        )
+        .bind(&self.id)
+        .bind(&locale)
+        .bind(&time_zone)
+        .execute(e)
+        .await?;
+
+        self.locale = locale;
+        self.time_zone = time_zone;
+        Ok(())
+    }
+
+    /// Whether a token of the given generation still stands: any revocation
+    /// since it was issued bumped the user's one.
+    pub fn accepts_token_of_generation(&self, generation: i32) -> bool {
+        generation == self.token_generation
+    }
+
+    /// Changes the password, and logs the user out everywhere.
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn set_password(
         &self,
         e: impl PgExecutor<'_>,
-    ) -> Result<Vec<Notification>, sqlx_core::Error> {
-        let results: Vec<Notification> = sqlx::query_as(
+        password_hash: &str,
+    ) -> Result<(), sqlx_core::Error> {
+        sqlx::query(
             "
-            	select
-             	id, to_user, data, to_char(created_at, 'dd-mm-yyyy HH24:MI:SS (utc)') created_at
-             	from notifications where to_user = $1
-             ",
+                update users
+                set password = $2, token_generation = token_generation + 1
+                where id = $1
+            ",
         )
         .bind(&self.id)
-        .fetch_all(e)
+        .bind(password_hash)
+        .execute(e)
         .await?;
 
-        Ok(results)
+        Ok(())
     }
 
-    pub async fn add_fcm_token(
+    /// Disabling a user also revokes their tokens, so enabling them back
+    /// doesn't bring the old ones back to life.
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn set_disabled(
         &self,
         e: impl PgExecutor<'_>,
-        token: &str,
+        disabled: bool,
     ) -> Result<(), sqlx_core::Error> {
         sqlx::query(
             "
-                insert into fcm_tokens values ($1, $2)
+                update users set
+                disabled_at = case when $2 then coalesce(disabled_at, now()) end,
+                token_generation = case
+                    when $2 then token_generation + 1
+                    else token_generation
+                end
+                where id = $1
             ",
         )
-        .bind(&token)
         .bind(&self.id)
+        .bind(disabled)
         .execute(e)
         .await?;
 
         Ok(())
     }
 
-    pub async fn from_id(
+    /// Deletes the user along with their tokens, notifications and
+    /// settings. The audit log keeps what happened to them.
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn delete(&self, e: impl PgExecutor<'_>) -> Result<(), sqlx_core::Error> {
+        sqlx::query("delete from users where id = $1")
+            .bind(&self.id)
+            .execute(e)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Rejects every token issued so far to `user_id`, or to everybody when
+    /// it's `None`. Returns how many users were affected.
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
+    pub async fn revoke_tokens(
         e: impl PgExecutor<'_>,
-        id: &str,
-    ) -> Result<Option<User>, sqlx_core::Error> {
-        let query = sqlx::query(
+        user_id: Option<&str>,
+    ) -> Result<u64, sqlx_core::Error> {
+        let result = sqlx::query(
             "
-                select * from users where id = $1
+                update users set token_generation = token_generation + 1
+                where $1::text is null or id = $1
             ",
         )
-        .bind(&id)
-        .fetch_optional(e)
+        .bind(user_id)
+        .execute(e)
         .await?;
 
-        if let Some(user) = query {
-            Ok(Some(User {
-                id: user.get("id"),
-                name: user.get("name"),
-                surname: user.get("surname"),
-                email: user.get("email"),
-                password: user.get("password"),
-                propic_url: user.get("propic_url"),
-            }))
-        } else {
-            Ok(None)
-        }
+        Ok(result.rows_affected())
     }
 
+    /// A fresh user id, picked before registering so a token can be signed
+    /// for the user before anything is stored.
+    pub fn new_id() -> String {
+        nanoid!(32)
+    }
+
+    #[instrument(skip_all, fields(db.system = "postgresql"))]
     pub async fn register(
         e: impl PgExecutor<'_>,
-        email: &str,
-        name: &str,
-        surname: &str,
-        password_hash: &str,
-    ) -> Result<String, sqlx_core::Error> {
-        let user_id = nanoid!(32);
+        user: &NewUser,
+    ) -> Result<(), sqlx_core::Error> {
         sqlx::query(
             "
-            insert into users (id, name, surname, email, password) values
+            insert into users (id, name, surname, email, password, locale, time_zone) values
             (
                 $1,
                 $2,
                 $3,
                 $4,
-                $5
+                $5,
+                $6,
+                $7
             )
         ",
         )
-        .bind(&user_id)
-        .bind(name)
-        .bind(surname)
-        .bind(email)
-        .bind(password_hash)
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(&user.surname)
+        .bind(&user.email)
+        .bind(&user.password_hash)
+        .bind(user.locale.code())
+        .bind(&user.time_zone)
         .execute(e)
         .await?; // in 0.7, `Transaction` can no longer implement `Executor` directly,
                  // so it must be dereferenced to the internal connection type.
 
-        Ok(user_id.to_string())
+        Ok(())
     }
 }
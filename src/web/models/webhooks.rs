@@ -0,0 +1,440 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{types::Json, PgConnection, PgExecutor};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::web::models::outbox::{OutboxEvent, OutboxKind};
+
+/// A partner told about user events.
+#[derive(Serialize, ToSchema, Debug, Clone, sqlx::FromRow)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    /// the event types sent, every one of them when empty
+    #[schema(example = json!(["user.registered"]))]
+    pub events: Vec<String>,
+    /// only ever shown when it's set
+    #[serde(skip)]
+    pub secret: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// nothing is sent while it's set
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+impl WebhookSubscription {
+    pub fn wants(&self, kind: &str) -> bool {
+        self.disabled_at.is_none()
+            && (self.events.is_empty() || self.events.iter().any(|e| e == kind))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NewWebhookSubscription {
+    pub url: String,
+    pub events: Vec<OutboxKind>,
+    pub secret: String,
+    pub description: Option<String>,
+}
+
+/// What to change, leaving out what's `None`.
+#[derive(Clone, Debug, Default)]
+pub struct WebhookUpdate {
+    pub url: Option<String>,
+    pub events: Option<Vec<OutboxKind>>,
+    pub secret: Option<String>,
+    pub description: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// waiting for its next attempt
+    Pending,
+    Delivered,
+    /// gave up after too many attempts, until it's redelivered
+    Dead,
+}
+
+impl DeliveryState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryState::Pending => "pending",
+            DeliveryState::Delivered => "delivered",
+            DeliveryState::Dead => "dead",
+        }
+    }
+}
+
+/// An event on its way to a subscription, along with every attempt at
+/// sending it.
+#[derive(Serialize, ToSchema, Debug, Clone, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    #[serde(skip)]
+    pub subscription_id: String,
+    /// the `id` in the payload
+    pub event_id: i64,
+    #[schema(example = "user.registered")]
+    pub kind: String,
+    pub user_id: String,
+    /// the `data` in the payload
+    #[schema(value_type = Object)]
+    pub payload: Json<Value>,
+    pub occurred_at: DateTime<Utc>,
+    /// `pending`, `delivered` or `dead`
+    pub state: String,
+    /// failed attempts since it was last (re)delivered
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// oldest first
+    #[sqlx(skip)]
+    pub log: Vec<WebhookAttempt>,
+}
+
+impl WebhookDelivery {
+    /// The event, as the subscription receives it.
+    pub fn event(&self) -> OutboxEvent {
+        OutboxEvent {
+            id: self.event_id,
+            kind: self.kind.clone(),
+            user_id: self.user_id.clone(),
+            payload: self.payload.clone(),
+            created_at: self.occurred_at,
+            attempts: self.attempts,
+        }
+    }
+}
+
+/// A request made for a delivery.
+#[derive(Serialize, ToSchema, Debug, Clone, sqlx::FromRow)]
+pub struct WebhookAttempt {
+    #[serde(skip)]
+    pub delivery_id: i64,
+    pub attempted_at: DateTime<Utc>,
+    /// missing when there was no response at all
+    pub status_code: Option<i16>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+/// Which deliveries to look for.
+#[derive(Clone, Debug, Default)]
+pub struct DeliveryFilter {
+    pub state: Option<DeliveryState>,
+    /// only deliveries older than this one, to page through them
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+impl DeliveryFilter {
+    pub const DEFAULT_LIMIT: i64 = 50;
+
+    pub fn matches(&self, delivery: &WebhookDelivery) -> bool {
+        self.state.iter().all(|state| state.as_str() == delivery.state)
+            && self.before.iter().all(|before| delivery.id < *before)
+    }
+}
+
+/// A delivery that's due, with what it takes to send it.
+#[derive(sqlx::FromRow)]
+pub struct DueDelivery {
+    #[sqlx(flatten)]
+    pub delivery: WebhookDelivery,
+    pub url: String,
+    pub secret: String,
+}
+
+/// How an attempt went, and what comes next.
+pub enum Outcome {
+    Delivered,
+    Retry(Duration),
+    Dead,
+}
+
+fn kinds(events: &[OutboxKind]) -> Vec<&'static str> {
+    events.iter().map(|kind| kind.as_str()).collect()
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn create(
+    e: impl PgExecutor<'_>,
+    subscription: &NewWebhookSubscription,
+) -> Result<WebhookSubscription, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            insert into webhook_subscriptions (id, url, events, secret, description)
+            values ($1, $2, $3, $4, $5)
+            returning *
+        ",
+    )
+    .bind(nanoid!(16))
+    .bind(&subscription.url)
+    .bind(kinds(&subscription.events))
+    .bind(&subscription.secret)
+    .bind(&subscription.description)
+    .fetch_one(e)
+    .await
+}
+
+/// Oldest first.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn list(
+    e: impl PgExecutor<'_>,
+) -> Result<Vec<WebhookSubscription>, sqlx_core::Error> {
+    sqlx::query_as("select * from webhook_subscriptions order by created_at, id")
+        .fetch_all(e)
+        .await
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn find(
+    e: impl PgExecutor<'_>,
+    id: &str,
+) -> Result<Option<WebhookSubscription>, sqlx_core::Error> {
+    sqlx::query_as("select * from webhook_subscriptions where id = $1")
+        .bind(id)
+        .fetch_optional(e)
+        .await
+}
+
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn update(
+    e: impl PgExecutor<'_>,
+    id: &str,
+    update: &WebhookUpdate,
+) -> Result<Option<WebhookSubscription>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            update webhook_subscriptions set
+            url = coalesce($2, url),
+            events = coalesce($3, events),
+            secret = coalesce($4, secret),
+            description = coalesce($5, description),
+            disabled_at = case
+                when $6 then null
+                when not $6 then coalesce(disabled_at, now())
+                else disabled_at
+            end
+            where id = $1
+            returning *
+        ",
+    )
+    .bind(id)
+    .bind(&update.url)
+    .bind(update.events.as_deref().map(kinds))
+    .bind(&update.secret)
+    .bind(&update.description)
+    .bind(update.enabled)
+    .fetch_optional(e)
+    .await
+}
+
+/// Along with its deliveries.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn delete(
+    e: impl PgExecutor<'_>,
+    id: &str,
+) -> Result<bool, sqlx_core::Error> {
+    let result = sqlx::query("delete from webhook_subscriptions where id = $1")
+        .bind(id)
+        .execute(e)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Newest first, with their attempts.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn deliveries(
+    e: impl PgExecutor<'_> + Copy,
+    subscription_id: &str,
+    filter: &DeliveryFilter,
+) -> Result<Vec<WebhookDelivery>, sqlx_core::Error> {
+    let mut deliveries: Vec<WebhookDelivery> = sqlx::query_as(
+        "
+            select * from webhook_deliveries
+            where subscription_id = $1
+            and ($2::text is null or state = $2)
+            and ($3::bigint is null or id < $3)
+            order by id desc
+            limit $4
+        ",
+    )
+    .bind(subscription_id)
+    .bind(filter.state.map(DeliveryState::as_str))
+    .bind(filter.before)
+    .bind(filter.limit)
+    .fetch_all(e)
+    .await?;
+
+    let ids: Vec<i64> = deliveries.iter().map(|d| d.id).collect();
+    let attempts: Vec<WebhookAttempt> = sqlx::query_as(
+        "
+            select delivery_id, attempted_at, status_code, error, duration_ms
+            from webhook_attempts where delivery_id = any($1)
+            order by id
+        ",
+    )
+    .bind(&ids)
+    .fetch_all(e)
+    .await?;
+    for attempt in attempts {
+        if let Some(delivery) =
+            deliveries.iter_mut().find(|d| d.id == attempt.delivery_id)
+        {
+            delivery.log.push(attempt);
+        }
+    }
+
+    Ok(deliveries)
+}
+
+/// Sends the delivery again as soon as possible, with a fresh set of
+/// attempts, whatever its state. `None` if it's not one of the
+/// subscription's.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn redeliver(
+    e: impl PgExecutor<'_>,
+    subscription_id: &str,
+    delivery_id: i64,
+) -> Result<Option<WebhookDelivery>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            update webhook_deliveries set
+            state = 'pending', attempts = 0, next_attempt_at = now(),
+            delivered_at = null
+            where id = $1 and subscription_id = $2
+            returning *
+        ",
+    )
+    .bind(delivery_id)
+    .bind(subscription_id)
+    .fetch_optional(e)
+    .await
+}
+
+/// Leases up to `limit` deliveries that are due for `lease`: until then, or
+/// until their attempt is recorded, no other dispatcher takes them. Like for
+/// the outbox, only the oldest pending delivery of each user is taken for a
+/// subscription, so they arrive in order.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn claim(
+    e: impl PgExecutor<'_>,
+    limit: i64,
+    lease: Duration,
+) -> Result<Vec<DueDelivery>, sqlx_core::Error> {
+    sqlx::query_as(
+        "
+            with claimed as (
+                update webhook_deliveries set
+                locked_until = now() + make_interval(secs => $2)
+                where id in (
+                    select d.id
+                    from webhook_deliveries d
+                    join webhook_subscriptions s on s.id = d.subscription_id
+                    where d.state = 'pending'
+                    and d.next_attempt_at <= now()
+                    and (d.locked_until is null or d.locked_until <= now())
+                    and s.disabled_at is null
+                    and not exists (
+                        select 1 from webhook_deliveries earlier
+                        where earlier.subscription_id = d.subscription_id
+                        and earlier.user_id = d.user_id
+                        and earlier.state = 'pending'
+                        and earlier.id < d.id
+                    )
+                    order by d.id
+                    limit $1
+                    for update of d skip locked
+                )
+                returning *
+            )
+            select claimed.*, s.url, s.secret
+            from claimed join webhook_subscriptions s
+            on s.id = claimed.subscription_id
+            order by claimed.id
+        ",
+    )
+    .bind(limit)
+    .bind(lease.as_secs_f64())
+    .fetch_all(e)
+    .await
+}
+
+/// Logs `attempt`, and moves the delivery on according to `outcome`.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn record(
+    conn: &mut PgConnection,
+    attempt: &WebhookAttempt,
+    outcome: Outcome,
+) -> Result<(), sqlx_core::Error> {
+    sqlx::query(
+        "
+            insert into webhook_attempts (
+                delivery_id, attempted_at, status_code, error, duration_ms
+            )
+            values ($1, $2, $3, $4, $5)
+        ",
+    )
+    .bind(attempt.delivery_id)
+    .bind(attempt.attempted_at)
+    .bind(attempt.status_code)
+    .bind(&attempt.error)
+    .bind(attempt.duration_ms)
+    .execute(&mut *conn)
+    .await?;
+
+    let (state, retry_in) = match outcome {
+        Outcome::Delivered => (DeliveryState::Delivered, Duration::ZERO),
+        Outcome::Retry(retry_in) => (DeliveryState::Pending, retry_in),
+        Outcome::Dead => (DeliveryState::Dead, Duration::ZERO),
+    };
+    sqlx::query(
+        "
+            update webhook_deliveries set
+            state = $2,
+            attempts = case when $2 = 'delivered' then attempts else attempts + 1 end,
+            next_attempt_at = now() + make_interval(secs => $3),
+            delivered_at = case when $2 = 'delivered' then now() end,
+            locked_until = null
+            where id = $1
+        ",
+    )
+    .bind(attempt.delivery_id)
+    .bind(state.as_str())
+    .bind(retry_in.as_secs_f64())
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes the deliveries done with, delivered or dead, created more than
+/// `older_than` ago.
+#[instrument(skip_all, fields(db.system = "postgresql"))]
+pub async fn purge(
+    e: impl PgExecutor<'_>,
+    older_than: Duration,
+) -> Result<u64, sqlx_core::Error> {
+    let result = sqlx::query(
+        "
+            delete from webhook_deliveries
+            where state <> 'pending'
+            and created_at < now() - make_interval(secs => $1)
+        ",
+    )
+    .bind(older_than.as_secs_f64())
+    .execute(e)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,141 @@
+//! Requests made on behalf of partners, like webhook deliveries, must not
+//! reach our own network: whoever manages a subscription could otherwise
+//! point it at the metadata service or at another service in the cluster.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hyper::client::connect::dns::Name;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    Url,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Refused {
+    #[error("only http and https URLs can be called")]
+    Scheme,
+    #[error("`{0}` is a name of the internal network")]
+    InternalHost(String),
+    #[error("{0} isn't a public address")]
+    PrivateAddress(IpAddr),
+}
+
+/// Whether `url` may be called, as far as can be told without resolving
+/// its host: `PublicResolver` checks the addresses it resolves to.
+pub fn check_url(url: &Url) -> Result<(), Refused> {
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(Refused::Scheme);
+    }
+    let host = url.host_str().unwrap_or_default();
+    // IPv6 addresses are the ones in brackets
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => check_ip(ip),
+        Err(_) => check_domain(host),
+    }
+}
+
+fn check_ip(ip: IpAddr) -> Result<(), Refused> {
+    match is_public(ip) {
+        true => Ok(()),
+        false => Err(Refused::PrivateAddress(ip)),
+    }
+}
+
+// names only the cluster's resolver knows: a name without dots is looked up
+// in its search domains, e.g. `users` is `users.default.svc.cluster.local`
+fn check_domain(domain: &str) -> Result<(), Refused> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let internal = !domain.contains('.')
+        || ["localhost", "local", "internal", "svc", "cluster.local"]
+            .iter()
+            .any(|suffix| {
+                domain == *suffix || domain.ends_with(&format!(".{suffix}"))
+            });
+    match internal {
+        true => Err(Refused::InternalHost(domain)),
+        false => Ok(()),
+    }
+}
+
+/// Whether `ip` is reachable from the internet, rather than private,
+/// loopback, link-local (like the 169.254.169.254 metadata service), shared
+/// or otherwise reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "this network", carrier-grade NAT, IETF protocol assignments,
+        // benchmarking and the reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    // an IPv4 address in disguise is as public as the address itself
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+    let segments = ip.segments();
+    let [first, second, third, .., high, low] = segments;
+    let v4 = |high: u16, low: u16| Ipv4Addr::from((high as u32) << 16 | low as u32);
+    match first {
+        // NAT64 carries it at the end, 6to4 right after the prefix
+        0x64 if second == 0xff9b => is_public_v4(v4(high, low)),
+        0x2002 => is_public_v4(v4(second, third)),
+        _ => {
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local and the deprecated site-local
+                || (first & 0xffc0) == 0xfe80
+                || (first & 0xffc0) == 0xfec0
+                // documentation
+                || (first == 0x2001 && second == 0x0db8)
+                // IPv4-compatible, also deprecated
+                || segments[..6].iter().all(|s| *s == 0))
+        }
+    }
+}
+
+/// Resolves names like the system does, but only to public addresses, so
+/// that a name can't be pointed at the internal network once it passed
+/// `check_url`. Connections go to the very addresses that were checked.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let resolved: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let public: Vec<SocketAddr> = resolved
+                .iter()
+                .copied()
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            match (public.is_empty(), resolved.first()) {
+                (true, Some(addr)) => {
+                    Err(Box::new(Refused::PrivateAddress(addr.ip())) as _)
+                }
+                _ => Ok(Box::new(public.into_iter()) as Addrs),
+            }
+        })
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tracing::instrument;
+
+use crate::{
+    telemetry,
+    web::{
+        dto::notification_payload::NotificationPayload, i18n, locale::Locale,
+        middlewares::request_id::{RequestContext, X_REQUEST_ID},
+        models::users::User,
+    },
+};
+
+/// Talks to the fcm-messenger service, which owns the Firebase credentials
+/// and does the actual delivery.
+#[derive(Clone)]
+pub struct PushClient {
+    http: reqwest::Client,
+    url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PushMessage {
+    pub tokens: Vec<String>,
+    pub title: String,
+    pub body: String,
+    pub data: HashMap<String, String>,
+}
+
+impl PushClient {
+    pub fn new(url: &str) -> PushClient {
+        PushClient {
+            http: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client"))]
+    pub async fn send(&self, message: &PushMessage) -> Result<(), reqwest::Error> {
+        self.http
+            .post(format!("{}/send", self.url))
+            .headers(trace_headers())
+            .json(message)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Whether fcm-messenger answers at all: anything but a 5xx will do.
+    pub async fn check(&self) -> Result<(), anyhow::Error> {
+        let status = self
+            .http
+            .get(&self.url)
+            .headers(trace_headers())
+            .send()
+            .await?
+            .status();
+        if status.is_server_error() {
+            return Err(anyhow::anyhow!("fcm-messenger answered {status}"));
+        }
+
+        Ok(())
+    }
+}
+
+/// `traceparent` and `X-Request-Id` for fcm-messenger, so its spans join
+/// our traces and its logs can be matched with ours.
+fn trace_headers() -> reqwest::header::HeaderMap {
+    let request_id = RequestContext::current()
+        .map(|context| (X_REQUEST_ID.to_string(), context.request_id));
+
+    telemetry::propagation_headers()
+        .into_iter()
+        .chain(request_id)
+        .filter_map(|(key, value)| Some((key.parse().ok()?, value.parse().ok()?)))
+        .collect()
+}
+
+/// Title and body of the push for `payload`, written for somebody reading
+/// in `locale` and `time_zone`, or `None` for kinds that aren't worth a push.
+pub fn render(
+    payload: &NotificationPayload,
+    sender: Option<&User>,
+    locale: Locale,
+    time_zone: Tz,
+    at: DateTime<Utc>,
+) -> Option<(String, String)> {
+    let sender = match sender {
+        Some(u) => format!("{} {}", u.name, u.surname),
+        None => i18n::t(locale, "push.someone", &[]),
+    };
+    let at = locale.format_datetime(at, time_zone);
+
+    match payload {
+        NotificationPayload::Refuel(p) => Some((
+            i18n::t(locale, "push.refuel.title", &[("car", &p.car_name)]),
+            i18n::t(
+                locale,
+                "push.refuel.body",
+                &[
+                    ("sender", &sender),
+                    ("liters", &locale.format_number(p.fuel_value)),
+                    ("car", &p.car_name),
+                    ("at", &at),
+                ],
+            ),
+        )),
+        NotificationPayload::CarInvite(p) => Some((
+            i18n::t(locale, "push.car_invite.title", &[]),
+            i18n::t(
+                locale,
+                "push.car_invite.body",
+                &[("sender", &sender), ("car", &p.car_name)],
+            ),
+        )),
+        NotificationPayload::Unknown(_) => None,
+    }
+}
@@ -0,0 +1,1071 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use nanoid::nanoid;
+use serde_json::Value;
+use sqlx::types::Json;
+
+use crate::web::{
+    dto::me::{
+        notification_settings::NotificationSettings,
+        notifications::{Notification, NotificationRow},
+    },
+    jobs::retention::{RetentionAction, RetentionRule},
+    locale::Locale,
+    models::{
+        audit_events::{Actor, AuditEvent, AuditFilter, NewAuditEvent},
+        idempotency_keys::{Reservation, StoredResponse},
+        notifications::{CreatedNotification, InsertedBatch, MarkedRead},
+        outbox::{NewOutboxEvent, OutboxEvent},
+        users::{RecipientModel, User, UserModel},
+        webhooks::{
+            DeliveryFilter, DeliveryState, DueDelivery, NewWebhookSubscription,
+            Outcome, WebhookAttempt, WebhookDelivery, WebhookSubscription,
+            WebhookUpdate,
+        },
+    },
+    repositories::{
+        AuditRepository, IdempotencyRepository, NewUser, NotificationRepository,
+        OutboxRepository, RepositoryError, RepositoryResult, UserRepository,
+        WebhookRepository,
+    },
+};
+
+struct StoredRow {
+    id: String,
+    to_user: String,
+    data: Value,
+    idempotency_key: Option<String>,
+    created_at: DateTime<Utc>,
+    read_at: Option<DateTime<Utc>>,
+    archived_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct Store {
+    users: Vec<User>,
+    /// `(token, user_id)`
+    fcm_tokens: Vec<(String, String)>,
+    /// in insertion order, which is also creation order
+    notifications: Vec<StoredRow>,
+    settings: HashMap<String, NotificationSettings>,
+    /// `(user_id, title, body)`
+    held_pushes: Vec<(String, String, String)>,
+    idempotency_keys: HashMap<(String, String), StoredKey>,
+    /// oldest first
+    audit_events: Vec<AuditEvent>,
+    /// in the order they were written
+    outbox: Vec<StoredEvent>,
+    /// the id of the last event written, purged or not
+    last_event_id: i64,
+    webhooks: Vec<WebhookSubscription>,
+    /// oldest first
+    webhook_deliveries: Vec<WebhookDelivery>,
+    /// until when each leased delivery is taken
+    webhook_leases: HashMap<i64, DateTime<Utc>>,
+}
+
+struct StoredEvent {
+    id: i64,
+    event: NewOutboxEvent,
+    created_at: DateTime<Utc>,
+    published_at: Option<DateTime<Utc>>,
+    attempts: i32,
+    next_attempt_at: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+struct StoredKey {
+    request_hash: String,
+    response: Option<StoredResponse>,
+    created_at: DateTime<Utc>,
+}
+
+impl Store {
+    /// Writes an event to the outbox, and queues it for the webhooks
+    /// subscribed to it.
+    fn emit(&mut self, event: NewOutboxEvent) {
+        self.last_event_id += 1;
+        let event_id = self.last_event_id;
+        let now = Utc::now();
+        for webhook in &self.webhooks {
+            if !webhook.wants(event.kind.as_str()) {
+                continue;
+            }
+            self.webhook_deliveries.push(WebhookDelivery {
+                id: self.webhook_deliveries.len() as i64 + 1,
+                subscription_id: webhook.id.clone(),
+                event_id,
+                kind: event.kind.as_str().to_string(),
+                user_id: event.user_id.clone(),
+                payload: Json(event.payload.clone()),
+                occurred_at: now,
+                state: DeliveryState::Pending.as_str().to_string(),
+                attempts: 0,
+                next_attempt_at: now,
+                created_at: now,
+                delivered_at: None,
+                log: vec![],
+            });
+        }
+        self.outbox.push(StoredEvent {
+            id: event_id,
+            event,
+            created_at: now,
+            published_at: None,
+            attempts: 0,
+            next_attempt_at: now,
+            locked_until: None,
+        });
+    }
+
+    fn notification(&self, row: &StoredRow) -> Notification {
+        let sender = row
+            .data
+            .get("owner")
+            .and_then(Value::as_str)
+            .and_then(|id| self.users.iter().find(|u| u.id == id));
+
+        Notification::from(NotificationRow {
+            id: row.id.clone(),
+            to_user: row.to_user.clone(),
+            created_at: row.created_at,
+            read_at: row.read_at,
+            data: Json(row.data.clone()),
+            sender_id: sender.map(|s| s.id.clone()),
+            sender_name: sender.map(|s| s.name.clone()),
+            sender_surname: sender.map(|s| s.surname.clone()),
+            sender_propic_url: sender.and_then(|s| s.propic_url.clone()),
+        })
+    }
+
+    fn owned_mut(
+        &mut self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> Option<&mut StoredRow> {
+        self.notifications
+            .iter_mut()
+            .find(|n| n.id == notification_id && n.to_user == user_id)
+    }
+
+    fn unread(&self, user_id: &str) -> i64 {
+        self.notifications
+            .iter()
+            .filter(|n| n.to_user == user_id)
+            .filter(|n| n.read_at.is_none() && n.archived_at.is_none())
+            .count() as i64
+    }
+}
+
+/// Every repository backed by the same in-memory store, for tests.
+#[derive(Clone, Default)]
+pub struct MemoryRepository {
+    store: Arc<Mutex<Store>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> MemoryRepository {
+        MemoryRepository::default()
+    }
+
+    /// The domain events written so far, oldest first.
+    pub fn outbox(&self) -> Vec<NewOutboxEvent> {
+        let store = self.store.lock().unwrap();
+        store.outbox.iter().map(|stored| stored.event.clone()).collect()
+    }
+
+    /// The ids of the events published so far, in the order they were
+    /// written.
+    pub fn published(&self) -> Vec<i64> {
+        let store = self.store.lock().unwrap();
+        store
+            .outbox
+            .iter()
+            .filter(|stored| stored.published_at.is_some())
+            .map(|stored| stored.id)
+            .collect()
+    }
+
+    /// Moves the notifications, the outbox and the webhook deliveries `by`
+    /// into the future: they're that much older, and the waits before
+    /// retrying and the leases that would end by then are over.
+    pub fn advance(&self, by: Duration) {
+        let by = chrono::Duration::from_std(by).unwrap();
+        let mut store = self.store.lock().unwrap();
+        for notification in &mut store.notifications {
+            notification.created_at -= by;
+            notification.read_at = notification.read_at.map(|at| at - by);
+            notification.archived_at = notification.archived_at.map(|at| at - by);
+        }
+        for stored in &mut store.outbox {
+            stored.next_attempt_at -= by;
+            stored.locked_until = stored.locked_until.map(|until| until - by);
+        }
+        for delivery in &mut store.webhook_deliveries {
+            delivery.next_attempt_at -= by;
+        }
+        for until in store.webhook_leases.values_mut() {
+            *until -= by;
+        }
+    }
+
+    /// What's stored for each idempotency key: the request hash and the
+    /// body of the response, once there is one.
+    pub fn idempotency_keys(&self) -> Vec<(String, Option<Vec<u8>>)> {
+        let store = self.store.lock().unwrap();
+        store
+            .idempotency_keys
+            .values()
+            .map(|stored| {
+                let body = stored.response.as_ref().map(|r| r.body.clone());
+                (stored.request_hash.clone(), body)
+            })
+            .collect()
+    }
+
+    /// The pushes held for digests, as `(user_id, title, body)`.
+    pub fn held_pushes(&self) -> Vec<(String, String, String)> {
+        self.store.lock().unwrap().held_pushes.clone()
+    }
+
+    /// Changes a stored user in place, e.g. to disable them.
+    pub fn update_user(&self, user_id: &str, update: impl FnOnce(&mut User)) {
+        let mut store = self.store.lock().unwrap();
+        if let Some(user) = store.users.iter_mut().find(|u| u.id == user_id) {
+            update(user);
+        }
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn by_id(&self, id: &str) -> RepositoryResult<Option<User>> {
+        let store = self.store.lock().unwrap();
+        Ok(store.users.iter().find(|u| u.id == id).cloned())
+    }
+
+    async fn by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        let store = self.store.lock().unwrap();
+        Ok(store.users.iter().find(|u| u.email == email).cloned())
+    }
+
+    async fn register(&self, user: NewUser) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        if store.users.iter().any(|u| u.email == user.email) {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let user = User {
+            email: user.email,
+            name: user.name,
+            surname: user.surname,
+            id: user.id,
+            password: user.password_hash,
+            propic_url: None,
+            locale: user.locale.code().to_string(),
+            time_zone: user.time_zone,
+            disabled_at: None,
+            token_generation: 0,
+        };
+        store.emit(NewOutboxEvent::user_registered(UserModel::from(user.clone()))?);
+        store.users.push(user);
+        Ok(())
+    }
+
+    async fn update_preferences(
+        &self,
+        user: &mut User,
+        locale: Option<Locale>,
+        time_zone: Option<&str>,
+    ) -> RepositoryResult<()> {
+        let before = (user.locale.clone(), user.time_zone.clone());
+        if let Some(locale) = locale {
+            user.locale = locale.code().to_string();
+        }
+        if let Some(time_zone) = time_zone {
+            user.time_zone = time_zone.to_string();
+        }
+        let updated = user.clone();
+        self.update_user(&user.id, |stored| *stored = updated);
+        if before != (user.locale.clone(), user.time_zone.clone()) {
+            let event = NewOutboxEvent::user_updated(user.clone())?;
+            self.store.lock().unwrap().emit(event);
+        }
+        Ok(())
+    }
+
+    async fn set_disabled(
+        &self,
+        user: &mut User,
+        disabled: bool,
+    ) -> RepositoryResult<()> {
+        match disabled {
+            true => {
+                user.disabled_at = user.disabled_at.or(Some(Utc::now()));
+                user.token_generation += 1;
+            }
+            false => user.disabled_at = None,
+        }
+        let updated = user.clone();
+        self.update_user(&user.id, |stored| *stored = updated);
+        let event = NewOutboxEvent::user_updated(user.clone())?;
+        self.store.lock().unwrap().emit(event);
+        Ok(())
+    }
+
+    async fn set_password(
+        &self,
+        user: &mut User,
+        password_hash: &str,
+    ) -> RepositoryResult<()> {
+        user.password = password_hash.to_string();
+        user.token_generation += 1;
+        let updated = user.clone();
+        self.update_user(&user.id, |stored| *stored = updated);
+        let event = NewOutboxEvent::user_updated(user.clone())?;
+        self.store.lock().unwrap().emit(event);
+        Ok(())
+    }
+
+    async fn add_fcm_token(
+        &self,
+        user_id: &str,
+        token: &str,
+    ) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        if store.fcm_tokens.iter().any(|(t, _)| t == token) {
+            return Err(RepositoryError::Conflict);
+        }
+        store
+            .fcm_tokens
+            .push((token.to_string(), user_id.to_string()));
+        store.emit(NewOutboxEvent::fcm_token_added(user_id, token));
+        Ok(())
+    }
+
+    async fn fcm_tokens(
+        &self,
+        user_ids: &[String],
+    ) -> RepositoryResult<Vec<(String, String)>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .fcm_tokens
+            .iter()
+            .filter(|(_, user_id)| user_ids.contains(user_id))
+            .map(|(token, user_id)| (user_id.clone(), token.clone()))
+            .collect())
+    }
+
+    async fn recipients(
+        &self,
+        ids: &[String],
+    ) -> RepositoryResult<Vec<RecipientModel>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .users
+            .iter()
+            .filter(|u| ids.contains(&u.id))
+            .map(|u| RecipientModel {
+                id: u.id.clone(),
+                email: u.email.clone(),
+                locale: u.locale.clone(),
+                time_zone: u.time_zone.clone(),
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl NotificationRepository for MemoryRepository {
+    async fn list(
+        &self,
+        user_id: &str,
+        archived: bool,
+    ) -> RepositoryResult<Vec<Notification>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .notifications
+            .iter()
+            .rev()
+            .filter(|n| n.to_user == user_id)
+            .filter(|n| n.archived_at.is_some() == archived)
+            .map(|n| store.notification(n))
+            .collect())
+    }
+
+    async fn get(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<Option<Notification>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .notifications
+            .iter()
+            .find(|n| n.id == notification_id && n.to_user == user_id)
+            .filter(|n| n.archived_at.is_none())
+            .map(|n| store.notification(n)))
+    }
+
+    async fn list_after(
+        &self,
+        user_id: &str,
+        after_id: &str,
+    ) -> RepositoryResult<Vec<Notification>> {
+        let store = self.store.lock().unwrap();
+        let Some(position) = store
+            .notifications
+            .iter()
+            .position(|n| n.id == after_id && n.to_user == user_id)
+        else {
+            return Ok(vec![]);
+        };
+
+        Ok(store.notifications[position + 1..]
+            .iter()
+            .filter(|n| n.to_user == user_id && n.archived_at.is_none())
+            .map(|n| store.notification(n))
+            .collect())
+    }
+
+    async fn list_since(
+        &self,
+        user_id: &str,
+        since: DateTime<Utc>,
+    ) -> RepositoryResult<Vec<Notification>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .notifications
+            .iter()
+            .filter(|n| {
+                n.to_user == user_id
+                    && n.archived_at.is_none()
+                    && n.created_at >= since
+            })
+            .map(|n| store.notification(n))
+            .collect())
+    }
+
+    async fn count_unread(&self, user_id: &str) -> RepositoryResult<i64> {
+        Ok(self.store.lock().unwrap().unread(user_id))
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<Option<MarkedRead>> {
+        let mut store = self.store.lock().unwrap();
+        let Some(n) = store.owned_mut(user_id, notification_id) else {
+            return Ok(None);
+        };
+        let was_unread = n.read_at.is_none();
+        n.read_at.get_or_insert(Utc::now());
+        Ok(Some(MarkedRead {
+            marked: was_unread as u64,
+            unread: store.unread(user_id),
+        }))
+    }
+
+    async fn mark_read_until(
+        &self,
+        user_id: &str,
+        until: Option<&str>,
+    ) -> RepositoryResult<Option<MarkedRead>> {
+        let mut store = self.store.lock().unwrap();
+        let until = match until {
+            Some(id) => match store.owned_mut(user_id, id) {
+                Some(n) => Some(n.created_at),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+
+        let now = Utc::now();
+        let mut marked = 0;
+        for n in store.notifications.iter_mut() {
+            if n.to_user == user_id
+                && n.read_at.is_none()
+                && !matches!(until, Some(until) if n.created_at > until)
+            {
+                n.read_at = Some(now);
+                marked += 1;
+            }
+        }
+        Ok(Some(MarkedRead {
+            marked,
+            unread: store.unread(user_id),
+        }))
+    }
+
+    async fn delete(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<bool> {
+        let mut store = self.store.lock().unwrap();
+        let before = store.notifications.len();
+        store
+            .notifications
+            .retain(|n| !(n.id == notification_id && n.to_user == user_id));
+        Ok(store.notifications.len() < before)
+    }
+
+    async fn set_archived(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+        archived: bool,
+    ) -> RepositoryResult<bool> {
+        let mut store = self.store.lock().unwrap();
+        Ok(match store.owned_mut(user_id, notification_id) {
+            Some(n) => {
+                n.archived_at = match archived {
+                    true => n.archived_at.or(Some(Utc::now())),
+                    false => None,
+                };
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn insert_batch(
+        &self,
+        recipients: &[String],
+        archived: &[String],
+        payload: &Value,
+        idempotency_key: Option<&str>,
+    ) -> RepositoryResult<Option<InsertedBatch>> {
+        let mut store = self.store.lock().unwrap();
+        let stored: Vec<&StoredRow> = store
+            .notifications
+            .iter()
+            .filter(|n| {
+                idempotency_key.is_some() && n.idempotency_key.as_deref() == idempotency_key
+            })
+            .collect();
+        if stored.iter().any(|n| n.data != *payload) {
+            return Ok(None);
+        }
+        let existing = stored
+            .into_iter()
+            .map(|n| CreatedNotification {
+                id: n.id.clone(),
+                to_user: n.to_user.clone(),
+            })
+            .collect();
+
+        let now = Utc::now();
+        let mut created = vec![];
+        for to_user in recipients {
+            let exists = store.users.iter().any(|u| &u.id == to_user);
+            let duplicate = idempotency_key.is_some()
+                && store.notifications.iter().any(|n| {
+                    &n.to_user == to_user
+                        && n.idempotency_key.as_deref() == idempotency_key
+                });
+            if !exists || duplicate {
+                continue;
+            }
+
+            let id = nanoid!(32);
+            store.notifications.push(StoredRow {
+                id: id.clone(),
+                to_user: to_user.clone(),
+                data: payload.clone(),
+                idempotency_key: idempotency_key.map(str::to_string),
+                created_at: now,
+                read_at: None,
+                archived_at: archived.contains(to_user).then_some(now),
+            });
+            created.push(CreatedNotification {
+                id,
+                to_user: to_user.clone(),
+            });
+        }
+        Ok(Some(InsertedBatch { created, existing }))
+    }
+
+    async fn settings(
+        &self,
+        user_id: &str,
+    ) -> RepositoryResult<NotificationSettings> {
+        let store = self.store.lock().unwrap();
+        Ok(store.settings.get(user_id).cloned().unwrap_or_default())
+    }
+
+    async fn save_settings(
+        &self,
+        user_id: &str,
+        settings: &NotificationSettings,
+    ) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        store.settings.insert(user_id.to_string(), settings.clone());
+        Ok(())
+    }
+
+    async fn settings_for(
+        &self,
+        user_ids: &[String],
+    ) -> RepositoryResult<HashMap<String, NotificationSettings>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .settings
+            .iter()
+            .filter(|(user_id, _)| user_ids.contains(user_id))
+            .map(|(user_id, settings)| (user_id.clone(), settings.clone()))
+            .collect())
+    }
+
+    async fn hold_push(
+        &self,
+        user_id: &str,
+        title: &str,
+        body: &str,
+    ) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        store.held_pushes.push((
+            user_id.to_string(),
+            title.to_string(),
+            body.to_string(),
+        ));
+        Ok(())
+    }
+
+    async fn prune(
+        &self,
+        kind: Option<&str>,
+        overridden: &[&str],
+        rule: &RetentionRule,
+        limit: i64,
+    ) -> RepositoryResult<Vec<(String, u64)>> {
+        let mut store = self.store.lock().unwrap();
+        let cutoff = Utc::now() - chrono::Duration::days(rule.days.into());
+        let type_of = |row: &StoredRow| {
+            row.data
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("refuel")
+                .to_string()
+        };
+        let pruned: Vec<(String, String)> = store
+            .notifications
+            .iter()
+            .filter(|n| n.created_at < cutoff)
+            .filter(|n| {
+                rule.action == RetentionAction::Delete || n.archived_at.is_none()
+            })
+            .filter(|n| match kind {
+                Some(kind) => type_of(n) == kind,
+                None => !overridden.contains(&type_of(n).as_str()),
+            })
+            .take(limit as usize)
+            .map(|n| (n.id.clone(), type_of(n)))
+            .collect();
+
+        let is_pruned = |n: &StoredRow| pruned.iter().any(|(id, _)| *id == n.id);
+        match rule.action {
+            RetentionAction::Delete => store.notifications.retain(|n| !is_pruned(n)),
+            RetentionAction::Archive => {
+                for n in store.notifications.iter_mut().filter(|n| is_pruned(n)) {
+                    n.archived_at = Some(Utc::now());
+                }
+            }
+        }
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for (_, kind) in pruned {
+            *counts.entry(kind).or_default() += 1;
+        }
+        Ok(counts.into_iter().collect())
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for MemoryRepository {
+    async fn reserve(
+        &self,
+        scope: &str,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+        abandoned_after: Duration,
+    ) -> RepositoryResult<Reservation> {
+        let mut store = self.store.lock().unwrap();
+        let id = (scope.to_string(), key.to_string());
+        let age = |stored: &StoredKey| {
+            (Utc::now() - stored.created_at).to_std().unwrap_or_default()
+        };
+
+        // same as in Postgres: expired and abandoned keys are up for grabs
+        let live = |stored: &&StoredKey| {
+            age(stored) < ttl
+                && (stored.response.is_some() || age(stored) < abandoned_after)
+        };
+        let reservation = match store.idempotency_keys.get(&id).filter(live) {
+            None => Reservation::Reserved,
+            Some(stored) if stored.request_hash != request_hash => {
+                Reservation::Mismatch
+            }
+            Some(stored) => match &stored.response {
+                Some(response) => Reservation::Completed(response.clone()),
+                None => Reservation::InProgress,
+            },
+        };
+        if reservation == Reservation::Reserved {
+            store.idempotency_keys.insert(
+                id,
+                StoredKey {
+                    request_hash: request_hash.to_string(),
+                    response: None,
+                    created_at: Utc::now(),
+                },
+            );
+        }
+        Ok(reservation)
+    }
+
+    async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        response: &StoredResponse,
+    ) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        let id = (scope.to_string(), key.to_string());
+        if let Some(stored) = store.idempotency_keys.get_mut(&id) {
+            stored.response = Some(response.clone());
+        }
+        Ok(())
+    }
+
+    async fn release(&self, scope: &str, key: &str) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        store
+            .idempotency_keys
+            .remove(&(scope.to_string(), key.to_string()));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuditRepository for MemoryRepository {
+    async fn append(&self, event: NewAuditEvent) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        let (actor_type, actor_id) = match event.actor {
+            Actor::User(id) => ("user", Some(id)),
+            Actor::Service(name) => ("service", Some(name)),
+            Actor::Admin(name) => ("admin", name),
+            Actor::Anonymous => ("anonymous", None),
+        };
+        let id = store.audit_events.len() as i64 + 1;
+        store.audit_events.push(AuditEvent {
+            id,
+            kind: event.kind.as_str().to_string(),
+            actor_type: actor_type.to_string(),
+            actor_id,
+            user_id: event.user_id,
+            ip: event.ip,
+            user_agent: event.user_agent,
+            request_id: event.request_id,
+            details: Json(event.details),
+            created_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> RepositoryResult<Vec<AuditEvent>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .audit_events
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .take(filter.limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for MemoryRepository {
+    async fn claim(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> RepositoryResult<Vec<OutboxEvent>> {
+        let now = Utc::now();
+        let locked_until = now + chrono::Duration::from_std(lease).unwrap();
+        let mut store = self.store.lock().unwrap();
+        let mut waiting = vec![];
+        let mut claimed = vec![];
+        for stored in &mut store.outbox {
+            if stored.published_at.is_some() {
+                continue;
+            }
+            // only the oldest unpublished event of each user
+            let user_id = &stored.event.user_id;
+            if waiting.contains(user_id) {
+                continue;
+            }
+            waiting.push(user_id.clone());
+            if stored.next_attempt_at > now
+                || stored.locked_until.is_some_and(|until| until > now)
+                || claimed.len() as i64 == limit
+            {
+                continue;
+            }
+            stored.locked_until = Some(locked_until);
+            claimed.push(OutboxEvent {
+                id: stored.id,
+                kind: stored.event.kind.as_str().to_string(),
+                user_id: user_id.clone(),
+                payload: Json(stored.event.payload.clone()),
+                created_at: stored.created_at,
+                attempts: stored.attempts,
+            });
+        }
+        Ok(claimed)
+    }
+
+    async fn mark_published(&self, id: i64) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        if let Some(stored) = store.outbox.iter_mut().find(|e| e.id == id) {
+            stored.published_at = Some(Utc::now());
+            stored.locked_until = None;
+        }
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        id: i64,
+        _error: &str,
+        retry_in: Duration,
+    ) -> RepositoryResult<()> {
+        let mut store = self.store.lock().unwrap();
+        if let Some(stored) = store.outbox.iter_mut().find(|e| e.id == id) {
+            stored.attempts += 1;
+            stored.next_attempt_at =
+                Utc::now() + chrono::Duration::from_std(retry_in).unwrap();
+            stored.locked_until = None;
+        }
+        Ok(())
+    }
+
+    async fn purge_published(&self, older_than: Duration) -> RepositoryResult<u64> {
+        let cutoff = Utc::now() - chrono::Duration::from_std(older_than).unwrap();
+        let mut store = self.store.lock().unwrap();
+        let before = store.outbox.len();
+        store
+            .outbox
+            .retain(|stored| !matches!(stored.published_at, Some(at) if at < cutoff));
+        Ok((before - store.outbox.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for MemoryRepository {
+    async fn create(
+        &self,
+        subscription: NewWebhookSubscription,
+    ) -> RepositoryResult<WebhookSubscription> {
+        let webhook = WebhookSubscription {
+            id: nanoid!(16),
+            url: subscription.url,
+            events: subscription
+                .events
+                .iter()
+                .map(|kind| kind.as_str().to_string())
+                .collect(),
+            secret: subscription.secret,
+            description: subscription.description,
+            created_at: Utc::now(),
+            disabled_at: None,
+        };
+        self.store.lock().unwrap().webhooks.push(webhook.clone());
+        Ok(webhook)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<WebhookSubscription>> {
+        Ok(self.store.lock().unwrap().webhooks.clone())
+    }
+
+    async fn get(&self, id: &str) -> RepositoryResult<Option<WebhookSubscription>> {
+        let store = self.store.lock().unwrap();
+        Ok(store.webhooks.iter().find(|w| w.id == id).cloned())
+    }
+
+    async fn update(
+        &self,
+        id: &str,
+        update: WebhookUpdate,
+    ) -> RepositoryResult<Option<WebhookSubscription>> {
+        let mut store = self.store.lock().unwrap();
+        let Some(webhook) = store.webhooks.iter_mut().find(|w| w.id == id) else {
+            return Ok(None);
+        };
+        if let Some(url) = update.url {
+            webhook.url = url;
+        }
+        if let Some(events) = update.events {
+            webhook.events =
+                events.iter().map(|kind| kind.as_str().to_string()).collect();
+        }
+        if let Some(secret) = update.secret {
+            webhook.secret = secret;
+        }
+        if let Some(description) = update.description {
+            webhook.description = Some(description);
+        }
+        match update.enabled {
+            Some(true) => webhook.disabled_at = None,
+            Some(false) => {
+                webhook.disabled_at = webhook.disabled_at.or(Some(Utc::now()))
+            }
+            None => {}
+        }
+        Ok(Some(webhook.clone()))
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<bool> {
+        let mut store = self.store.lock().unwrap();
+        let before = store.webhooks.len();
+        store.webhooks.retain(|w| w.id != id);
+        store.webhook_deliveries.retain(|d| d.subscription_id != id);
+        Ok(store.webhooks.len() < before)
+    }
+
+    async fn deliveries(
+        &self,
+        subscription_id: &str,
+        filter: &DeliveryFilter,
+    ) -> RepositoryResult<Vec<WebhookDelivery>> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .webhook_deliveries
+            .iter()
+            .rev()
+            .filter(|d| d.subscription_id == subscription_id && filter.matches(d))
+            .take(filter.limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn redeliver(
+        &self,
+        subscription_id: &str,
+        delivery_id: i64,
+    ) -> RepositoryResult<Option<WebhookDelivery>> {
+        let mut store = self.store.lock().unwrap();
+        let Some(delivery) = store
+            .webhook_deliveries
+            .iter_mut()
+            .find(|d| d.id == delivery_id && d.subscription_id == subscription_id)
+        else {
+            return Ok(None);
+        };
+        delivery.state = DeliveryState::Pending.as_str().to_string();
+        delivery.attempts = 0;
+        delivery.next_attempt_at = Utc::now();
+        delivery.delivered_at = None;
+        Ok(Some(delivery.clone()))
+    }
+
+    async fn claim(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> RepositoryResult<Vec<DueDelivery>> {
+        let now = Utc::now();
+        let locked_until = now + chrono::Duration::from_std(lease).unwrap();
+        let mut store = self.store.lock().unwrap();
+        let store = &mut *store;
+        let mut waiting = vec![];
+        let mut claimed = vec![];
+        for delivery in &store.webhook_deliveries {
+            if delivery.state != DeliveryState::Pending.as_str() {
+                continue;
+            }
+            // only the oldest pending delivery of each user, per subscription
+            let key = (&delivery.subscription_id, &delivery.user_id);
+            if waiting.contains(&key) {
+                continue;
+            }
+            waiting.push(key);
+            let Some(webhook) = store
+                .webhooks
+                .iter()
+                .find(|w| w.id == delivery.subscription_id)
+            else {
+                continue;
+            };
+            let leased = store
+                .webhook_leases
+                .get(&delivery.id)
+                .is_some_and(|until| *until > now);
+            if webhook.disabled_at.is_some()
+                || delivery.next_attempt_at > now
+                || leased
+                || claimed.len() as i64 == limit
+            {
+                continue;
+            }
+            claimed.push(DueDelivery {
+                delivery: delivery.clone(),
+                url: webhook.url.clone(),
+                secret: webhook.secret.clone(),
+            });
+        }
+        for due in &claimed {
+            store.webhook_leases.insert(due.delivery.id, locked_until);
+        }
+        Ok(claimed)
+    }
+
+    async fn record(
+        &self,
+        attempt: &WebhookAttempt,
+        outcome: Outcome,
+    ) -> RepositoryResult<()> {
+        let now = Utc::now();
+        let mut store = self.store.lock().unwrap();
+        store.webhook_leases.remove(&attempt.delivery_id);
+        let Some(delivery) = store
+            .webhook_deliveries
+            .iter_mut()
+            .find(|d| d.id == attempt.delivery_id)
+        else {
+            return Ok(());
+        };
+        delivery.log.push(attempt.clone());
+        let (state, retry_in) = match outcome {
+            Outcome::Delivered => (DeliveryState::Delivered, Duration::ZERO),
+            Outcome::Retry(retry_in) => (DeliveryState::Pending, retry_in),
+            Outcome::Dead => (DeliveryState::Dead, Duration::ZERO),
+        };
+        if state != DeliveryState::Delivered {
+            delivery.attempts += 1;
+        }
+        delivery.state = state.as_str().to_string();
+        delivery.next_attempt_at = now + chrono::Duration::from_std(retry_in).unwrap();
+        delivery.delivered_at = (state == DeliveryState::Delivered).then_some(now);
+        Ok(())
+    }
+
+    async fn purge(&self, older_than: Duration) -> RepositoryResult<u64> {
+        let cutoff = Utc::now() - chrono::Duration::from_std(older_than).unwrap();
+        let mut store = self.store.lock().unwrap();
+        let before = store.webhook_deliveries.len();
+        store.webhook_deliveries.retain(|d| {
+            d.state == DeliveryState::Pending.as_str() || d.created_at >= cutoff
+        });
+        Ok((before - store.webhook_deliveries.len()) as u64)
+    }
+}
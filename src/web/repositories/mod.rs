@@ -0,0 +1,343 @@
+//! Where handlers get their data from. They only ever see these traits,
+//! through `AppState`, so they can be exercised against the in-memory
+//! backend instead of Postgres.
+
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::web::{
+    dto::me::{
+        notification_settings::NotificationSettings,
+        notifications::Notification,
+    },
+    jobs::retention::RetentionRule,
+    locale::Locale,
+    models::{
+        audit_events::{AuditEvent, AuditFilter, NewAuditEvent},
+        idempotency_keys::{Reservation, StoredResponse},
+        notifications::{InsertedBatch, MarkedRead},
+        outbox::OutboxEvent,
+        users::{RecipientModel, User},
+        webhooks::{
+            DeliveryFilter, DueDelivery, NewWebhookSubscription, Outcome,
+            WebhookAttempt, WebhookDelivery, WebhookSubscription, WebhookUpdate,
+        },
+    },
+};
+
+pub use crate::web::models::users::NewUser;
+
+#[cfg(test)]
+pub mod memory;
+pub mod postgres;
+
+#[derive(Debug, Error)]
+pub enum RepositoryError {
+    /// something that has to be unique, like an email, is already there
+    #[error("already exists")]
+    Conflict,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    /// something to be stored couldn't be turned into JSON
+    #[error("couldn't encode: {0}")]
+    Encode(#[from] serde_json::Error),
+}
+
+pub type RepositoryResult<T> = Result<T, RepositoryError>;
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn by_id(&self, id: &str) -> RepositoryResult<Option<User>>;
+
+    async fn by_email(&self, email: &str) -> RepositoryResult<Option<User>>;
+
+    /// `Conflict` when the email is taken.
+    async fn register(&self, user: NewUser) -> RepositoryResult<()>;
+
+    /// Updates the locale and/or time zone, leaving out what's `None`.
+    async fn update_preferences(
+        &self,
+        user: &mut User,
+        locale: Option<Locale>,
+        time_zone: Option<&str>,
+    ) -> RepositoryResult<()>;
+
+    /// Disables the user, revoking their tokens, or enables them back.
+    async fn set_disabled(
+        &self,
+        user: &mut User,
+        disabled: bool,
+    ) -> RepositoryResult<()>;
+
+    /// Revokes their tokens too.
+    async fn set_password(
+        &self,
+        user: &mut User,
+        password_hash: &str,
+    ) -> RepositoryResult<()>;
+
+    /// `Conflict` when the token is already stored.
+    async fn add_fcm_token(
+        &self,
+        user_id: &str,
+        token: &str,
+    ) -> RepositoryResult<()>;
+
+    /// FCM tokens of every user in `user_ids`, as `(user_id, token)` pairs.
+    async fn fcm_tokens(
+        &self,
+        user_ids: &[String],
+    ) -> RepositoryResult<Vec<(String, String)>>;
+
+    async fn recipients(
+        &self,
+        ids: &[String],
+    ) -> RepositoryResult<Vec<RecipientModel>>;
+}
+
+/// Notifications, and the settings deciding how they're delivered. Every
+/// lookup is scoped to the user the notification was sent to.
+#[async_trait]
+pub trait NotificationRepository: Send + Sync {
+    /// Newest first.
+    async fn list(
+        &self,
+        user_id: &str,
+        archived: bool,
+    ) -> RepositoryResult<Vec<Notification>>;
+
+    /// A notification that hasn't been archived.
+    async fn get(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<Option<Notification>>;
+
+    /// Active notifications created after `after_id`, oldest first. Empty
+    /// when `after_id` doesn't exist anymore.
+    async fn list_after(
+        &self,
+        user_id: &str,
+        after_id: &str,
+    ) -> RepositoryResult<Vec<Notification>>;
+
+    /// Active notifications created at or after `since`, oldest first.
+    async fn list_since(
+        &self,
+        user_id: &str,
+        since: DateTime<Utc>,
+    ) -> RepositoryResult<Vec<Notification>>;
+
+    async fn count_unread(&self, user_id: &str) -> RepositoryResult<i64>;
+
+    /// Whether this call marked it as read (`marked` is 0 or 1) and what's
+    /// left unread right after, `None` when the notification doesn't exist
+    /// or belongs to someone else. The original `read_at` is kept if it was
+    /// already read.
+    async fn mark_read(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<Option<MarkedRead>>;
+
+    /// Marks everything up to (and including) `until` as read, or all of it
+    /// without a cursor. Returns how many were flipped and what's left
+    /// unread right after, or `None` when `until` isn't one of theirs.
+    async fn mark_read_until(
+        &self,
+        user_id: &str,
+        until: Option<&str>,
+    ) -> RepositoryResult<Option<MarkedRead>>;
+
+    async fn delete(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<bool>;
+
+    async fn set_archived(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+        archived: bool,
+    ) -> RepositoryResult<bool>;
+
+    /// Stores `payload` for every existing recipient, the `archived` ones
+    /// straight into the archive, skipping whoever already got one with
+    /// the same `idempotency_key`. Calls with the same key are handled one
+    /// at a time, so a retry racing the first call sees what it stored.
+    /// `None`, with nothing stored, when the key was used for another
+    /// payload.
+    async fn insert_batch(
+        &self,
+        recipients: &[String],
+        archived: &[String],
+        payload: &Value,
+        idempotency_key: Option<&str>,
+    ) -> RepositoryResult<Option<InsertedBatch>>;
+
+    /// The defaults when they never changed them.
+    async fn settings(
+        &self,
+        user_id: &str,
+    ) -> RepositoryResult<NotificationSettings>;
+
+    async fn save_settings(
+        &self,
+        user_id: &str,
+        settings: &NotificationSettings,
+    ) -> RepositoryResult<()>;
+
+    /// Settings of the users in `user_ids` that changed them.
+    async fn settings_for(
+        &self,
+        user_ids: &[String],
+    ) -> RepositoryResult<HashMap<String, NotificationSettings>>;
+
+    /// Keeps a push for the digest sent after quiet hours.
+    async fn hold_push(
+        &self,
+        user_id: &str,
+        title: &str,
+        body: &str,
+    ) -> RepositoryResult<()>;
+
+    /// Deletes or archives, as `rule` says, up to `limit` notifications
+    /// older than its days: of type `kind`, or of every type but the
+    /// `overridden` ones when `kind` is `None`. Returns how many of each
+    /// type, legacy untyped ones being refuels.
+    async fn prune(
+        &self,
+        kind: Option<&str>,
+        overridden: &[&str],
+        rule: &RetentionRule,
+        limit: i64,
+    ) -> RepositoryResult<Vec<(String, u64)>>;
+}
+
+/// Responses to calls made with an `Idempotency-Key`, each key belonging to
+/// a `scope` (the user or address that made the call).
+#[async_trait]
+pub trait IdempotencyRepository: Send + Sync {
+    /// Claims `key` for the call hashing to `request_hash`. Keys older than
+    /// `ttl`, or still in progress after `abandoned_after`, are claimed
+    /// anew.
+    async fn reserve(
+        &self,
+        scope: &str,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+        abandoned_after: Duration,
+    ) -> RepositoryResult<Reservation>;
+
+    async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        response: &StoredResponse,
+    ) -> RepositoryResult<()>;
+
+    /// Forgets `key`, for calls that failed on our side.
+    async fn release(&self, scope: &str, key: &str) -> RepositoryResult<()>;
+}
+
+/// The security audit log: events can be added, never changed.
+#[async_trait]
+pub trait AuditRepository: Send + Sync {
+    async fn append(&self, event: NewAuditEvent) -> RepositoryResult<()>;
+
+    /// Newest first.
+    async fn list(&self, filter: &AuditFilter) -> RepositoryResult<Vec<AuditEvent>>;
+}
+
+/// The user events waiting to be published by the outbox relay. Events are
+/// written by the repositories making the change they're about.
+#[async_trait]
+pub trait OutboxRepository: Send + Sync {
+    /// Leases up to `limit` due events, oldest first, for `lease`: no other
+    /// relay gets them until they're marked or the lease runs out. Only the
+    /// oldest unpublished event of each user is ever handed out.
+    async fn claim(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> RepositoryResult<Vec<OutboxEvent>>;
+
+    async fn mark_published(&self, id: i64) -> RepositoryResult<()>;
+
+    /// Puts the event back for another attempt in `retry_in`.
+    async fn mark_failed(
+        &self,
+        id: i64,
+        error: &str,
+        retry_in: Duration,
+    ) -> RepositoryResult<()>;
+
+    /// Deletes the events published more than `older_than` ago, returning
+    /// how many.
+    async fn purge_published(&self, older_than: Duration) -> RepositoryResult<u64>;
+}
+
+/// Webhook subscriptions, and the log of what was sent to them.
+#[async_trait]
+pub trait WebhookRepository: Send + Sync {
+    async fn create(
+        &self,
+        subscription: NewWebhookSubscription,
+    ) -> RepositoryResult<WebhookSubscription>;
+
+    /// Oldest first.
+    async fn list(&self) -> RepositoryResult<Vec<WebhookSubscription>>;
+
+    async fn get(&self, id: &str) -> RepositoryResult<Option<WebhookSubscription>>;
+
+    /// `None` when there's no such subscription.
+    async fn update(
+        &self,
+        id: &str,
+        update: WebhookUpdate,
+    ) -> RepositoryResult<Option<WebhookSubscription>>;
+
+    async fn delete(&self, id: &str) -> RepositoryResult<bool>;
+
+    /// Newest first.
+    async fn deliveries(
+        &self,
+        subscription_id: &str,
+        filter: &DeliveryFilter,
+    ) -> RepositoryResult<Vec<WebhookDelivery>>;
+
+    /// Queues the delivery to be sent again right away, `None` when it's
+    /// not one of the subscription's.
+    async fn redeliver(
+        &self,
+        subscription_id: &str,
+        delivery_id: i64,
+    ) -> RepositoryResult<Option<WebhookDelivery>>;
+
+    /// Leases up to `limit` due deliveries of enabled subscriptions, oldest
+    /// first, for `lease`. Only the oldest pending delivery of each user is
+    /// handed out for a subscription.
+    async fn claim(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> RepositoryResult<Vec<DueDelivery>>;
+
+    /// Logs `attempt`, and moves its delivery on according to `outcome`,
+    /// ending the lease.
+    async fn record(
+        &self,
+        attempt: &WebhookAttempt,
+        outcome: Outcome,
+    ) -> RepositoryResult<()>;
+
+    /// Deletes the delivered and dead deliveries created more than
+    /// `older_than` ago, returning how many.
+    async fn purge(&self, older_than: Duration) -> RepositoryResult<u64>;
+}
@@ -0,0 +1,564 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{Pool, Postgres, Transaction};
+
+use crate::web::{
+    dto::me::{
+        notification_settings::NotificationSettings,
+        notifications::Notification,
+    },
+    jobs::retention::RetentionRule,
+    locale::Locale,
+    models::{
+        audit_events::{self, AuditEvent, AuditFilter, NewAuditEvent},
+        idempotency_keys::{self, Reservation, StoredResponse},
+        notification_settings,
+        notifications::{self, CreatedNotification, InsertedBatch, MarkedRead},
+        outbox::{self, NewOutboxEvent, OutboxEvent},
+        users::{RecipientModel, User, UserModel},
+        webhooks::{
+            self, DeliveryFilter, DueDelivery, NewWebhookSubscription, Outcome,
+            WebhookAttempt, WebhookDelivery, WebhookSubscription, WebhookUpdate,
+        },
+    },
+    repositories::{
+        AuditRepository, IdempotencyRepository, NewUser, NotificationRepository,
+        OutboxRepository, RepositoryError, RepositoryResult, UserRepository,
+        WebhookRepository,
+    },
+};
+
+/// Unique violations are the only database errors handlers act upon.
+fn conflict(err: sqlx::Error) -> RepositoryError {
+    match err {
+        sqlx::Error::Database(db_err)
+            if db_err.code().as_deref() == Some("23505") =>
+        {
+            RepositoryError::Conflict
+        }
+        err => RepositoryError::Database(err),
+    }
+}
+
+/// The user as `tx` left them, e.g. with the `disabled_at` set by the
+/// database.
+async fn reloaded(
+    tx: &mut Transaction<'_, Postgres>,
+    id: &str,
+) -> RepositoryResult<User> {
+    Ok(User::from_id(&mut **tx, id)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)?)
+}
+
+#[derive(Clone)]
+pub struct PgUserRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgUserRepository {
+    pub fn new(pool: Pool<Postgres>) -> PgUserRepository {
+        PgUserRepository { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn by_id(&self, id: &str) -> RepositoryResult<Option<User>> {
+        Ok(User::from_id(&self.pool, id).await?)
+    }
+
+    async fn by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        Ok(User::from_email(&self.pool, email).await?)
+    }
+
+    async fn register(&self, user: NewUser) -> RepositoryResult<()> {
+        // the rest of PIENO hears about it if and only if it's committed
+        let mut tx = self.pool.begin().await?;
+        User::register(&mut *tx, &user)
+            .await
+            .map_err(conflict)?;
+        let event = NewOutboxEvent::user_registered(UserModel {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            surname: user.surname,
+            propic_url: None,
+            locale: user.locale,
+            time_zone: user.time_zone,
+        })?;
+        outbox::enqueue(&mut *tx, &event).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn update_preferences(
+        &self,
+        user: &mut User,
+        locale: Option<Locale>,
+        time_zone: Option<&str>,
+    ) -> RepositoryResult<()> {
+        let before = (user.locale.clone(), user.time_zone.clone());
+        let mut tx = self.pool.begin().await?;
+        user.update_preferences(&mut *tx, locale, time_zone).await?;
+        if before != (user.locale.clone(), user.time_zone.clone()) {
+            let event = NewOutboxEvent::user_updated(user.clone())?;
+            outbox::enqueue(&mut *tx, &event).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn set_disabled(
+        &self,
+        user: &mut User,
+        disabled: bool,
+    ) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        user.set_disabled(&mut *tx, disabled).await?;
+        *user = reloaded(&mut tx, &user.id).await?;
+        outbox::enqueue(&mut *tx, &NewOutboxEvent::user_updated(user.clone())?)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn set_password(
+        &self,
+        user: &mut User,
+        password_hash: &str,
+    ) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        user.set_password(&mut *tx, password_hash).await?;
+        *user = reloaded(&mut tx, &user.id).await?;
+        outbox::enqueue(&mut *tx, &NewOutboxEvent::user_updated(user.clone())?)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn add_fcm_token(
+        &self,
+        user_id: &str,
+        token: &str,
+    ) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        User::add_fcm_token(&mut *tx, user_id, token)
+            .await
+            .map_err(conflict)?;
+        outbox::enqueue(&mut *tx, &NewOutboxEvent::fcm_token_added(user_id, token))
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn fcm_tokens(
+        &self,
+        user_ids: &[String],
+    ) -> RepositoryResult<Vec<(String, String)>> {
+        Ok(notifications::fcm_tokens(&self.pool, user_ids).await?)
+    }
+
+    async fn recipients(
+        &self,
+        ids: &[String],
+    ) -> RepositoryResult<Vec<RecipientModel>> {
+        Ok(User::recipients(&self.pool, ids).await?)
+    }
+}
+
+#[derive(Clone)]
+pub struct PgNotificationRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgNotificationRepository {
+    pub fn new(pool: Pool<Postgres>) -> PgNotificationRepository {
+        PgNotificationRepository { pool }
+    }
+}
+
+#[async_trait]
+impl NotificationRepository for PgNotificationRepository {
+    async fn list(
+        &self,
+        user_id: &str,
+        archived: bool,
+    ) -> RepositoryResult<Vec<Notification>> {
+        Ok(notifications::list(&self.pool, user_id, archived).await?)
+    }
+
+    async fn get(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<Option<Notification>> {
+        Ok(notifications::get(&self.pool, user_id, notification_id).await?)
+    }
+
+    async fn list_after(
+        &self,
+        user_id: &str,
+        after_id: &str,
+    ) -> RepositoryResult<Vec<Notification>> {
+        Ok(notifications::list_after(&self.pool, user_id, after_id).await?)
+    }
+
+    async fn list_since(
+        &self,
+        user_id: &str,
+        since: DateTime<Utc>,
+    ) -> RepositoryResult<Vec<Notification>> {
+        Ok(notifications::list_since(&self.pool, user_id, since).await?)
+    }
+
+    async fn count_unread(&self, user_id: &str) -> RepositoryResult<i64> {
+        Ok(notifications::count_unread(&self.pool, user_id).await?)
+    }
+
+    async fn mark_read(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<Option<MarkedRead>> {
+        // counted within the same transaction, so it includes this change
+        let mut tx = self.pool.begin().await?;
+        let marked =
+            notifications::mark_read(&mut *tx, user_id, notification_id).await?;
+        if !marked && !notifications::exists(&mut *tx, user_id, notification_id).await? {
+            return Ok(None);
+        }
+        let unread = notifications::count_unread(&mut *tx, user_id).await?;
+        tx.commit().await?;
+
+        Ok(Some(MarkedRead {
+            marked: marked as u64,
+            unread,
+        }))
+    }
+
+    async fn mark_read_until(
+        &self,
+        user_id: &str,
+        until: Option<&str>,
+    ) -> RepositoryResult<Option<MarkedRead>> {
+        // the cursor can't go away between checking it and using it, and
+        // the count includes what was just marked
+        let mut tx = self.pool.begin().await?;
+        if let Some(until) = until {
+            if !notifications::exists(&mut *tx, user_id, until).await? {
+                return Ok(None);
+            }
+        }
+        let marked =
+            notifications::mark_read_until(&mut *tx, user_id, until).await?;
+        let unread = notifications::count_unread(&mut *tx, user_id).await?;
+        tx.commit().await?;
+
+        Ok(Some(MarkedRead { marked, unread }))
+    }
+
+    async fn delete(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+    ) -> RepositoryResult<bool> {
+        Ok(notifications::delete(&self.pool, user_id, notification_id).await?)
+    }
+
+    async fn set_archived(
+        &self,
+        user_id: &str,
+        notification_id: &str,
+        archived: bool,
+    ) -> RepositoryResult<bool> {
+        Ok(notifications::set_archived(
+            &self.pool,
+            user_id,
+            notification_id,
+            archived,
+        )
+        .await?)
+    }
+
+    async fn insert_batch(
+        &self,
+        recipients: &[String],
+        archived: &[String],
+        payload: &Value,
+        idempotency_key: Option<&str>,
+    ) -> RepositoryResult<Option<InsertedBatch>> {
+        let mut tx = self.pool.begin().await?;
+        let mut existing = vec![];
+        if let Some(key) = idempotency_key {
+            notifications::lock_idempotency_key(&mut *tx, key).await?;
+            let stored =
+                notifications::find_by_idempotency_key(&mut *tx, key, payload)
+                    .await?;
+            if stored.iter().any(|n| !n.same_payload) {
+                return Ok(None);
+            }
+            existing = stored
+                .into_iter()
+                .map(|n| CreatedNotification {
+                    id: n.id,
+                    to_user: n.to_user,
+                })
+                .collect();
+        }
+        let created = notifications::insert_batch(
+            &mut *tx,
+            recipients,
+            archived,
+            payload,
+            idempotency_key,
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(InsertedBatch { created, existing }))
+    }
+
+    async fn settings(
+        &self,
+        user_id: &str,
+    ) -> RepositoryResult<NotificationSettings> {
+        Ok(notification_settings::get(&self.pool, user_id).await?)
+    }
+
+    async fn save_settings(
+        &self,
+        user_id: &str,
+        settings: &NotificationSettings,
+    ) -> RepositoryResult<()> {
+        Ok(notification_settings::save(&self.pool, user_id, settings).await?)
+    }
+
+    async fn settings_for(
+        &self,
+        user_ids: &[String],
+    ) -> RepositoryResult<HashMap<String, NotificationSettings>> {
+        Ok(notification_settings::for_users(&self.pool, user_ids).await?)
+    }
+
+    async fn hold_push(
+        &self,
+        user_id: &str,
+        title: &str,
+        body: &str,
+    ) -> RepositoryResult<()> {
+        Ok(notification_settings::hold_push(&self.pool, user_id, title, body)
+            .await?)
+    }
+
+    async fn prune(
+        &self,
+        kind: Option<&str>,
+        overridden: &[&str],
+        rule: &RetentionRule,
+        limit: i64,
+    ) -> RepositoryResult<Vec<(String, u64)>> {
+        let counts =
+            notifications::prune(&self.pool, kind, overridden, rule, limit)
+                .await?;
+        Ok(counts
+            .into_iter()
+            .map(|(kind, count)| (kind, count as u64))
+            .collect())
+    }
+}
+
+#[derive(Clone)]
+pub struct PgIdempotencyRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgIdempotencyRepository {
+    pub fn new(pool: Pool<Postgres>) -> PgIdempotencyRepository {
+        PgIdempotencyRepository { pool }
+    }
+}
+
+#[async_trait]
+impl IdempotencyRepository for PgIdempotencyRepository {
+    async fn reserve(
+        &self,
+        scope: &str,
+        key: &str,
+        request_hash: &str,
+        ttl: Duration,
+        abandoned_after: Duration,
+    ) -> RepositoryResult<Reservation> {
+        Ok(idempotency_keys::reserve(
+            &self.pool,
+            scope,
+            key,
+            request_hash,
+            ttl,
+            abandoned_after,
+        )
+        .await?)
+    }
+
+    async fn complete(
+        &self,
+        scope: &str,
+        key: &str,
+        response: &StoredResponse,
+    ) -> RepositoryResult<()> {
+        Ok(idempotency_keys::complete(&self.pool, scope, key, response).await?)
+    }
+
+    async fn release(&self, scope: &str, key: &str) -> RepositoryResult<()> {
+        Ok(idempotency_keys::release(&self.pool, scope, key).await?)
+    }
+}
+
+#[derive(Clone)]
+pub struct PgAuditRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgAuditRepository {
+    pub fn new(pool: Pool<Postgres>) -> PgAuditRepository {
+        PgAuditRepository { pool }
+    }
+}
+
+#[async_trait]
+impl AuditRepository for PgAuditRepository {
+    async fn append(&self, event: NewAuditEvent) -> RepositoryResult<()> {
+        Ok(audit_events::append(&self.pool, &event).await?)
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> RepositoryResult<Vec<AuditEvent>> {
+        Ok(audit_events::list(&self.pool, filter).await?)
+    }
+}
+
+#[derive(Clone)]
+pub struct PgOutboxRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgOutboxRepository {
+    pub fn new(pool: Pool<Postgres>) -> PgOutboxRepository {
+        PgOutboxRepository { pool }
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for PgOutboxRepository {
+    async fn claim(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> RepositoryResult<Vec<OutboxEvent>> {
+        Ok(outbox::claim(&self.pool, limit, lease).await?)
+    }
+
+    async fn mark_published(&self, id: i64) -> RepositoryResult<()> {
+        Ok(outbox::mark_published(&self.pool, id).await?)
+    }
+
+    async fn mark_failed(
+        &self,
+        id: i64,
+        error: &str,
+        retry_in: Duration,
+    ) -> RepositoryResult<()> {
+        Ok(outbox::mark_failed(&self.pool, id, error, retry_in).await?)
+    }
+
+    async fn purge_published(&self, older_than: Duration) -> RepositoryResult<u64> {
+        Ok(outbox::purge_published(&self.pool, older_than).await?)
+    }
+}
+
+pub struct PgWebhookRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PgWebhookRepository {
+    pub fn new(pool: Pool<Postgres>) -> PgWebhookRepository {
+        PgWebhookRepository { pool }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for PgWebhookRepository {
+    async fn create(
+        &self,
+        subscription: NewWebhookSubscription,
+    ) -> RepositoryResult<WebhookSubscription> {
+        Ok(webhooks::create(&self.pool, &subscription).await?)
+    }
+
+    async fn list(&self) -> RepositoryResult<Vec<WebhookSubscription>> {
+        Ok(webhooks::list(&self.pool).await?)
+    }
+
+    async fn get(&self, id: &str) -> RepositoryResult<Option<WebhookSubscription>> {
+        Ok(webhooks::find(&self.pool, id).await?)
+    }
+
+    async fn update(
+        &self,
+        id: &str,
+        update: WebhookUpdate,
+    ) -> RepositoryResult<Option<WebhookSubscription>> {
+        Ok(webhooks::update(&self.pool, id, &update).await?)
+    }
+
+    async fn delete(&self, id: &str) -> RepositoryResult<bool> {
+        Ok(webhooks::delete(&self.pool, id).await?)
+    }
+
+    async fn deliveries(
+        &self,
+        subscription_id: &str,
+        filter: &DeliveryFilter,
+    ) -> RepositoryResult<Vec<WebhookDelivery>> {
+        Ok(webhooks::deliveries(&self.pool, subscription_id, filter).await?)
+    }
+
+    async fn redeliver(
+        &self,
+        subscription_id: &str,
+        delivery_id: i64,
+    ) -> RepositoryResult<Option<WebhookDelivery>> {
+        Ok(webhooks::redeliver(&self.pool, subscription_id, delivery_id).await?)
+    }
+
+    async fn claim(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> RepositoryResult<Vec<DueDelivery>> {
+        Ok(webhooks::claim(&self.pool, limit, lease).await?)
+    }
+
+    async fn record(
+        &self,
+        attempt: &WebhookAttempt,
+        outcome: Outcome,
+    ) -> RepositoryResult<()> {
+        let mut tx = self.pool.begin().await?;
+        webhooks::record(&mut tx, attempt, outcome).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn purge(&self, older_than: Duration) -> RepositoryResult<u64> {
+        Ok(webhooks::purge(&self.pool, older_than).await?)
+    }
+}
@@ -0,0 +1,18 @@
+use axum::routing::{delete, get, patch, post};
+
+use crate::web::routes::table::RouteTable;
+
+pub mod root;
+
+pub fn admin_routes(table: RouteTable) -> RouteTable {
+    table
+        .route::<root::__path_list_audit_events>(|_| get(root::list_audit_events))
+        .route::<root::__path_list_webhooks>(|_| get(root::list_webhooks))
+        .route::<root::__path_create_webhook>(|_| post(root::create_webhook))
+        .route::<root::__path_update_webhook>(|_| patch(root::update_webhook))
+        .route::<root::__path_delete_webhook>(|_| delete(root::delete_webhook))
+        .route::<root::__path_list_webhook_deliveries>(|_| {
+            get(root::list_webhook_deliveries)
+        })
+        .route::<root::__path_redeliver_webhook>(|_| post(root::redeliver_webhook))
+}
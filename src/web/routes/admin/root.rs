@@ -0,0 +1,279 @@
+use axum::extract::{Path, State};
+use nanoid::nanoid;
+use serde_json::{json, Value};
+
+use crate::web::{
+    audit,
+    dto::admin::{
+        audit_events_query::{AuditEventsQuery, AuditEventsResponse},
+        create_webhook_request::{
+            CreateWebhookRequest, CreateWebhookResponse, WebhooksResponse,
+        },
+        update_webhook_request::{UpdateWebhookRequest, WebhookResponse},
+        webhook_deliveries_query::{
+            WebhookDeliveriesQuery, WebhookDeliveriesResponse,
+            WebhookDeliveryResponse,
+        },
+    },
+    errors::{code::ErrorCode, HttpError},
+    extractors::{
+        admin::Admin, validate_body::ValidatedJson,
+        validate_query::ValidatedQuery,
+    },
+    models::{
+        audit_events::{Actor, AuditFilter, AuditKind, NewAuditEvent},
+        webhooks::NewWebhookSubscription,
+    },
+    versions::{ApiVersion, Versioned},
+    AppState,
+};
+
+#[utoipa::path(
+    get,
+    path="/admin/audit-events",
+    responses(
+        (status = 200, description = "The audit events matching every filter given, newest first", body = AuditEventsResponse),
+        (status = 400, description = "Malformed query string"),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+    ),
+    params(AuditEventsQuery),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn list_audit_events(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    _: Admin,
+    ValidatedQuery(query): ValidatedQuery<AuditEventsQuery>,
+) -> Result<Versioned<AuditEventsResponse>, HttpError> {
+    let events = s.audit.list(&AuditFilter::from(query)).await?;
+
+    Ok(Versioned(version, AuditEventsResponse {
+        success: true,
+        events,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path="/admin/webhooks",
+    responses(
+        (status = 200, description = "Every webhook subscription, oldest first", body = WebhooksResponse),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+    ),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn list_webhooks(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    _: Admin,
+) -> Result<Versioned<WebhooksResponse>, HttpError> {
+    Ok(Versioned(version, WebhooksResponse {
+        success: true,
+        webhooks: s.webhooks.list().await?,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path="/admin/webhooks",
+    request_body = CreateWebhookRequest,
+    responses(
+        (status = 200, description = "Webhook created. Its secret is only shown here", body = CreateWebhookResponse),
+        (status = 400, description = "Invalid URL, event type or secret"),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+    ),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn create_webhook(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Admin(service): Admin,
+    ValidatedJson(body): ValidatedJson<CreateWebhookRequest>,
+) -> Result<Versioned<CreateWebhookResponse>, HttpError> {
+    let secret = body.secret.unwrap_or_else(|| nanoid!(40));
+    let webhook = s
+        .webhooks
+        .create(NewWebhookSubscription {
+            url: body.url,
+            events: body.events,
+            secret: secret.clone(),
+            description: body.description,
+        })
+        .await?;
+    let event = NewAuditEvent::new(AuditKind::WebhookCreated, Actor::Service(service))
+        .details(json!({
+            "webhook_id": webhook.id,
+            "url": webhook.url,
+            "events": webhook.events,
+        }));
+    audit::record(&*s.audit, event).await;
+
+    Ok(Versioned(version, CreateWebhookResponse {
+        success: true,
+        webhook,
+        secret,
+    }))
+}
+
+#[utoipa::path(
+    patch,
+    path="/admin/webhooks/{id}",
+    request_body = UpdateWebhookRequest,
+    responses(
+        (status = 200, description = "Webhook updated", body = WebhookResponse),
+        (status = 400, description = "Invalid URL, event type or secret"),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+        (status = 404, description = "Webhook not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Webhook id"),
+    ),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn update_webhook(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Admin(service): Admin,
+    Path(id): Path<String>,
+    ValidatedJson(body): ValidatedJson<UpdateWebhookRequest>,
+) -> Result<Versioned<WebhookResponse>, HttpError> {
+    // what was asked for, but the secret itself
+    let changes = json!({
+        "url": body.url,
+        "events": body.events,
+        "enabled": body.enabled,
+        "description": body.description,
+        "secret_changed": body.secret.is_some(),
+    });
+    let webhook = s
+        .webhooks
+        .update(&id, body.into())
+        .await?
+        .ok_or(HttpError::Simple(ErrorCode::WebhookNotFound))?;
+    let event = NewAuditEvent::new(AuditKind::WebhookUpdated, Actor::Service(service))
+        .details(json!({"webhook_id": id, "changes": changes}));
+    audit::record(&*s.audit, event).await;
+
+    Ok(Versioned(version, WebhookResponse {
+        success: true,
+        webhook,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path="/admin/webhooks/{id}",
+    responses(
+        (status = 200, description = "Webhook deleted, along with its deliveries"),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+        (status = 404, description = "Webhook not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Webhook id"),
+    ),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn delete_webhook(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Admin(service): Admin,
+    Path(id): Path<String>,
+) -> Result<Versioned<Value>, HttpError> {
+    if s.webhooks.delete(&id).await? {
+        let event = NewAuditEvent::new(AuditKind::WebhookDeleted, Actor::Service(service))
+            .details(json!({"webhook_id": id}));
+        audit::record(&*s.audit, event).await;
+        Ok(Versioned(version, json!({"success": true})))
+    } else {
+        Err(HttpError::Simple(ErrorCode::WebhookNotFound))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path="/admin/webhooks/{id}/deliveries",
+    responses(
+        (status = 200, description = "The webhook's deliveries, newest first, each with the log of its attempts", body = WebhookDeliveriesResponse),
+        (status = 400, description = "Malformed query string"),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+        (status = 404, description = "Webhook not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Webhook id"),
+        WebhookDeliveriesQuery,
+    ),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn list_webhook_deliveries(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    _: Admin,
+    Path(id): Path<String>,
+    ValidatedQuery(query): ValidatedQuery<WebhookDeliveriesQuery>,
+) -> Result<Versioned<WebhookDeliveriesResponse>, HttpError> {
+    if s.webhooks.get(&id).await?.is_none() {
+        return Err(HttpError::Simple(ErrorCode::WebhookNotFound));
+    }
+    let deliveries = s.webhooks.deliveries(&id, &query.into()).await?;
+
+    Ok(Versioned(version, WebhookDeliveriesResponse {
+        success: true,
+        deliveries,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path="/admin/webhooks/{id}/deliveries/{delivery_id}/redeliver",
+    responses(
+        (status = 200, description = "Delivery queued to be sent again right away, with a fresh set of attempts", body = WebhookDeliveryResponse),
+        (status = 401, description = "No admin token sent, or an invalid one"),
+        (status = 403, description = "The admin token isn't of one of `ADMIN_SERVICES`"),
+        (status = 404, description = "Webhook delivery not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Webhook id"),
+        ("delivery_id" = i64, Path, description = "Delivery id"),
+    ),
+    security(
+        ("adminAuth" = [])
+    )
+)]
+pub async fn redeliver_webhook(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Admin(service): Admin,
+    Path((id, delivery_id)): Path<(String, i64)>,
+) -> Result<Versioned<WebhookDeliveryResponse>, HttpError> {
+    let delivery = s
+        .webhooks
+        .redeliver(&id, delivery_id)
+        .await?
+        .ok_or(HttpError::Simple(ErrorCode::WebhookDeliveryNotFound))?;
+    let event = NewAuditEvent::new(AuditKind::WebhookRedelivered, Actor::Service(service))
+        .details(json!({"webhook_id": id, "delivery_id": delivery_id}));
+    audit::record(&*s.audit, event).await;
+
+    Ok(Versioned(version, WebhookDeliveryResponse {
+        success: true,
+        delivery,
+    }))
+}
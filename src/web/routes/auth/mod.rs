@@ -1,12 +1,14 @@
-use axum::{routing::{get, post, put}, Router};
-use crate::web::AppState;
+use axum::{middleware, routing::{get, post, put}};
+use crate::web::{middlewares::idempotency::idempotency, routes::table::RouteTable, AppState};
 pub mod root;
 
-pub fn auth_routes(state: &AppState) -> Router {
-    Router::new()
-        .route("/auth", get(root::index))
-        .route("/auth/register", post(root::register))
-        .route("/auth/login", post(root::login))
-        .route("/auth/fcm", put(root::add_fcm_token))
-        .with_state(state.clone())
-}
\ No newline at end of file
+pub fn auth_routes(table: RouteTable) -> RouteTable {
+    // what mobile clients retry on flaky networks
+    let idempotent = |state: &AppState| middleware::from_fn_with_state(state.clone(), idempotency);
+
+    table
+        .route::<root::__path_index>(|_| get(root::index))
+        .route::<root::__path_register>(|state| post(root::register).layer(idempotent(state)))
+        .route::<root::__path_login>(|_| post(root::login))
+        .route::<root::__path_add_fcm_token>(|state| put(root::add_fcm_token).layer(idempotent(state)))
+}
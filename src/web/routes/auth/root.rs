@@ -1,4 +1,5 @@
 use crate::web::{
+    audit,
     dto::{
         auth::{
             logged_user_response::LoggedUserResponse,
@@ -9,20 +10,29 @@ use crate::web::{
         user_claims::UserClaims,
         Claim,
     },
-    errors::HttpError,
+    errors::{code::ErrorCode, HttpError},
     extractors::{token::Token, validate_body::ValidatedJson},
-    models::users::{User, UserModel},
+    i18n,
+    metrics::{LOGINS, TOKENS_ISSUED},
+    models::{
+        audit_events::{Actor, AuditKind, NewAuditEvent},
+        users::{User, UserModel},
+    },
+    repositories::{NewUser, RepositoryError},
     util::{hash_password, verify_password},
+    versions::{ApiVersion, Versioned},
     AppState,
 };
-use axum::{debug_handler, extract::State, http::StatusCode, Json};
+use axum::{debug_handler, extract::State};
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 
 #[utoipa::path(
     get,
-    path="/auth/",
+    path="/auth",
     responses(
         (status = 200, description = "Gets logged user", body = LoggedUserResponse),
+        (status = 401, description = "Invalid token sent"),
         (status = 404, description = "User not found")
     ),
     security(
@@ -31,131 +41,151 @@ use serde_json::{json, Value};
 )]
 pub async fn index(
     State(s): State<AppState>,
+    version: ApiVersion,
     Token(user): Token<Claim<UserClaims>>,
-) -> Result<Json<LoggedUserResponse>, HttpError> {
-    let mut conn = s.pool.acquire().await?;
-    if let Some(user) = User::from_id(&mut *conn, &user.data().user_id).await? {
-        Ok(Json(LoggedUserResponse {
+) -> Result<Versioned<LoggedUserResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        Ok(Versioned(version, LoggedUserResponse {
             success: true,
-            user: UserModel {
-                email: user.email,
-                name: user.name,
-                surname: user.surname,
-                id: user.id,
-                propic_url: user.propic_url,
-            },
+            user: UserModel::from(user),
         }))
     } else {
-        Err(HttpError::Simple(
-            StatusCode::NOT_FOUND,
-            "user_not_found".to_string(),
-        ))
+        Err(HttpError::Simple(ErrorCode::UserNotFound))
     }
 }
 
 #[utoipa::path(
     post,
     path="/auth/login",
+    request_body = LoginRequest,
     responses(
         (status = 200, description = "Login successful. Outputs a token the user must use to make authenticated requests.", body = LoginResponse),
+        (status = 400, description = "Malformed body, or not an email"),
         (status = 401, description = "Invalid credentials: either the email and/or the password is invalid."),
-    ),
-    params(
-        ("email" = String, Path, description = "User email"),
-        ("password" = String, Path, description = "User password"),
+        (status = 403, description = "The account has been disabled."),
     ),
 )]
 pub async fn login(
     State(s): State<AppState>,
+    version: ApiVersion,
     ValidatedJson(body): ValidatedJson<LoginRequest>,
-) -> Result<Json<LoginResponse>, HttpError> {
-    let mut conn = s.pool.acquire().await?;
+) -> Result<Versioned<LoginResponse>, HttpError> {
+    let Some(user) = s.users.by_email(&body.email).await? else {
+        LOGINS.with_label_values(&["failure"]).inc();
+        // whatever was typed, it could be somebody's password: only its
+        // fingerprint, to tell repeated attempts apart
+        let email_sha256 = hex::encode(Sha256::digest(body.email.as_bytes()));
+        let event = NewAuditEvent::new(AuditKind::LoginFailure, Actor::Anonymous)
+            .details(json!({"reason": "unknown_email", "email_sha256": email_sha256}));
+        audit::record(&*s.audit, event).await;
+        return Err(HttpError::Simple(ErrorCode::InvalidCredentials));
+    };
+    let failure = |reason: &str| {
+        NewAuditEvent::new(AuditKind::LoginFailure, Actor::Anonymous)
+            .user(&user.id)
+            .details(json!({"reason": reason}))
+    };
 
-    if let Some(user) = User::from_email(&mut *conn, &body.email).await? {
-        let verify = verify_password(&body.password, &user.password).await?;
-        if !verify {
-            return Err(HttpError::Simple(
-                StatusCode::UNAUTHORIZED,
-                "invalid_credentials".to_string(),
-            ));
-        }
+    let verify = verify_password(&body.password, &user.password).await?;
+    if !verify {
+        LOGINS.with_label_values(&["failure"]).inc();
+        audit::record(&*s.audit, failure("wrong_password")).await;
+        return Err(HttpError::Simple(ErrorCode::InvalidCredentials));
+    }
+    // only told to whoever knows the password
+    if user.disabled_at.is_some() {
+        LOGINS.with_label_values(&["failure"]).inc();
+        audit::record(&*s.audit, failure("account_disabled")).await;
+        return Err(HttpError::Simple(ErrorCode::AccountDisabled));
+    }
 
-        let token =
-            Token::<Claim<UserClaims>>::generate(Claim::from(UserClaims {
-                user_id: user.id,
-                name: user.name,
-                surname: user.surname,
-                propic_url: user.propic_url,
-            }))
-            .await?;
+    let event = NewAuditEvent::new(AuditKind::LoginSuccess, Actor::User(user.id.clone()))
+        .user(&user.id);
+    let token =
+        Token::<Claim<UserClaims>>::generate(Claim::from(UserClaims {
+            user_id: user.id,
+            name: user.name,
+            surname: user.surname,
+            propic_url: user.propic_url,
+            generation: user.token_generation,
+        }, s.config.jwt.timeout), &s.config.jwt)
+        .await?;
+    LOGINS.with_label_values(&["success"]).inc();
+    TOKENS_ISSUED.with_label_values(&["login"]).inc();
+    audit::record(&*s.audit, event).await;
 
-        Ok(Json(LoginResponse {
-            success: true,
-            token: token,
-        }))
-    } else {
-        Err(HttpError::Simple(
-            StatusCode::UNAUTHORIZED,
-            "invalid_credentials".to_string(),
-        ))
-    }
+    Ok(Versioned(version, LoginResponse {
+        success: true,
+        token,
+    }))
 }
 
 #[utoipa::path(
     post,
     path="/auth/register",
+    request_body = RegisterRequest,
     responses(
         (status = 200, description = "Registration successful. Outputs a token the user must use to make authenticated requests.", body = RegisterResponse),
-        (status = 409, description = "The email is already registered."),
+        (status = 400, description = "Malformed body, fields that don't pass validation, or an invalid idempotency key"),
+        (status = 409, description = "The email is already registered, or a call with the same idempotency key is still being handled."),
+        (status = 422, description = "The idempotency key was already used with a different body"),
     ),
     params(
-        ("email" = String, Path, description = "User email"),
-        ("name" = String, Path, description = "User name"),
-        ("surname" = String, Path, description = "User surname"),
-        ("password" = String, Path, description = "User password"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe: calling again with the same key and body replays the first response, marked by `Idempotent-Replayed: true`"),
     ),
 )]
 pub async fn register(
     State(s): State<AppState>,
+    version: ApiVersion,
     ValidatedJson(body): ValidatedJson<RegisterRequest>,
-) -> Result<Json<RegisterResponse>, HttpError> {
-    let mut tx = s.pool.begin().await?;
-
+) -> Result<Versioned<RegisterResponse>, HttpError> {
     let hashed_password = hash_password(&body.password).await?;
-    let user_id = User::register(
-        &mut *tx,
-        &body.email,
-        &body.name,
-        &body.surname,
-        &hashed_password,
-    )
-    .await?;
-
+    // signed first: nobody gets registered without getting their token
+    let user_id = User::new_id();
     let token = Token::<Claim<UserClaims>>::generate(Claim::from(UserClaims {
-        user_id: user_id,
-        name: body.name,
-        surname: body.surname,
+        user_id: user_id.clone(),
+        name: body.name.clone(),
+        surname: body.surname.clone(),
         propic_url: None,
-    }))
+        // nothing was revoked yet
+        generation: 0,
+    }, s.config.jwt.timeout), &s.config.jwt)
     .await?;
+    s.users
+        .register(NewUser {
+            id: user_id.clone(),
+            email: body.email,
+            name: body.name.clone(),
+            surname: body.surname.clone(),
+            password_hash: hashed_password,
+            locale: body.locale,
+            time_zone: body.time_zone.unwrap_or("UTC".to_string()),
+        })
+        .await?;
+    let event = NewAuditEvent::new(AuditKind::UserRegistered, Actor::User(user_id.clone()))
+        .user(&user_id);
+    audit::record(&*s.audit, event).await;
+    TOKENS_ISSUED.with_label_values(&["register"]).inc();
 
-    tx.commit().await?;
-
-    Ok(Json(RegisterResponse {
+    Ok(Versioned(version, RegisterResponse {
         success: true,
-        token: token,
+        token,
     }))
 }
 
 #[utoipa::path(
     put,
     path="/auth/fcm",
+    request_body = PutFcmTokenRequest,
     responses(
         (status = 200, description = "Token successfully inserted."),
-        (status = 400, description = "The account you made the request with is no longer available")
+        (status = 400, description = "Malformed body, an invalid idempotency key, or the account you made the request with is no longer available"),
+        (status = 401, description = "Invalid token sent"),
+        (status = 409, description = "A call with the same idempotency key is still being handled"),
+        (status = 422, description = "The idempotency key was already used with a different body"),
     ),
     params(
-        ("token" = String, Path, description = "The Firebase Cloud Messaging token"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe: calling again with the same key and body replays the first response, marked by `Idempotent-Replayed: true`"),
     ),
     security(
         ("bearerAuth" = [])
@@ -164,44 +194,28 @@ pub async fn register(
 #[debug_handler]
 pub async fn add_fcm_token(
     State(s): State<AppState>,
+    version: ApiVersion,
     Token(user): Token<Claim<UserClaims>>,
     ValidatedJson(body): ValidatedJson<PutFcmTokenRequest>,
-) -> Result<Json<Value>, HttpError> {
-    let mut tx = s.pool.begin().await?;
-    if let Some(user) = User::from_id(&mut *tx, &user.data().user_id).await? {
-        let result = user.add_fcm_token(&mut *tx, &body.token).await;
-        tx.commit().await?;
-
-        if let Err(err) = result {
-            // if the token is already inside the db we want to return 200 OK anyways
-            match err {
-                sqlx_core::Error::Database(db_err) => {
-                    if let Some(code) = db_err.code() {
-                        if code == "23505" {
-                            return Ok(Json(
-                                json!({"success": true, "message": "fcm already in db"}),
-                            ));
-                        } else {
-                            return Err(HttpError::DbError(
-                                sqlx::Error::Database(db_err),
-                            ));
-                        }
-                    } else {
-                        return Err(HttpError::DbError(sqlx::Error::Database(
-                            db_err,
-                        )));
-                    }
-                }
-                e => return Err(HttpError::DbError(e)),
+) -> Result<Versioned<Value>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        match s.users.add_fcm_token(&user.id, &body.token).await {
+            Ok(()) => {
+                let event = NewAuditEvent::new(AuditKind::FcmTokenAdded, Actor::User(user.id.clone()))
+                    .user(&user.id);
+                audit::record(&*s.audit, event).await;
+                Ok(Versioned(version, json!({"success": true})))
             }
+            // if the token is already inside the db we want to return 200 OK anyways
+            Err(RepositoryError::Conflict) => Ok(Versioned(version, json!({
+                "success": true,
+                "message": i18n::t(i18n::current(), "message.fcm_token_known", &[]),
+            }))),
+            Err(err) => Err(err.into()),
         }
-        Ok(Json(json!({"success": true})))
     } else {
         // somebody has forged the token (zamn...)
         // or maybe somebody is trying to make a request with a token that belongs to a deleted account
-        Err(HttpError::Simple(
-            StatusCode::BAD_REQUEST,
-            "account_unavailable".to_string(),
-        ))
+        Err(HttpError::Simple(ErrorCode::AccountUnavailable))
     }
 }
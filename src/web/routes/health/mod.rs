@@ -0,0 +1,11 @@
+use axum::routing::get;
+
+use crate::web::routes::table::RouteTable;
+
+pub mod root;
+
+pub fn health_routes(table: RouteTable) -> RouteTable {
+    table
+        .route::<root::__path_live>(|_| get(root::live))
+        .route::<root::__path_ready>(|_| get(root::ready))
+}
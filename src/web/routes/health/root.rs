@@ -0,0 +1,116 @@
+use std::{collections::BTreeMap, future::Future, time::Duration};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::{
+    db,
+    web::{
+        dto::health::health_response::{CheckResult, HealthResponse},
+        AppState,
+    },
+};
+
+/// Checks taking longer than this count as failed, orchestrators have short
+/// probe timeouts.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[utoipa::path(
+    get,
+    path="/health/live",
+    responses(
+        (status = 200, description = "The process is up. It never checks dependencies, so a database outage doesn't get every replica restarted"),
+    ),
+)]
+pub async fn live() -> Json<Value> {
+    Json(json!({
+        "success": true
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path="/health/ready",
+    responses(
+        (status = 200, description = "Ready to serve requests", body = HealthResponse),
+        (status = 503, description = "Shutting down, or a dependency is unavailable: `checks` tells which, the details are only logged", body = HealthResponse),
+    ),
+)]
+pub async fn ready(
+    State(s): State<AppState>,
+) -> (StatusCode, Json<HealthResponse>) {
+    let mut checks = BTreeMap::new();
+
+    if s.shutdown.is_cancelled() {
+        checks.insert(
+            "shutdown".to_string(),
+            CheckResult::failed("shutting_down"),
+        );
+    } else {
+        let (database, schema, push, mail) = tokio::join!(
+            check("database", async {
+                sqlx::query("select 1").execute(&s.pool).await?;
+                Ok(())
+            }),
+            // somebody could be migrating or reverting under our feet
+            check("schema", async { Ok(db::check_schema(&s.pool).await?) }),
+            // backends that aren't configured aren't checked
+            async {
+                match &s.push {
+                    Some(push) => Some(check("push", push.check()).await),
+                    None => None,
+                }
+            },
+            async {
+                match &s.mailer {
+                    Some(mailer) => Some(check("mail", mailer.check()).await),
+                    None => None,
+                }
+            },
+        );
+
+        checks.insert("database".to_string(), database);
+        checks.insert("schema".to_string(), schema);
+        if let Some(push) = push {
+            checks.insert("push".to_string(), push);
+        }
+        if let Some(mail) = mail {
+            checks.insert("mail".to_string(), mail);
+        }
+    }
+
+    let ready = checks.values().all(|c| c.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(HealthResponse {
+            success: ready,
+            checks,
+        }),
+    )
+}
+
+/// Only whether it passed makes it to the response, anyone can call this:
+/// why it didn't is for the logs.
+async fn check(
+    name: &str,
+    f: impl Future<Output = Result<(), anyhow::Error>>,
+) -> CheckResult {
+    match tokio::time::timeout(CHECK_TIMEOUT, f).await {
+        Ok(Ok(())) => CheckResult::passed(),
+        Ok(Err(e)) => {
+            warn!(check = name, "readiness check failed: {e:#}");
+            CheckResult::failed("unavailable")
+        }
+        Err(_) => {
+            warn!(check = name, "readiness check timed out");
+            CheckResult::failed("timed_out")
+        }
+    }
+}
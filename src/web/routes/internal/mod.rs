@@ -0,0 +1,11 @@
+use axum::routing::post;
+
+use crate::web::routes::table::RouteTable;
+
+pub mod root;
+
+pub fn internal_routes(table: RouteTable) -> RouteTable {
+    table.route::<root::__path_create_notifications>(|_| {
+        post(root::create_notifications)
+    })
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use axum::{extract::State, http::HeaderMap};
+use tracing::{info, Instrument};
+use serde_json::Value;
+
+use crate::web::{
+    dto::{
+        internal::create_notifications_request::{
+            CreateNotificationsRequest, CreateNotificationsResponse,
+        },
+        service_claims::ServiceClaims,
+        Claim,
+    },
+    errors::{code::ErrorCode, HttpError},
+    extractors::{token::Token, validate_body::ValidatedJson},
+    delivery::{self, DeliveryTarget},
+    middlewares::{
+        idempotency::IDEMPOTENCY_KEY, request_id::with_current_context,
+    },
+    models::notifications::{CreatedNotification, InsertedBatch},
+    versions::{ApiVersion, Versioned},
+    AppState,
+};
+
+#[utoipa::path(
+    post,
+    path="/internal/notifications",
+    request_body = CreateNotificationsRequest,
+    responses(
+        (status = 200, description = "Notifications stored for every existing recipient", body = CreateNotificationsResponse),
+        (status = 400, description = "Invalid payload or recipients"),
+        (status = 422, description = "The idempotency key was already used with a different payload"),
+    ),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe: calling again with the same key and payload won't create duplicates"),
+    ),
+    security(
+        ("serviceAuth" = [])
+    )
+)]
+pub async fn create_notifications(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(service): Token<Claim<ServiceClaims>>,
+    headers: HeaderMap,
+    ValidatedJson(body): ValidatedJson<CreateNotificationsRequest>,
+) -> Result<Versioned<CreateNotificationsResponse>, HttpError> {
+    // keys are scoped by service, so two services can't clash
+    let idempotency_key = headers
+        .get(&IDEMPOTENCY_KEY)
+        .and_then(|key| key.to_str().ok())
+        .map(|key| format!("{}:{key}", service.data().service));
+    let payload: Value = serde_json::to_value(&body.payload).map_err(|_| {
+        HttpError::Simple(ErrorCode::InvalidPayload)
+    })?;
+
+    let recipients: Vec<String> = body
+        .recipients
+        .iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .cloned()
+        .collect();
+    let settings = s.notifications.settings_for(&recipients).await?;
+    // users who turned in-app off for this type still get it stored, for the
+    // sake of idempotency, but straight into the archive
+    let hidden: Vec<String> = recipients
+        .iter()
+        .filter(|r| {
+            settings
+                .get(*r)
+                .is_some_and(|s| !s.channels_for(body.payload.kind()).in_app)
+        })
+        .cloned()
+        .collect();
+    // checking the key and storing happen together, so a retry racing with
+    // this one waits for it and replays what it stored
+    let Some(InsertedBatch { created, existing }) = s
+        .notifications
+        .insert_batch(&recipients, &hidden, &payload, idempotency_key.as_deref())
+        .await?
+    else {
+        return Err(HttpError::Simple(ErrorCode::IdempotencyKeyReused));
+    };
+
+    info!(
+        "{} created {} notifications ({} already there)",
+        service.data().service,
+        created.len(),
+        existing.len()
+    );
+
+    // only what's new gets delivered, retries must not buzz phones twice.
+    // each channel is up to the settings, `push` only turns pushes off
+    if !created.is_empty() {
+        let targets = created
+            .iter()
+            .map(|n| DeliveryTarget {
+                user_id: n.to_user.clone(),
+                notification_id: n.id.clone(),
+            })
+            .collect();
+        // tracked, so shutting down waits for pushes that are on their way,
+        // and still part of this request's trace and context
+        s.tasks.spawn(
+            with_current_context(delivery::deliver(
+                s.users.clone(),
+                s.notifications.clone(),
+                s.push.clone().filter(|_| body.push),
+                s.mailer.clone(),
+                body.payload,
+                targets,
+                settings,
+            ))
+            .in_current_span(),
+        );
+    }
+
+    let replayed = !existing.is_empty();
+    let notifications: Vec<CreatedNotification> =
+        existing.into_iter().chain(created).collect();
+    let delivered: HashSet<&str> =
+        notifications.iter().map(|n| n.to_user.as_str()).collect();
+    let skipped = recipients
+        .iter()
+        .filter(|r| !delivered.contains(r.as_str()))
+        .cloned()
+        .collect();
+
+    Ok(Versioned(version, CreateNotificationsResponse {
+        success: true,
+        notifications,
+        skipped,
+        replayed,
+    }))
+}
@@ -1 +1,9 @@
+use axum::routing::get;
+
+use crate::web::routes::table::RouteTable;
+
 pub mod root;
+
+pub fn main_routes(table: RouteTable) -> RouteTable {
+    table.route::<root::__path_index>(|_| get(root::index))
+}
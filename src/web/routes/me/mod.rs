@@ -1,11 +1,41 @@
-use axum::{routing::get, Router};
+use axum::routing::{delete, get, patch, post, put};
 
-use crate::web::AppState;
+use crate::web::routes::table::RouteTable;
 
 pub mod root;
 
-pub fn me_routes(state: &AppState) -> Router {
-    Router::new()
-        .route("/me/notifications", get(root::get_me_notifications))
-        .with_state(state.clone())
-}
\ No newline at end of file
+/// The ones from before versions, still served at the root.
+pub fn unversioned_me_routes(table: RouteTable) -> RouteTable {
+    table.route::<root::__path_get_me_notifications>(|_| {
+        get(root::get_me_notifications)
+    })
+}
+
+pub fn me_routes(table: RouteTable) -> RouteTable {
+    unversioned_me_routes(table)
+        .route::<root::__path_update_profile>(|_| patch(root::update_profile))
+        .route::<root::__path_stream_notifications>(|_| {
+            get(root::stream_notifications)
+        })
+        .route::<root::__path_get_unread_count>(|_| get(root::get_unread_count))
+        .route::<root::__path_mark_all_read>(|_| post(root::mark_all_read))
+        .route::<root::__path_delete_notification>(|_| {
+            delete(root::delete_notification)
+        })
+        .route::<root::__path_mark_read>(|_| post(root::mark_read))
+        .route::<root::__path_archive_notification>(|_| {
+            post(root::archive_notification)
+        })
+        .route::<root::__path_unarchive_notification>(|_| {
+            delete(root::unarchive_notification)
+        })
+        .route::<root::__path_get_security_events>(|_| {
+            get(root::get_security_events)
+        })
+        .route::<root::__path_get_notification_settings>(|_| {
+            get(root::get_notification_settings)
+        })
+        .route::<root::__path_put_notification_settings>(|_| {
+            put(root::put_notification_settings)
+        })
+}
@@ -1,43 +1,507 @@
-use axum::{extract::State, http::StatusCode, Json};
+use std::{collections::HashSet, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::Utc;
+use futures::Stream;
+use tracing::warn;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::web::{
     dto::{
+        auth::logged_user_response::LoggedUserResponse,
         me::{
-            notifications::NotificationResponse,
+            mark_read_request::{MarkAllReadRequest, MarkReadResponse},
+            notification_settings::{
+                NotificationSettings, NotificationSettingsResponse,
+            },
+            security_events::{SecurityEventsQuery, SecurityEventsResponse},
             update_profile_request::UpdateProfileRequest,
+            notifications::{
+                Notification, NotificationResponse, NotificationsQuery,
+                UnreadCountResponse,
+            },
         },
         user_claims::UserClaims,
         Claim,
     },
-    errors::HttpError,
-    extractors::{token::Token, validate_body::ValidatedJson},
-    models::users::User,
+    errors::{code::ErrorCode, HttpError},
+    extractors::{
+        token::{StreamToken, Token}, validate_body::ValidatedJson,
+        validate_query::ValidatedQuery,
+    },
+    metrics::NOTIFICATIONS_SERVED,
+    models::{audit_events::AuditFilter, users::UserModel},
+    versions::{ApiVersion, Versioned, VersionedBody},
     AppState,
 };
 
+#[utoipa::path(
+    patch,
+    path="/me",
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = 200, description = "Profile updated", body = LoggedUserResponse),
+        (status = 400, description = "Invalid locale or time zone"),
+        (status = 401, description = "Invalid token sent"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn update_profile(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    ValidatedJson(body): ValidatedJson<UpdateProfileRequest>,
+) -> Result<Versioned<LoggedUserResponse>, HttpError> {
+    if let Some(mut user) = s.users.by_id(&user.data().user_id).await? {
+        s.users
+            .update_preferences(&mut user, body.locale, body.time_zone.as_deref())
+            .await?;
+        Ok(Versioned(version, LoggedUserResponse {
+            success: true,
+            user: UserModel::from(user),
+        }))
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
 #[utoipa::path(
     get,
     path="/me/notifications",
     responses(
         (status = 200, description = "Notifications fetched correctly", body = NotificationResponse),
+        (status = 400, description = "Malformed query string"),
         (status = 401, description = "Invalid token sent"),
     ),
+    params(NotificationsQuery),
+    security(
+        ("bearerAuth" = [])
+    )
 )]
 pub async fn get_me_notifications(
     State(s): State<AppState>,
+    version: ApiVersion,
     Token(user): Token<Claim<UserClaims>>,
-) -> Result<Json<NotificationResponse>, HttpError> {
-    let mut conn = s.pool.acquire().await?;
-    if let Some(user) = User::from_id(&mut *conn, &user.data().user_id).await? {
-        let notifications = user.get_notifications(&mut *conn).await?;
-        Ok(Json(NotificationResponse {
+    ValidatedQuery(query): ValidatedQuery<NotificationsQuery>,
+) -> Result<Versioned<NotificationResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        let notifications =
+            s.notifications.list(&user.id, query.archived).await?;
+        NOTIFICATIONS_SERVED
+            .with_label_values(&["list"])
+            .inc_by(notifications.len() as u64);
+        Ok(Versioned(version, NotificationResponse {
             success: true,
             notifications,
         }))
     } else {
-        Err(HttpError::Simple(
-            StatusCode::UNAUTHORIZED,
-            "invalid_credentials".to_string(),
-        ))
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path="/me/notifications/stream",
+    responses(
+        (status = 200, description = "Server-Sent Events stream of `notification` events, one for every new notification. The event id is the notification id, so reconnecting with `Last-Event-ID` replays whatever was missed.", content_type = "text/event-stream"),
+        (status = 401, description = "Invalid token sent"),
+    ),
+    params(
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last notification received, to resume the stream"),
+        ("access_token" = Option<String>, Query, description = "The token, for clients that can't set the `Authorization` header (such as browsers' EventSource)"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn stream_notifications(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    StreamToken(user): StreamToken,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, anyhow::Error>>>, HttpError> {
+    let Some(user) = s.users.by_id(&user.data().user_id).await? else {
+        return Err(HttpError::Simple(ErrorCode::InvalidCredentials));
+    };
+
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string);
+    // subscribe before loading the backlog, so nothing slips in between
+    let mut events = s.hub.subscribe();
+    let started_at = Utc::now();
+    let notifications = s.notifications;
+    let shutdown = s.shutdown;
+
+    let stream = async_stream::try_stream! {
+        let mut last_id = last_event_id;
+        // ids already sent by catching up, which can show up again as events
+        let mut sent = HashSet::new();
+        if let Some(after) = last_id.clone() {
+            for n in notifications.list_after(&user.id, &after).await? {
+                sent.insert(n.id().to_string());
+                last_id = Some(n.id().to_string());
+                yield notification_event(n, version)?;
+            }
+        }
+
+        loop {
+            // streams never end on their own, so they'd hold up the shutdown
+            // forever: clients reconnect to another replica with Last-Event-ID
+            let event = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                event = events.recv() => event,
+            };
+            match event {
+                Ok(event) if event.to_user == user.id => {
+                    if sent.remove(&event.id) {
+                        continue;
+                    }
+                    if let Some(n) = notifications.get(&user.id, &event.id).await? {
+                        last_id = Some(n.id().to_string());
+                        yield notification_event(n, version)?;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("notification stream lagged behind by {skipped} events, catching up");
+                    // without anything sent yet, whatever came since the
+                    // stream started could be among the skipped ones
+                    let missed = match last_id.clone() {
+                        Some(after) => notifications.list_after(&user.id, &after).await?,
+                        None => notifications.list_since(&user.id, started_at).await?,
+                    };
+                    for n in missed {
+                        if sent.contains(n.id()) {
+                            continue;
+                        }
+                        sent.insert(n.id().to_string());
+                        last_id = Some(n.id().to_string());
+                        yield notification_event(n, version)?;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(15))))
+}
+
+fn notification_event(
+    n: Notification,
+    version: ApiVersion,
+) -> Result<Event, anyhow::Error> {
+    NOTIFICATIONS_SERVED.with_label_values(&["stream"]).inc();
+    Ok(Event::default()
+        .id(n.id())
+        .event("notification")
+        .json_data(n.adapt(version)?)?)
+}
+
+#[utoipa::path(
+    get,
+    path="/me/notifications/unread-count",
+    responses(
+        (status = 200, description = "Number of unread notifications, meant for badges", body = UnreadCountResponse),
+        (status = 401, description = "Invalid token sent"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn get_unread_count(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+) -> Result<Versioned<UnreadCountResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        let unread = s.notifications.count_unread(&user.id).await?;
+        Ok(Versioned(version, UnreadCountResponse {
+            success: true,
+            unread,
+        }))
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path="/me/notifications/{id}/read",
+    responses(
+        (status = 200, description = "Notification marked as read. Marking an already read notification is a no-op, with `marked` at 0.", body = MarkReadResponse),
+        (status = 401, description = "Invalid token sent"),
+        (status = 404, description = "Notification not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Notification id"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn mark_read(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    Path(notification_id): Path<String>,
+) -> Result<Versioned<MarkReadResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        let Some(marked) =
+            s.notifications.mark_read(&user.id, &notification_id).await?
+        else {
+            return Err(HttpError::Simple(ErrorCode::NotificationNotFound));
+        };
+
+        Ok(Versioned(version, MarkReadResponse {
+            success: true,
+            marked: marked.marked,
+            unread: marked.unread,
+        }))
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path="/me/notifications/read-all",
+    request_body = MarkAllReadRequest,
+    responses(
+        (status = 200, description = "Notifications up to the cursor marked as read", body = MarkReadResponse),
+        (status = 400, description = "Malformed body"),
+        (status = 401, description = "Invalid token sent"),
+        (status = 404, description = "The cursor notification doesn't exist"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn mark_all_read(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    ValidatedJson(body): ValidatedJson<MarkAllReadRequest>,
+) -> Result<Versioned<MarkReadResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        let Some(marked) = s
+            .notifications
+            .mark_read_until(&user.id, body.until.as_deref())
+            .await?
+        else {
+            return Err(HttpError::Simple(ErrorCode::NotificationNotFound));
+        };
+
+        Ok(Versioned(version, MarkReadResponse {
+            success: true,
+            marked: marked.marked,
+            unread: marked.unread,
+        }))
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
     }
 }
+
+#[utoipa::path(
+    delete,
+    path="/me/notifications/{id}",
+    responses(
+        (status = 200, description = "Notification deleted"),
+        (status = 401, description = "Invalid token sent"),
+        (status = 404, description = "Notification not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Notification id"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn delete_notification(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    Path(notification_id): Path<String>,
+) -> Result<Versioned<Value>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        if s.notifications.delete(&user.id, &notification_id).await? {
+            Ok(Versioned(version, json!({"success": true})))
+        } else {
+            Err(HttpError::Simple(ErrorCode::NotificationNotFound))
+        }
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path="/me/notifications/{id}/archive",
+    responses(
+        (status = 200, description = "Notification archived. It won't show up in the default listing nor in the unread counter."),
+        (status = 401, description = "Invalid token sent"),
+        (status = 404, description = "Notification not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Notification id"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn archive_notification(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    Path(notification_id): Path<String>,
+) -> Result<Versioned<Value>, HttpError> {
+    set_archived(s, version, &user.data().user_id, &notification_id, true).await
+}
+
+#[utoipa::path(
+    delete,
+    path="/me/notifications/{id}/archive",
+    responses(
+        (status = 200, description = "Notification restored from the archive"),
+        (status = 401, description = "Invalid token sent"),
+        (status = 404, description = "Notification not found"),
+    ),
+    params(
+        ("id" = String, Path, description = "Notification id"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn unarchive_notification(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    Path(notification_id): Path<String>,
+) -> Result<Versioned<Value>, HttpError> {
+    set_archived(s, version, &user.data().user_id, &notification_id, false).await
+}
+
+async fn set_archived(
+    s: AppState,
+    version: ApiVersion,
+    user_id: &str,
+    notification_id: &str,
+    archived: bool,
+) -> Result<Versioned<Value>, HttpError> {
+    if let Some(user) = s.users.by_id(user_id).await? {
+        if s
+            .notifications
+            .set_archived(&user.id, notification_id, archived)
+            .await?
+        {
+            Ok(Versioned(version, json!({"success": true})))
+        } else {
+            Err(HttpError::Simple(ErrorCode::NotificationNotFound))
+        }
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path="/me/notification-settings",
+    responses(
+        (status = 200, description = "Notification preferences of the user", body = NotificationSettingsResponse),
+        (status = 401, description = "Invalid token sent"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn get_notification_settings(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+) -> Result<Versioned<NotificationSettingsResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        let settings = s.notifications.settings(&user.id).await?;
+        Ok(Versioned(version, NotificationSettingsResponse {
+            success: true,
+            settings,
+        }))
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    put,
+    path="/me/notification-settings",
+    request_body = NotificationSettings,
+    responses(
+        (status = 200, description = "Preferences replaced", body = NotificationSettingsResponse),
+        (status = 400, description = "Unknown notification types or invalid time zone"),
+        (status = 401, description = "Invalid token sent"),
+    ),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn put_notification_settings(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    ValidatedJson(settings): ValidatedJson<NotificationSettings>,
+) -> Result<Versioned<NotificationSettingsResponse>, HttpError> {
+    if let Some(user) = s.users.by_id(&user.data().user_id).await? {
+        s.notifications.save_settings(&user.id, &settings).await?;
+        Ok(Versioned(version, NotificationSettingsResponse {
+            success: true,
+            settings,
+        }))
+    } else {
+        Err(HttpError::Simple(ErrorCode::InvalidCredentials))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path="/me/security-events",
+    responses(
+        (status = 200, description = "Logins, token revocations and other security events of the account, newest first", body = SecurityEventsResponse),
+        (status = 400, description = "Malformed query string"),
+        (status = 401, description = "Invalid token sent"),
+    ),
+    params(SecurityEventsQuery),
+    security(
+        ("bearerAuth" = [])
+    )
+)]
+pub async fn get_security_events(
+    State(s): State<AppState>,
+    version: ApiVersion,
+    Token(user): Token<Claim<UserClaims>>,
+    ValidatedQuery(query): ValidatedQuery<SecurityEventsQuery>,
+) -> Result<Versioned<SecurityEventsResponse>, HttpError> {
+    let events = s
+        .audit
+        .list(&AuditFilter {
+            user_id: Some(user.data().user_id.clone()),
+            before: query.before,
+            limit: query.limit.unwrap_or(AuditFilter::DEFAULT_LIMIT),
+            ..AuditFilter::default()
+        })
+        .await?;
+
+    Ok(Versioned(version, SecurityEventsResponse {
+        success: true,
+        events,
+    }))
+}
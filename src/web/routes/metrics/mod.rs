@@ -0,0 +1,9 @@
+use axum::routing::get;
+
+use crate::web::routes::table::RouteTable;
+
+pub mod root;
+
+pub fn metrics_routes(table: RouteTable) -> RouteTable {
+    table.route::<root::__path_metrics>(|_| get(root::metrics))
+}
@@ -0,0 +1,24 @@
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::IntoResponse,
+};
+
+use crate::web::{errors::{code::ErrorCode, HttpError}, metrics, AppState};
+
+#[utoipa::path(
+    get,
+    path="/metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain"),
+    ),
+)]
+pub async fn metrics(
+    State(s): State<AppState>,
+) -> Result<impl IntoResponse, HttpError> {
+    let body = metrics::render(&s.pool).map_err(|_| {
+        HttpError::Simple(ErrorCode::InternalServerError)
+    })?;
+
+    Ok(([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
@@ -1,3 +1,8 @@
 pub mod main;
+pub mod admin;
 pub mod auth;
-pub mod me;
\ No newline at end of file
+pub mod health;
+pub mod internal;
+pub mod metrics;
+pub mod me;
+pub mod table;
\ No newline at end of file
@@ -0,0 +1,71 @@
+use axum::{routing::MethodRouter, Router};
+use utoipa::openapi::{path::PathsBuilder, Paths};
+
+use crate::web::AppState;
+
+/// Routes along with their documentation: each entry is mounted on the
+/// router and described in the OpenAPI document, so neither can go without
+/// the other.
+pub struct RouteTable {
+    /// `None` when only the documentation is wanted, e.g. by `users openapi`
+    state: Option<AppState>,
+    router: Router<AppState>,
+    paths: PathsBuilder,
+}
+
+impl RouteTable {
+    pub fn new(state: &AppState) -> RouteTable {
+        RouteTable {
+            state: Some(state.clone()),
+            router: Router::new(),
+            paths: PathsBuilder::new(),
+        }
+    }
+
+    /// A table that only collects the documentation: handlers aren't built.
+    pub fn docs() -> RouteTable {
+        RouteTable {
+            state: None,
+            router: Router::new(),
+            paths: PathsBuilder::new(),
+        }
+    }
+
+    /// Mounts the handler `P` documents (the `__path_*` struct generated by
+    /// `#[utoipa::path]`) on the path it's documented with. Several
+    /// handlers on the same path are merged.
+    pub fn route<P: utoipa::Path>(
+        mut self,
+        handler: impl FnOnce(&AppState) -> MethodRouter<AppState>,
+    ) -> RouteTable {
+        let path = P::path();
+        if let Some(state) = &self.state {
+            self.router = self.router.route(&axum_path(&path), handler(state));
+        }
+        self.paths = self.paths.path(path, P::path_item(None));
+
+        self
+    }
+
+    pub fn into_router(self) -> Router {
+        match self.state {
+            Some(state) => self.router.with_state(state),
+            None => Router::new(),
+        }
+    }
+
+    pub fn into_paths(self) -> Paths {
+        self.paths.build()
+    }
+}
+
+/// `/me/notifications/{id}` the way axum wants it: `/me/notifications/:id`.
+fn axum_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix('{') {
+            Some(param) => format!(":{}", param.trim_end_matches('}')),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
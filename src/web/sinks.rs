@@ -0,0 +1,112 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::OnceCell;
+use tracing::{info, instrument};
+
+use crate::{config::OutboxSink, web::models::outbox::OutboxEvent};
+
+/// Somewhere user events get published to by the outbox relay. An event
+/// that fails is published again later, so a sink is free to deliver it
+/// more than once.
+#[async_trait]
+pub trait Sink: Send + Sync {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), anyhow::Error>;
+}
+
+pub fn from_config(sink: &OutboxSink) -> Arc<dyn Sink> {
+    match sink {
+        OutboxSink::Log => Arc::new(LogSink),
+        OutboxSink::Webhook { url } => Arc::new(WebhookSink::new(url)),
+        OutboxSink::Nats {
+            url,
+            subject_prefix,
+        } => Arc::new(NatsSink::new(url, subject_prefix)),
+    }
+}
+
+/// Only logs the events, under the `outbox` target.
+pub struct LogSink;
+
+#[async_trait]
+impl Sink for LogSink {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), anyhow::Error> {
+        info!(target: "outbox", event = %event.envelope(), "{}", event.kind);
+        Ok(())
+    }
+}
+
+/// POSTs each event to a URL, which has to answer with a 2xx.
+pub struct WebhookSink {
+    http: reqwest::Client,
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> WebhookSink {
+        WebhookSink {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for WebhookSink {
+    #[instrument(skip_all, fields(otel.kind = "client"))]
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), anyhow::Error> {
+        self.http
+            .post(&self.url)
+            // so the receiver can drop the deliveries it already got
+            .header("idempotency-key", event.id.to_string())
+            .json(&event.envelope())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Publishes each event on `<prefix>.<type>`, e.g.
+/// `pieno.users.user.registered`. The connection is made on the first
+/// event, and kept.
+pub struct NatsSink {
+    url: String,
+    subject_prefix: String,
+    client: OnceCell<async_nats::Client>,
+}
+
+impl NatsSink {
+    pub fn new(url: &str, subject_prefix: &str) -> NatsSink {
+        NatsSink {
+            url: url.to_string(),
+            subject_prefix: subject_prefix.to_string(),
+            client: OnceCell::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    #[instrument(skip_all, fields(otel.kind = "producer"))]
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), anyhow::Error> {
+        let client = self
+            .client
+            .get_or_try_init(|| async_nats::connect(&self.url))
+            .await?;
+        client
+            .publish(
+                format!("{}.{}", self.subject_prefix, event.kind),
+                serde_json::to_vec(&event.envelope())?.into(),
+            )
+            .await?;
+        // only then is it on its way to the server
+        client.flush().await?;
+
+        Ok(())
+    }
+}
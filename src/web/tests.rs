@@ -0,0 +1,2641 @@
+//! The whole router against the in-memory repositories: no Postgres needed.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use axum::{
+    body::{Body, BodyDataStream},
+    http::{header::AUTHORIZATION, HeaderMap, Method, Request, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use chrono::{Datelike, Timelike, Utc};
+use futures::StreamExt;
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+use reqwest::dns::Resolve;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPoolOptions;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::ServiceExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::{
+    config::{
+        Config, CorsConfig, DatabaseConfig, ErrorFormat, IdempotencyConfig,
+        JwtConfig, LogConfig, LogFormat, MailConfig, OutboxConfig, OutboxSink,
+        PushConfig, RouteGroup, WebhooksConfig,
+        SecurityHeaders, SecurityHeadersConfig, ServerConfig, TracingConfig,
+    },
+    web::{
+        dto::{
+            admin_claims::AdminClaims,
+            me::notification_settings::NotificationSettings,
+            service_claims::ServiceClaims, user_claims::UserClaims, Claim,
+        },
+        extractors::{token::Token, validate_body::ValidatedForm},
+        jobs::{
+            listener::{NotificationEvent, NotificationHub},
+            outbox::relay,
+            retention::{prune, RetentionAction, RetentionPolicy, RetentionRule},
+            webhooks::{dispatch, signature, HttpSender, Sender},
+        },
+        locale::Locale,
+        metrics::NOTIFICATIONS_PRUNED,
+        util::hash_password,
+        models::{
+            outbox::OutboxEvent,
+            users::User,
+            webhooks::{DueDelivery, WebhookAttempt},
+        },
+        outbound::PublicResolver,
+        push::PushClient,
+        repositories::{
+            memory::MemoryRepository, NewUser, NotificationRepository,
+            OutboxRepository, UserRepository, WebhookRepository,
+        },
+        sinks::Sink,
+        api_doc, router,
+        versions::ApiVersion,
+        AppState,
+    },
+};
+
+fn config() -> Config {
+    Config {
+        server: ServerConfig {
+            bind_address: ([127, 0, 0, 1], 0).into(),
+            shutdown_grace: Duration::ZERO,
+            shutdown_timeout: Duration::ZERO,
+            error_format: ErrorFormat::Problem,
+            unversioned_deprecated_at: "2026-10-19T00:00:00Z".parse().unwrap(),
+            unversioned_sunset: None,
+            trust_forwarded_for: false,
+            admin_services: vec!["backoffice".to_string()],
+        },
+        database: DatabaseConfig {
+            url: "postgresql://localhost/unused".to_string(),
+            max_connections: 1,
+            acquire_timeout: Duration::from_secs(1),
+            migrate_on_startup: false,
+        },
+        jwt: JwtConfig {
+            secret: "test".to_string(),
+            service_secret: "test-services".to_string(),
+            admin_secret: "test-admin".to_string(),
+            timeout: Duration::from_secs(3600),
+        },
+        retention: None,
+        push: PushConfig {
+            fcm_service_url: None,
+        },
+        mail: MailConfig {
+            smtp_url: None,
+            from: "PIENO <noreply@localhost>".parse().unwrap(),
+            check_interval: Duration::from_secs(60),
+        },
+        tracing: TracingConfig {
+            otlp_endpoint: None,
+            service_name: "users".to_string(),
+            sample_ratio: 1.0,
+        },
+        log: LogConfig {
+            format: LogFormat::Pretty,
+        },
+        cors: CorsConfig {
+            allowed_origins: vec![],
+            allow_credentials: false,
+            max_age: Duration::from_secs(600),
+        },
+        security_headers: SecurityHeadersConfig {
+            defaults: SecurityHeaders {
+                strict_transport_security: Some("max-age=60".to_string()),
+                content_type_options: Some("nosniff".to_string()),
+                referrer_policy: Some("no-referrer".to_string()),
+                content_security_policy: Some("default-src 'none'".to_string()),
+            },
+            overrides: HashMap::new(),
+        },
+        idempotency: IdempotencyConfig {
+            secret: "test-idempotency".to_string(),
+            ttl: Duration::from_secs(3600),
+            abandoned_after: Duration::from_secs(60),
+            cleanup_interval: Duration::from_secs(3600),
+        },
+        outbox: OutboxConfig {
+            sink: OutboxSink::Log,
+            poll_interval: Duration::from_secs(1),
+            batch_size: 100,
+            max_backoff: Duration::from_secs(300),
+            lease: Duration::from_secs(60),
+            keep_published: Duration::from_secs(3600),
+        },
+        webhooks: WebhooksConfig {
+            poll_interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+            max_attempts: 10,
+            max_backoff: Duration::from_secs(3600),
+            keep_deliveries: Duration::from_secs(86400),
+        },
+    }
+}
+
+struct TestApp {
+    router: Router,
+    repository: MemoryRepository,
+    /// what the Postgres listener would be feeding
+    hub: NotificationHub,
+    /// background work started by requests, like deliveries
+    tasks: TaskTracker,
+    /// what SIGTERM would cancel
+    shutdown: CancellationToken,
+    config: Arc<Config>,
+}
+
+impl TestApp {
+    fn new() -> TestApp {
+        TestApp::with_config(config())
+    }
+
+    fn with_config(config: Config) -> TestApp {
+        TestApp::with_push(config, None)
+    }
+
+    /// Pushes go to `push`, e.g. a [`FakeMessenger`].
+    fn with_push(config: Config, push: Option<PushClient>) -> TestApp {
+        let config = Arc::new(config);
+        let repository = MemoryRepository::new();
+        let hub = NotificationHub::new(16);
+        let tasks = TaskTracker::new();
+        let shutdown = CancellationToken::new();
+        let state = AppState {
+            // never connects: everything handlers need is in memory
+            pool: PgPoolOptions::new()
+                .connect_lazy(&config.database.url)
+                .unwrap(),
+            config: config.clone(),
+            users: Arc::new(repository.clone()),
+            notifications: Arc::new(repository.clone()),
+            idempotency: Arc::new(repository.clone()),
+            audit: Arc::new(repository.clone()),
+            outbox: Arc::new(repository.clone()),
+            webhooks: Arc::new(repository.clone()),
+            hub: hub.clone(),
+            push,
+            mailer: None,
+            shutdown: shutdown.clone(),
+            tasks: tasks.clone(),
+        };
+
+        TestApp {
+            router: router(&state),
+            repository,
+            hub,
+            tasks,
+            shutdown,
+            config,
+        }
+    }
+
+    /// A user with an unusable password, for tests that don't log in.
+    async fn user(&self, email: &str) -> (String, String) {
+        let id = User::new_id();
+        self.repository
+            .register(NewUser {
+                id: id.clone(),
+                email: email.to_string(),
+                name: "Mario".to_string(),
+                surname: "Rossi".to_string(),
+                password_hash: "-".to_string(),
+                locale: Locale::default(),
+                time_zone: "UTC".to_string(),
+            })
+            .await
+            .unwrap();
+        let token = Token::generate(
+            Claim::from(
+                UserClaims {
+                    user_id: id.clone(),
+                    name: "Mario".to_string(),
+                    surname: "Rossi".to_string(),
+                    propic_url: None,
+                    generation: 0,
+                },
+                self.config.jwt.timeout,
+            ),
+            &self.config.jwt,
+        )
+        .await
+        .unwrap_or_else(|_| panic!("couldn't sign a token"));
+
+        (id, token)
+    }
+
+    async fn service_token(&self) -> String {
+        self.token_of_service("cars").await
+    }
+
+    /// An admin token of one of the `admin_services`.
+    async fn admin_token(&self) -> String {
+        self.admin_token_of("backoffice").await
+    }
+
+    async fn admin_token_of(&self, service: &str) -> String {
+        Token::generate(
+            Claim::from(
+                AdminClaims {
+                    service: service.to_string(),
+                },
+                self.config.jwt.timeout,
+            ),
+            &self.config.jwt,
+        )
+        .await
+        .unwrap_or_else(|_| panic!("couldn't sign a token"))
+    }
+
+    async fn token_of_service(&self, service: &str) -> String {
+        Token::generate(
+            Claim::from(
+                ServiceClaims {
+                    service: service.to_string(),
+                },
+                self.config.jwt.timeout,
+            ),
+            &self.config.jwt,
+        )
+        .await
+        .unwrap_or_else(|_| panic!("couldn't sign a token"))
+    }
+
+    async fn call(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        self.call_with(method, uri, token, body, &[]).await
+    }
+
+    async fn call_with(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+        headers: &[(&str, &str)],
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = match body {
+            Some(body) => request
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        send(&self.router, request).await
+    }
+}
+
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+/// A stand-in for fcm-messenger, keeping the pushes it's asked to send.
+#[derive(Clone)]
+struct FakeMessenger {
+    url: String,
+    received: Arc<Mutex<Vec<Value>>>,
+    /// what each push came with, in the same order
+    headers: Arc<Mutex<Vec<HeaderMap>>>,
+}
+
+impl FakeMessenger {
+    async fn start() -> FakeMessenger {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(vec![]));
+        let headers = Arc::new(Mutex::new(vec![]));
+        let keep = (received.clone(), headers.clone());
+        let router = Router::new().route(
+            "/send",
+            post(|sent_with: HeaderMap, Json(message): Json<Value>| async move {
+                keep.0.lock().unwrap().push(message);
+                keep.1.lock().unwrap().push(sent_with);
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        FakeMessenger {
+            url,
+            received,
+            headers,
+        }
+    }
+
+    fn client(&self) -> Option<PushClient> {
+        Some(PushClient::new(&self.url))
+    }
+
+    /// What was pushed so far, once deliveries on their way are done.
+    async fn received(&self, app: &TestApp) -> Vec<Value> {
+        app.tasks.close();
+        app.tasks.wait().await;
+        app.tasks.reopen();
+        self.received.lock().unwrap().clone()
+    }
+}
+
+fn car_invite(owner: &str) -> Value {
+    json!({
+        "type": "car_invite",
+        "v": 1,
+        "owner": owner,
+        "car_id": "car",
+        "car_name": "Panda",
+    })
+}
+
+#[tokio::test]
+async fn register_then_login() {
+    let app = TestApp::new();
+    let credentials = json!({
+        "email": "mario@example.com",
+        "name": "Mario",
+        "surname": "Rossi",
+        "password": "Password1!",
+    });
+
+    let (status, body) = app
+        .call(Method::POST, "/auth/register", None, Some(credentials.clone()))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let token = body["token"].as_str().unwrap().to_string();
+
+    let (status, body) = app.call(Method::GET, "/auth", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["email"], "mario@example.com");
+
+    let (status, body) = app
+        .call(Method::POST, "/auth/register", None, Some(credentials))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "duplicate_row");
+
+    let (status, _) = app
+        .call(
+            Method::POST,
+            "/auth/login",
+            None,
+            Some(json!({"email": "mario@example.com", "password": "wrong!!!"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/auth/login",
+            None,
+            Some(json!({"email": "mario@example.com", "password": "Password1!"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["token"].is_string());
+}
+
+#[tokio::test]
+async fn tokens_are_checked() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+
+    let (status, body) = app.call(Method::GET, "/me/notifications", None, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "no_auth_header");
+
+    // the query string only carries tokens for streams
+    let uri = format!("/me/notifications?access_token={token}");
+    let (status, body) = app.call(Method::GET, &uri, None, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "no_auth_header");
+    let request = Request::builder()
+        .uri(format!("/v1/me/notifications/stream?access_token={token}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // user tokens aren't service tokens, and a service token signed with the
+    // users' secret is a forgery
+    let forged = Token::generate(
+        Claim::from(
+            ServiceClaims {
+                service: "cars".to_string(),
+            },
+            app.config.jwt.timeout,
+        ),
+        &JwtConfig {
+            service_secret: app.config.jwt.secret.clone(),
+            ..app.config.jwt.clone()
+        },
+    )
+    .await
+    .unwrap_or_else(|_| panic!("couldn't sign a token"));
+    for token in [&token, &forged] {
+        let (status, body) = app
+            .call(
+                Method::POST,
+                "/v1/internal/notifications",
+                Some(token),
+                Some(json!({"recipients": [id], "payload": car_invite(&id)})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "invalid_token");
+    }
+
+    app.repository
+        .update_user(&id, |user| user.disabled_at = Some(Utc::now()));
+    let (status, body) = app
+        .call(Method::GET, "/me/notifications", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "account_disabled");
+}
+
+#[tokio::test]
+async fn user_tokens_from_before_audiences_still_work() {
+    let app = TestApp::new();
+    let (id, _) = app.user("mario@example.com").await;
+    let exp = Utc::now().timestamp() + 3600;
+    // what was issued before tokens had an `aud`, or a generation
+    let legacy = |data: Value, secret: &str| {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &json!({"exp": exp, "iat": exp - 7200, "data": data}),
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    };
+
+    let user = legacy(
+        json!({"user_id": id, "name": "Mario", "surname": "Rossi", "propic_url": null}),
+        &app.config.jwt.secret,
+    );
+    let (status, _) = app.call(Method::GET, "/v1/me/notifications", Some(&user), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // service tokens always had their own audience
+    let service = legacy(json!({"service": "cars"}), &app.config.jwt.service_secret);
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({"recipients": [id], "payload": car_invite(&id)})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "invalid_token");
+}
+
+/// What `users user disable|enable|reset-password` and `users tokens revoke`
+/// do to a user, and what their tokens make of it.
+#[tokio::test]
+async fn revoked_tokens_stop_working_but_new_ones_do() {
+    let app = &TestApp::new();
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/auth/register",
+            None,
+            Some(json!({
+                "email": "mario@example.com",
+                "name": "Mario",
+                "surname": "Rossi",
+                "password": "Password1!",
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let id = {
+        let token = body["token"].as_str().unwrap();
+        let (_, body) = app.call(Method::GET, "/auth", Some(token), None).await;
+        body["user"]["id"].as_str().unwrap().to_string()
+    };
+    let login = |password: &'static str| async move {
+        let (status, body) = app
+            .call(
+                Method::POST,
+                "/auth/login",
+                None,
+                Some(json!({"email": "mario@example.com", "password": password})),
+            )
+            .await;
+        (status, body["token"].as_str().map(str::to_string))
+    };
+    let me = |token: String| async move {
+        let (status, body) = app.call(Method::GET, "/auth", Some(&token), None).await;
+        (status, body["code"].clone())
+    };
+
+    // what `users user disable` and `enable` do: the old token stays
+    // revoked, a new one works
+    let stored = || async { app.repository.by_id(&id).await.unwrap().unwrap() };
+    let (_, token) = login("Password1!").await;
+    let token = token.unwrap();
+    app.repository.set_disabled(&mut stored().await, true).await.unwrap();
+    assert_eq!(login("Password1!").await.0, StatusCode::FORBIDDEN);
+    app.repository.set_disabled(&mut stored().await, false).await.unwrap();
+    assert_eq!(me(token).await, (StatusCode::UNAUTHORIZED, json!("token_revoked")));
+    // even within the same second as the revocation
+    let (_, token) = login("Password1!").await;
+    let token = token.unwrap();
+    assert_eq!(me(token.clone()).await.0, StatusCode::OK);
+
+    // revoked
+    app.repository.update_user(&id, |user| user.token_generation += 1);
+    assert_eq!(me(token).await, (StatusCode::UNAUTHORIZED, json!("token_revoked")));
+    let (_, token) = login("Password1!").await;
+    let token = token.unwrap();
+    assert_eq!(me(token.clone()).await.0, StatusCode::OK);
+
+    // `users user reset-password`
+    let password_hash = hash_password("Password2!").await.unwrap_or_default();
+    app.repository
+        .set_password(&mut stored().await, &password_hash)
+        .await
+        .unwrap();
+    assert_eq!(me(token).await, (StatusCode::UNAUTHORIZED, json!("token_revoked")));
+    assert_eq!(login("Password1!").await.0, StatusCode::UNAUTHORIZED);
+    let (_, token) = login("Password2!").await;
+    assert_eq!(me(token.unwrap()).await.0, StatusCode::OK);
+
+    // and the rest of PIENO is told about each of them
+    let updates: Vec<Value> = app
+        .repository
+        .outbox()
+        .into_iter()
+        .filter(|e| e.kind.as_str() == "user.updated")
+        .map(|e| e.payload)
+        .collect();
+    let disabled: Vec<&Value> = updates.iter().map(|p| &p["disabled"]).collect();
+    assert_eq!(disabled, [true, false, false]);
+    assert!(updates.iter().all(|p| p["id"] == id && p.get("password").is_none()));
+}
+
+#[tokio::test]
+async fn errors_are_problems() {
+    let app = TestApp::new();
+    let (_, token) = app.user("mario@example.com").await;
+
+    let request = Request::builder()
+        .uri("/v1/me/notifications/unknown")
+        .method(Method::DELETE)
+        .header(AUTHORIZATION, format!("Bearer {token}"))
+        .header("x-request-id", "req-1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    let body: Value = serde_json::from_slice(
+        &axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "type": "urn:pieno:users:error:notification_not_found",
+            "title": "Notification not found",
+            "status": 404,
+            "instance": "/v1/me/notifications/unknown",
+            "code": "notification_not_found",
+            "request_id": "req-1",
+        })
+    );
+
+    let (status, body) = app
+        .call(Method::GET, "/auth", Some("not.a.token"), None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "invalid_token");
+}
+
+#[tokio::test]
+async fn legacy_errors() {
+    let mut config = config();
+    config.server.error_format = ErrorFormat::Legacy;
+    let app = TestApp::with_config(config);
+
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/auth/register",
+            None,
+            Some(json!({"email": "nope", "name": "", "surname": "", "password": ""})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"], "invalid_fields");
+    assert_eq!(body["fields"], json!(["email", "password"]));
+}
+
+#[tokio::test]
+async fn fcm_tokens_can_be_sent_twice() {
+    let app = TestApp::new();
+    let (_, token) = app.user("mario@example.com").await;
+
+    for _ in 0..2 {
+        let (status, body) = app
+            .call(
+                Method::PUT,
+                "/auth/fcm",
+                Some(&token),
+                Some(json!({"token": "device"})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+    }
+}
+
+#[tokio::test]
+async fn notifications_lifecycle() {
+    let app = TestApp::new();
+    let (sender, _) = app.user("luigi@example.com").await;
+    let (id, token) = app.user("mario@example.com").await;
+    let service = app.service_token().await;
+
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({
+                "recipients": [id, "nobody"],
+                "payload": car_invite(&sender),
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["skipped"], json!(["nobody"]));
+    let notification = body["notifications"][0]["id"].as_str().unwrap().to_string();
+
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+        .await;
+    assert_eq!(body["notifications"].as_array().unwrap().len(), 1);
+    assert_eq!(body["notifications"][0]["sender"]["id"], sender);
+
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications/unread-count", Some(&token), None)
+        .await;
+    assert_eq!(body["unread"], 1);
+
+    let read = format!("/v1/me/notifications/{notification}/read");
+    let (status, body) = app.call(Method::POST, &read, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["marked"], 1);
+    assert_eq!(body["unread"], 0);
+    // already read
+    let (_, body) = app.call(Method::POST, &read, Some(&token), None).await;
+    assert_eq!(body["marked"], 0);
+
+    let (status, _) = app
+        .call(
+            Method::POST,
+            &format!("/v1/me/notifications/{notification}/archive"),
+            Some(&token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+        .await;
+    assert!(body["notifications"].as_array().unwrap().is_empty());
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications?archived=true", Some(&token), None)
+        .await;
+    assert_eq!(body["notifications"].as_array().unwrap().len(), 1);
+
+    let uri = format!("/v1/me/notifications/{notification}");
+    let (status, _) = app.call(Method::DELETE, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app.call(Method::DELETE, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "notification_not_found");
+}
+
+#[tokio::test]
+async fn a_notification_is_marked_read_once() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let (_, other) = app.user("luigi@example.com").await;
+    let service = app.service_token().await;
+    let (_, body) = app
+        .call(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({"recipients": [id], "payload": car_invite(&id)})),
+        )
+        .await;
+    let notification = body["notifications"][0]["id"].as_str().unwrap();
+    let read = format!("/v1/me/notifications/{notification}/read");
+
+    // only the owner can mark it, and it looks as missing to anybody else
+    let (status, body) = app.call(Method::POST, &read, Some(&other), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "notification_not_found");
+    let (status, _) = app
+        .call(Method::POST, "/v1/me/notifications/unknown/read", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // of concurrent calls, exactly one flips it
+    let calls = (0..5).map(|_| app.call(Method::POST, &read, Some(&token), None));
+    let marked: Vec<i64> = futures::future::join_all(calls)
+        .await
+        .into_iter()
+        .map(|(status, body)| {
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["unread"], 0);
+            body["marked"].as_i64().unwrap()
+        })
+        .collect();
+    assert_eq!(marked.iter().sum::<i64>(), 1);
+
+    // and it keeps the time it was first read at
+    let read_at = || async {
+        let (_, body) = app
+            .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+            .await;
+        body["notifications"][0]["read_at"].clone()
+    };
+    let first = read_at().await;
+    assert!(first.is_string());
+    app.call(Method::POST, &read, Some(&token), None).await;
+    assert_eq!(read_at().await, first);
+}
+
+/// The events of a `text/event-stream` response, as they come.
+struct EventStream {
+    body: BodyDataStream,
+    buffer: String,
+}
+
+impl EventStream {
+    fn new(response: axum::response::Response) -> EventStream {
+        EventStream {
+            body: response.into_body().into_data_stream(),
+            buffer: String::new(),
+        }
+    }
+
+    /// The `id` and `data` of the next event, `None` if none comes within a
+    /// second.
+    async fn next(&mut self) -> Option<(String, Value)> {
+        loop {
+            if let Some((frame, rest)) = self.buffer.split_once("\n\n") {
+                let frame = frame.to_string();
+                self.buffer = rest.to_string();
+                let field = |name: &str| {
+                    frame
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(|value| value.trim_start().to_string())
+                };
+                // anything else is a keep-alive
+                if let (Some(id), Some(data)) = (field("id:"), field("data:")) {
+                    return Some((id, serde_json::from_str(&data).unwrap()));
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(1), self.body.next())
+                .await
+                .ok()??
+                .unwrap();
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn streams_resume_after_the_last_event_id() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let (other, _) = app.user("luigi@example.com").await;
+    let service = app.service_token().await;
+    let notify = || async {
+        let (_, body) = app
+            .call(
+                Method::POST,
+                "/v1/internal/notifications",
+                Some(&service),
+                Some(json!({"recipients": [id], "payload": car_invite(&other)})),
+            )
+            .await;
+        body["notifications"][0]["id"].as_str().unwrap().to_string()
+    };
+    let open = |last_event_id: Option<&str>| {
+        let mut request = Request::get("/v1/me/notifications/stream")
+            .header(AUTHORIZATION, format!("Bearer {token}"));
+        if let Some(last_event_id) = last_event_id {
+            request = request.header("last-event-id", last_event_id);
+        }
+        app.router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+    };
+    let backlog = [notify().await, notify().await, notify().await];
+
+    // a client coming back gets what it missed, oldest first
+    let response = open(Some(&backlog[0])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let mut resumed = EventStream::new(response);
+    assert_eq!(resumed.next().await.unwrap().0, backlog[1]);
+    let (event_id, data) = resumed.next().await.unwrap();
+    assert_eq!(event_id, backlog[2]);
+    assert_eq!(data["id"], backlog[2]);
+    assert_eq!(data["data"]["type"], "car_invite");
+    // what it caught up with can show up again
+    app.hub.publish(NotificationEvent {
+        id: backlog[2].clone(),
+        to_user: id.clone(),
+    });
+    // a new one only gets what comes next
+    let mut fresh = EventStream::new(open(None).await.unwrap());
+
+    // and others' are none of their business
+    let live = notify().await;
+    app.hub.publish(NotificationEvent {
+        id: "of-someone-else".to_string(),
+        to_user: other.clone(),
+    });
+    app.hub.publish(NotificationEvent {
+        id: live.clone(),
+        to_user: id.clone(),
+    });
+    assert_eq!(resumed.next().await.unwrap().0, live);
+    assert!(resumed.next().await.is_none());
+    assert_eq!(fresh.next().await.unwrap().0, live);
+    assert!(fresh.next().await.is_none());
+}
+
+#[tokio::test]
+async fn mark_all_read_needs_a_known_cursor() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let service = app.service_token().await;
+    for _ in 0..2 {
+        app.call(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({"recipients": [id], "payload": car_invite(&id)})),
+        )
+        .await;
+    }
+
+    let (status, _) = app
+        .call(
+            Method::POST,
+            "/v1/me/notifications/read-all",
+            Some(&token),
+            Some(json!({"until": "missing"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/v1/me/notifications/read-all",
+            Some(&token),
+            Some(json!({})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["marked"], 2);
+    assert_eq!(body["unread"], 0);
+}
+
+#[tokio::test]
+async fn old_notifications_are_pruned_in_batches() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let recipients = std::slice::from_ref(&id);
+    let legacy = json!({
+        "owner": id,
+        "car_name": "Punto",
+        "tank_size": "45.0",
+        "consumption": "6,1",
+        "fuel_value": "30",
+    });
+    let made_up = json!({"type": "promo-2026-10", "v": 1});
+    for payload in [&legacy, &legacy, &car_invite(&id), &made_up] {
+        app.repository
+            .insert_batch(recipients, &[], payload, None)
+            .await
+            .unwrap();
+    }
+    app.repository.advance(Duration::from_secs(40 * 24 * 3600));
+    app.repository
+        .insert_batch(recipients, &[], &legacy, None)
+        .await
+        .unwrap();
+
+    let policy = RetentionPolicy {
+        default: Some(RetentionRule {
+            days: 30,
+            action: RetentionAction::Delete,
+        }),
+        overrides: HashMap::from([(
+            "car_invite".to_string(),
+            RetentionRule {
+                days: 30,
+                action: RetentionAction::Archive,
+            },
+        )]),
+        interval: Duration::from_secs(3600),
+        // one at a time, so every rule takes a few rounds
+        batch_size: 1,
+    };
+    let unknown = NOTIFICATIONS_PRUNED.with_label_values(&["unknown", "delete"]);
+    let unknown_before = unknown.get();
+    let report = prune(&app.repository, &policy).await.unwrap();
+    assert_eq!((report.deleted, report.archived), (3, 1));
+    // types producers make up don't get a time series each
+    assert_eq!(unknown.get() - unknown_before, 1);
+
+    // the recent one is left alone, the old invite is archived
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+        .await;
+    assert_eq!(body["notifications"].as_array().unwrap().len(), 1);
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications?archived=true", Some(&token), None)
+        .await;
+    let archived = body["notifications"].as_array().unwrap();
+    assert_eq!(archived.len(), 1);
+    assert_eq!(archived[0]["data"]["type"], "car_invite");
+
+    let report = prune(&app.repository, &policy).await.unwrap();
+    assert_eq!((report.deleted, report.archived), (0, 0));
+}
+
+#[tokio::test]
+async fn pushes_only_go_out_when_asked_and_once() {
+    let messenger = FakeMessenger::start().await;
+    let app = TestApp::with_push(config(), messenger.client());
+    let (id, token) = app.user("mario@example.com").await;
+    let (sender, _) = app.user("luigi@example.com").await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "device"})))
+        .await;
+    let service = app.service_token().await;
+    let app = &app;
+    let notify = |push: bool| {
+        let service = service.clone();
+        let body = json!({
+            "recipients": [id],
+            "payload": car_invite(&sender),
+            "push": push,
+        });
+        async move {
+            let (status, body) = app
+                .call_with(
+                    Method::POST,
+                    "/v1/internal/notifications",
+                    Some(&service),
+                    Some(body),
+                    &[("idempotency-key", &format!("invite-{push}"))],
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            body
+        }
+    };
+
+    // stored either way, pushed only when asked
+    notify(false).await;
+    let pushed = notify(true).await;
+    // a retry is replayed, without buzzing the phone again
+    assert_eq!(notify(true).await["replayed"], true);
+
+    let received = messenger.received(app).await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["tokens"], json!(["device"]));
+    assert_eq!(
+        received[0]["data"]["notification_id"],
+        pushed["notifications"][0]["id"]
+    );
+    assert_eq!(received[0]["title"], "New car invite");
+
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications/unread-count", Some(&token), None)
+        .await;
+    assert_eq!(body["unread"], 2);
+}
+
+#[tokio::test]
+async fn internal_notifications_need_a_payload_we_know() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let service = app.service_token().await;
+    let refuel = |changes: Value| {
+        let mut payload = json!({
+            "type": "refuel",
+            "v": 1,
+            "owner": id,
+            "car_name": "Panda",
+            "tank_size": 35,
+            "consumption": 5.5,
+            "fuel_value": 20,
+        });
+        let fields = payload.as_object_mut().unwrap();
+        fields.extend(changes.as_object().unwrap().clone());
+        json!({"recipients": [id], "payload": payload})
+    };
+    // the code of the error, and the one of the field when it's about one
+    let cases = [
+        (refuel(json!({"v": 2})), "invalid_fields", json!("invalid_payload")),
+        (refuel(json!({"tank_size": -1})), "invalid_fields", json!("invalid_payload")),
+        (refuel(json!({"car_name": ""})), "invalid_fields", json!("invalid_payload")),
+        (
+            json!({"recipients": [], "payload": car_invite(&id)}),
+            "invalid_fields",
+            json!("length"),
+        ),
+        (refuel(json!({"type": "promo"})), "invalid_body", Value::Null),
+        (refuel(json!({"tank_size": "a lot"})), "invalid_body", Value::Null),
+    ];
+    for (body, code, field_code) in cases {
+        let (status, response) = app
+            .call(
+                Method::POST,
+                "/v1/internal/notifications",
+                Some(&service),
+                Some(body.clone()),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(response["code"], code, "{body}");
+        if field_code.is_string() {
+            assert_eq!(response["fields"][0]["code"], field_code, "{body}");
+        }
+    }
+
+    // numbers written as strings, the way old producers did, are fine
+    let (status, _) = app
+        .call(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(refuel(json!({"consumption": "5,5"}))),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+        .await;
+    assert_eq!(body["notifications"].as_array().unwrap().len(), 1);
+    assert_eq!(body["notifications"][0]["data"]["consumption"], 5.5);
+}
+
+#[tokio::test]
+async fn idempotency_keys_replay() {
+    let app = TestApp::new();
+    let (id, _) = app.user("mario@example.com").await;
+    let service = app.service_token().await;
+    let request = json!({"recipients": [id], "payload": car_invite(&id)});
+    let key = [("idempotency-key", "once")];
+
+    let (_, first) = app
+        .call_with(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(request.clone()),
+            &key,
+        )
+        .await;
+    assert_eq!(first["replayed"], false);
+
+    let (status, second) = app
+        .call_with(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(request),
+            &key,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(second["replayed"], true);
+    assert_eq!(second["notifications"], first["notifications"]);
+
+    let (status, body) = app
+        .call_with(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({"recipients": [id], "payload": car_invite("someone")})),
+            &key,
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "idempotency_key_reused");
+}
+
+#[tokio::test]
+async fn retried_calls_are_replayed() {
+    let app = TestApp::new();
+    let credentials = json!({
+        "email": "mario@example.com",
+        "name": "Mario",
+        "surname": "Rossi",
+        "password": "Password1!",
+    });
+    let register = |body: Value, key: &str| {
+        Request::post("/v1/auth/register")
+            .header("content-type", "application/json")
+            .header("idempotency-key", key)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let (status, first) = send(&app.router, register(credentials.clone(), "signup")).await;
+    assert_eq!(status, StatusCode::OK);
+    // the first call made it: no 409 for the retry, but the same token
+    let response = app
+        .router
+        .clone()
+        .oneshot(register(credentials.clone(), "signup"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["idempotent-replayed"], "true");
+    let (_, second) = send(&app.router, register(credentials.clone(), "signup")).await;
+    assert_eq!(second["token"], first["token"]);
+
+    // neither the password nor the token can be read off what's stored
+    let stored = app.repository.idempotency_keys();
+    let [(request_hash, Some(body))] = stored.as_slice() else {
+        panic!("the response wasn't stored");
+    };
+    let plain = Sha256::new()
+        .chain_update(format!("POST /v1/auth/register\n{credentials}"))
+        .finalize();
+    assert_ne!(*request_hash, hex::encode(plain));
+    let token = first["token"].as_str().unwrap();
+    assert!(!body.windows(token.len()).any(|w| w == token.as_bytes()));
+    assert!(!body.windows(6).any(|w| w == b"token\""));
+
+    let mut changed = credentials.clone();
+    changed["name"] = json!("Luigi");
+    let (status, body) = send(&app.router, register(changed, "signup")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "idempotency_key_reused");
+    let (status, body) = send(&app.router, register(credentials, "not a key")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_idempotency_key");
+
+    // keys of different users don't clash
+    let (_, mario) = app.user("mario.rossi@example.com").await;
+    let (_, luigi) = app.user("luigi@example.com").await;
+    for (token, device) in [(&mario, "mario"), (&luigi, "luigi"), (&mario, "mario")] {
+        let (status, body) = app
+            .call_with(
+                Method::PUT,
+                "/v1/auth/fcm",
+                Some(token),
+                Some(json!({"token": device})),
+                &[("idempotency-key", "fcm")],
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        // replayed rather than told the token is already known
+        assert!(body.get("message").is_none());
+    }
+}
+
+#[tokio::test]
+async fn user_changes_are_written_to_the_outbox() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let profile = |time_zone: &str| Some(json!({"time_zone": time_zone}));
+
+    app.call(Method::PATCH, "/v1/me", Some(&token), profile("Europe/Rome")).await;
+    // nothing changed, nothing to tell
+    app.call(Method::PATCH, "/v1/me", Some(&token), profile("Europe/Rome")).await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "device"})))
+        .await;
+
+    let events = app.repository.outbox();
+    let kinds: Vec<&str> = events.iter().map(|e| e.kind.as_str()).collect();
+    assert_eq!(kinds, ["user.registered", "user.updated", "fcm_token.added"]);
+    assert!(events.iter().all(|e| e.user_id == id));
+    assert_eq!(events[1].payload["time_zone"], "Europe/Rome");
+    assert!(events[0].payload.get("password").is_none());
+    assert_eq!(
+        events[2].payload["token_sha256"],
+        hex::encode(Sha256::digest(b"device"))
+    );
+}
+
+/// Fails the events it's told to, a given number of times each, and
+/// remembers the ones it took.
+#[derive(Default)]
+struct FlakySink {
+    failures: Mutex<HashMap<i64, u32>>,
+    published: Mutex<Vec<i64>>,
+}
+
+impl FlakySink {
+    fn failing(event_id: i64, times: u32) -> FlakySink {
+        let sink = FlakySink::default();
+        sink.failures.lock().unwrap().insert(event_id, times);
+        sink
+    }
+
+    fn published(&self) -> Vec<i64> {
+        self.published.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Sink for FlakySink {
+    async fn publish(&self, event: &OutboxEvent) -> Result<(), anyhow::Error> {
+        if let Some(left @ 1..) = self.failures.lock().unwrap().get_mut(&event.id) {
+            *left -= 1;
+            return Err(anyhow!("sink is down"));
+        }
+        self.published.lock().unwrap().push(event.id);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn a_failing_outbox_event_holds_back_only_its_user() {
+    let app = TestApp::new();
+    let (_, mario) = app.user("mario@example.com").await;
+    app.user("luigi@example.com").await;
+    app.call(Method::PATCH, "/v1/me", Some(&mario), Some(json!({"time_zone": "Europe/Rome"})))
+        .await;
+    // 1 and 3 are Mario's, 2 is Luigi's
+    let sink = FlakySink::failing(1, 2);
+    let outbox = &app.repository;
+    let config = &app.config.outbox;
+
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 2);
+    assert_eq!(sink.published(), [2]);
+
+    // retried after a second, then after two
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 0);
+    outbox.advance(Duration::from_secs(1));
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 1);
+    outbox.advance(Duration::from_secs(1));
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 0);
+    outbox.advance(Duration::from_secs(1));
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 1);
+    assert_eq!(sink.published(), [2, 1]);
+
+    // only now that Mario's first one is out
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 1);
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 0);
+    assert_eq!(sink.published(), [2, 1, 3]);
+    assert_eq!(outbox.published(), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn outbox_events_of_a_dead_relay_are_published_again() {
+    let app = TestApp::new();
+    app.user("mario@example.com").await;
+    let sink = FlakySink::default();
+    let outbox = &app.repository;
+    let config = &app.config.outbox;
+
+    // a relay takes the event and publishes it, then dies before marking it
+    let claimed = OutboxRepository::claim(outbox, 10, config.lease).await.unwrap();
+    sink.publish(&claimed[0]).await.unwrap();
+
+    // it's still theirs until the lease runs out
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 0);
+    outbox.advance(config.lease);
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 1);
+    assert_eq!(sink.published(), [1, 1]);
+    assert_eq!(outbox.published(), [1]);
+    assert_eq!(relay(outbox, config, &sink).await.unwrap(), 0);
+}
+
+/// A partner that's down for its first `failures` requests, remembering
+/// the deliveries it got.
+#[derive(Default)]
+struct FlakyPartner {
+    failures: Mutex<u32>,
+    received: Mutex<Vec<i64>>,
+}
+
+impl FlakyPartner {
+    fn failing(times: u32) -> FlakyPartner {
+        FlakyPartner {
+            failures: Mutex::new(times),
+            ..FlakyPartner::default()
+        }
+    }
+
+    fn received(&self) -> Vec<i64> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Sender for FlakyPartner {
+    async fn send(&self, due: &DueDelivery) -> WebhookAttempt {
+        let mut failures = self.failures.lock().unwrap();
+        let down = *failures > 0;
+        *failures = failures.saturating_sub(1);
+        if !down {
+            self.received.lock().unwrap().push(due.delivery.id);
+        }
+        WebhookAttempt {
+            delivery_id: due.delivery.id,
+            attempted_at: Utc::now(),
+            status_code: Some(if down { 503 } else { 200 }),
+            error: down.then(|| "the partner answered 503".to_string()),
+            duration_ms: 1,
+        }
+    }
+}
+
+impl TestApp {
+    /// A webhook getting every event, and the URI of its deliveries.
+    async fn webhook(&self) -> String {
+        let admin = self.admin_token().await;
+        let (_, body) = self
+            .call(
+                Method::POST,
+                "/v1/admin/webhooks",
+                Some(&admin),
+                Some(json!({"url": "https://partner.example.com/pieno"})),
+            )
+            .await;
+        format!("/v1/admin/webhooks/{}/deliveries", body["webhook"]["id"].as_str().unwrap())
+    }
+}
+
+#[tokio::test]
+async fn webhooks_only_reach_public_addresses() {
+    let app = TestApp::new();
+    let admin = app.admin_token().await;
+
+    for url in [
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.7/",
+        "http://192.168.1.1:8080/",
+        "http://127.0.0.1/",
+        "http://[::1]/",
+        "http://[::ffff:172.16.0.1]/",
+        "http://[fd00::1]/",
+        "http://localhost:3000/",
+        "http://users/",
+        "https://users.default.svc.cluster.local/",
+        "https://metadata.google.internal/",
+    ] {
+        let (status, body) = app
+            .call(Method::POST, "/v1/admin/webhooks", Some(&admin), Some(json!({"url": url})))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{url}");
+        assert_eq!(body["fields"][0]["code"], "private_url", "{url}");
+    }
+    let deliveries = app.webhook().await;
+    let (status, body) = app
+        .call(
+            Method::PATCH,
+            deliveries.trim_end_matches("/deliveries"),
+            Some(&admin),
+            Some(json!({"url": "http://10.1.2.3/"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"][0]["code"], "private_url");
+
+    // names are checked again once resolved, every time something is sent
+    let Err(e) = PublicResolver.resolve("localhost".parse().unwrap()).await else {
+        panic!("localhost was resolved");
+    };
+    assert!(e.to_string().contains("isn't a public address"));
+    app.user("mario@example.com").await;
+    let claimed = WebhookRepository::claim(&app.repository, 1, Duration::from_secs(1));
+    let mut due = claimed.await.unwrap().remove(0);
+    // as if they had been stored before the URLs were checked
+    due.url = "http://169.254.169.254/latest/meta-data".to_string();
+    let attempt = HttpSender::new(Duration::from_secs(1)).send(&due).await;
+    assert_eq!(attempt.status_code, None);
+    assert!(attempt.error.unwrap().contains("isn't a public address"));
+    due.url = "http://localhost:3000/".to_string();
+    let attempt = HttpSender::new(Duration::from_secs(1)).send(&due).await;
+    assert!(attempt.error.unwrap().contains("internal network"));
+}
+
+#[tokio::test]
+async fn webhook_deliveries_die_after_max_attempts() {
+    let mut config = config();
+    config.webhooks.max_attempts = 3;
+    let app = TestApp::with_config(config);
+    let admin = app.admin_token().await;
+    let deliveries = app.webhook().await;
+    app.user("mario@example.com").await;
+    let partner = FlakyPartner::failing(u32::MAX);
+    let webhooks = &app.repository;
+    let config = &app.config.webhooks;
+
+    // retried after 30 seconds, then after a minute
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 1);
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 0);
+    webhooks.advance(Duration::from_secs(30));
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 1);
+    webhooks.advance(Duration::from_secs(30));
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 0);
+    webhooks.advance(Duration::from_secs(30));
+    let (_, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    assert_eq!(body["deliveries"][0]["state"], "pending");
+    assert_eq!(body["deliveries"][0]["attempts"], 2);
+
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 1);
+    let (_, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    let delivery = &body["deliveries"][0];
+    assert_eq!(delivery["state"], "dead");
+    assert_eq!(delivery["attempts"], 3);
+    assert_eq!(delivery["log"].as_array().unwrap().len(), 3);
+    assert_eq!(delivery["log"][2]["status_code"], 503);
+
+    // dead for good
+    webhooks.advance(Duration::from_secs(86400));
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 0);
+    assert!(partner.received().is_empty());
+}
+
+#[tokio::test]
+async fn redelivered_webhook_deliveries_are_sent_again() {
+    let app = TestApp::new();
+    let admin = app.admin_token().await;
+    let deliveries = app.webhook().await;
+    app.user("mario@example.com").await;
+    let partner = FlakyPartner::default();
+    let webhooks = &app.repository;
+    let config = &app.config.webhooks;
+
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 1);
+    let (_, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    let id = body["deliveries"][0]["id"].as_i64().unwrap();
+    assert_eq!(body["deliveries"][0]["state"], "delivered");
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 0);
+
+    let (status, _) = app
+        .call(Method::POST, &format!("{deliveries}/{id}/redeliver"), Some(&admin), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(dispatch(webhooks, config, &partner).await.unwrap(), 1);
+    assert_eq!(partner.received(), [id, id]);
+    let (_, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    assert_eq!(body["deliveries"][0]["state"], "delivered");
+    assert_eq!(body["deliveries"][0]["log"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn security_events_are_audited() {
+    let mut config = config();
+    config.server.trust_forwarded_for = true;
+    let app = TestApp::with_config(config);
+    let headers = [
+        ("user-agent", "PIENO/2.1 (Android 14)"),
+        ("x-forwarded-for", "10.0.0.1, 203.0.113.7"),
+        ("x-request-id", "req-audit"),
+    ];
+    let credentials = json!({
+        "email": "mario@example.com",
+        "name": "Mario",
+        "surname": "Rossi",
+        "password": "Password1!",
+    });
+    let login = |email: &str, password: &str| {
+        Some(json!({"email": email, "password": password}))
+    };
+
+    let (_, body) = app
+        .call_with(Method::POST, "/v1/auth/register", None, Some(credentials), &headers)
+        .await;
+    let token = body["token"].as_str().unwrap().to_string();
+    app.call_with(Method::POST, "/v1/auth/login", None, login("mario@example.com", "wrong!!!"), &headers)
+        .await;
+    app.call_with(Method::POST, "/v1/auth/login", None, login("nobody@example.com", "wrong!!!"), &headers)
+        .await;
+    app.call_with(Method::POST, "/v1/auth/login", None, login("mario@example.com", "Password1!"), &headers)
+        .await;
+
+    let (status, body) = app
+        .call(Method::GET, "/v1/me/security-events?limit=10", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let events = body["events"].as_array().unwrap();
+    let kinds: Vec<&str> = events.iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    // the unknown email isn't theirs to see
+    assert_eq!(kinds, ["login.success", "login.failure", "user.registered"]);
+    assert_eq!(events[1]["details"]["reason"], "wrong_password");
+    assert_eq!(events[1]["actor_type"], "anonymous");
+    assert_eq!(events[0]["actor_type"], "user");
+    assert_eq!(events[0]["ip"], "203.0.113.7");
+    assert_eq!(events[0]["user_agent"], "PIENO/2.1 (Android 14)");
+    assert_eq!(events[0]["request_id"], "req-audit");
+
+    let (_, body) = app
+        .call(
+            Method::GET,
+            &format!("/v1/me/security-events?before={}", events[0]["id"]),
+            Some(&token),
+            None,
+        )
+        .await;
+    assert_eq!(body["events"].as_array().unwrap().len(), 2);
+
+    let uri = "/v1/admin/audit-events?kind=login.failure&ip=203.0.113.7";
+    // holding the secret of service tokens isn't enough, whatever they claim
+    for service in ["cars", "backoffice"] {
+        let token = app.token_of_service(service).await;
+        let (status, body) = app.call(Method::GET, uri, Some(&token), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "invalid_token");
+    }
+    let outsider = app.admin_token_of("cars").await;
+    let (status, body) = app.call(Method::GET, uri, Some(&outsider), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "admin_only");
+    let admin = app.admin_token().await;
+    let (status, body) = app.call(Method::GET, uri, Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    // what was typed for an email isn't kept, only its fingerprint
+    assert!(events[0]["details"].get("email").is_none());
+    assert_eq!(
+        events[0]["details"]["email_sha256"],
+        hex::encode(Sha256::digest(b"nobody@example.com"))
+    );
+}
+
+#[tokio::test]
+async fn webhooks_get_deliveries_of_the_events_they_want() {
+    let app = TestApp::new();
+    let admin = app.admin_token().await;
+    let create = |events: Value| {
+        Some(json!({"url": "https://partner.example.com/pieno", "events": events}))
+    };
+
+    let (status, body) = app
+        .call(Method::POST, "/v1/admin/webhooks", Some(&admin), Some(json!({"url": "ftp://nope"})))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_fields");
+    let (status, body) = app
+        .call(Method::POST, "/v1/admin/webhooks", Some(&admin), create(json!(["user.registered"])))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["secret"].as_str().unwrap().len(), 40);
+    let webhook = body["webhook"]["id"].as_str().unwrap().to_string();
+    app.call(Method::POST, "/v1/admin/webhooks", Some(&admin), create(json!([])))
+        .await;
+    let (_, body) = app.call(Method::GET, "/v1/admin/webhooks", Some(&admin), None).await;
+    assert_eq!(body["webhooks"].as_array().unwrap().len(), 2);
+    assert!(body["webhooks"][0].get("secret").is_none());
+
+    let (id, token) = app.user("mario@example.com").await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "device-token-of-mario"})))
+        .await;
+
+    // partners never get the token itself: it's all it takes to push to it
+    let everything = format!("/v1/admin/webhooks/{}/deliveries", body["webhooks"][1]["id"].as_str().unwrap());
+    let (_, body) = app.call(Method::GET, &everything, Some(&admin), None).await;
+    let kinds: Vec<&str> = body["deliveries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["fcm_token.added", "user.registered"]);
+    assert!(body["deliveries"][0]["payload"]["token_sha256"].is_string());
+    assert!(!body.to_string().contains("device-token-of-mario"));
+
+    let deliveries = format!("/v1/admin/webhooks/{webhook}/deliveries");
+    let (status, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let delivered = body["deliveries"].as_array().unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0]["kind"], "user.registered");
+    assert_eq!(delivered[0]["state"], "pending");
+    assert_eq!(delivered[0]["payload"]["id"], id);
+    assert!(delivered[0]["payload"].get("password").is_none());
+
+    let redeliver = format!("{deliveries}/{}/redeliver", delivered[0]["id"]);
+    let (status, body) = app.call(Method::POST, &redeliver, Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["delivery"]["attempts"], 0);
+    let (status, body) = app
+        .call(Method::POST, &format!("{deliveries}/999/redeliver"), Some(&admin), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "webhook_delivery_not_found");
+
+    // disabled, it doesn't get new events anymore
+    let uri = format!("/v1/admin/webhooks/{webhook}");
+    app.call(Method::PATCH, &uri, Some(&admin), Some(json!({"enabled": false})))
+        .await;
+    app.user("luigi@example.com").await;
+    let (_, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    assert_eq!(body["deliveries"].as_array().unwrap().len(), 1);
+
+    let (status, _) = app.call(Method::DELETE, &uri, Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app.call(Method::GET, &deliveries, Some(&admin), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "webhook_not_found");
+
+    // every change is in the audit log, secrets aside
+    let (_, body) = app
+        .call(Method::GET, "/v1/admin/audit-events", Some(&admin), None)
+        .await;
+    let events: Vec<&Value> = body["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["kind"].as_str().unwrap().starts_with("webhook."))
+        .collect();
+    let kinds: Vec<&str> = events.iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(
+        kinds,
+        [
+            "webhook.deleted",
+            "webhook.updated",
+            "webhook.redelivered",
+            "webhook.created",
+            "webhook.created",
+        ]
+    );
+    assert!(events.iter().all(|e| e["actor_type"] == "service"
+        && e["actor_id"] == "backoffice"
+        && e["details"]["webhook_id"].is_string()));
+    assert_eq!(events[1]["details"]["changes"]["enabled"], false);
+    assert!(!body.to_string().contains("secret\":\""));
+
+    // the same as `openssl dgst -sha256 -hmac <secret>`
+    assert_eq!(
+        signature("whsec_test_secret_123", 1700000000, br#"{"id":1}"#),
+        "t=1700000000,v1=b00bc8211c746c8bb3adfaaa71164918a747ccf17a0c4f11ae4169757c64c7f0"
+    );
+}
+
+#[tokio::test]
+async fn notification_settings_round_trip() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let settings = json!({
+        "channels": {"car_invite": {"push": false, "email": false, "in_app": false}},
+        "muted_senders": [],
+        "muted_cars": [],
+    });
+
+    let (status, _) = app
+        .call(
+            Method::PUT,
+            "/v1/me/notification-settings",
+            Some(&token),
+            Some(settings),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notification-settings", Some(&token), None)
+        .await;
+    assert_eq!(body["settings"]["channels"]["car_invite"]["in_app"], false);
+
+    // quiet hours are in the time zone of the profile
+    let settings: NotificationSettings = serde_json::from_value(json!({
+        "quiet_hours": {"start": "22:00", "end": "07:00"},
+    }))
+    .unwrap();
+    let evening = "2026-10-19T20:30:00Z".parse().unwrap();
+    assert!(settings.in_quiet_hours(evening, "Europe/Rome"));
+    assert!(!settings.in_quiet_hours(evening, "UTC"));
+
+    // in-app is off for invites, so they go straight to the archive
+    let service = app.service_token().await;
+    app.call(
+        Method::POST,
+        "/v1/internal/notifications",
+        Some(&service),
+        Some(json!({"recipients": [id], "payload": car_invite(&id)})),
+    )
+    .await;
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications/unread-count", Some(&token), None)
+        .await;
+    assert_eq!(body["unread"], 0);
+}
+
+#[tokio::test]
+async fn pushes_follow_the_settings_of_each_recipient() {
+    let messenger = FakeMessenger::start().await;
+    let app = TestApp::with_push(config(), messenger.client());
+    let (sender, _) = app.user("luigi@example.com").await;
+    let now = Utc::now();
+    let hour = chrono::Duration::hours(1);
+    let quiet_now = json!({
+        "start": (now - hour).format("%H:%M").to_string(),
+        "end": (now + hour).format("%H:%M").to_string(),
+    });
+    let cases = [
+        ("muted", json!({"muted_senders": [sender]})),
+        (
+            "no-push",
+            json!({"channels": {"car_invite": {"push": false, "email": true, "in_app": true}}}),
+        ),
+        ("quiet", json!({"quiet_hours": quiet_now})),
+        ("default", json!({})),
+    ];
+    let mut recipients = vec![];
+    for (name, settings) in cases {
+        let (id, token) = app.user(&format!("{name}@example.com")).await;
+        let (status, _) = app
+            .call(Method::PUT, "/v1/me/notification-settings", Some(&token), Some(settings))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{name}");
+        app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": name})))
+            .await;
+        recipients.push((id, token));
+    }
+
+    let service = app.service_token().await;
+    let ids: Vec<&String> = recipients.iter().map(|(id, _)| id).collect();
+    app.call(
+        Method::POST,
+        "/v1/internal/notifications",
+        Some(&service),
+        Some(json!({"recipients": ids, "payload": car_invite(&sender), "push": true})),
+    )
+    .await;
+
+    // muted or not, everybody has it in the app
+    for (_, token) in &recipients {
+        let (_, body) = app
+            .call(Method::GET, "/v1/me/notifications/unread-count", Some(token), None)
+            .await;
+        assert_eq!(body["unread"], 1);
+    }
+    let received = messenger.received(&app).await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["tokens"], json!(["default"]));
+    // the quiet one gets it in the digest, once quiet hours are over
+    let held = app.repository.held_pushes();
+    assert_eq!(held.len(), 1);
+    assert_eq!(held[0].0, recipients[2].0);
+    assert_eq!(held[0].1, "New car invite");
+}
+
+#[tokio::test]
+async fn pushes_are_written_for_each_recipient() {
+    let messenger = FakeMessenger::start().await;
+    let app = TestApp::with_push(config(), messenger.client());
+    let (sender, _) = app.user("luigi@example.com").await;
+    let (english, token) = app.user("john@example.com").await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "en"})))
+        .await;
+    let (italian, token) = app.user("mario@example.com").await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "it"})))
+        .await;
+
+    // the profile says how they read
+    let (status, body) = app
+        .call(
+            Method::PATCH,
+            "/v1/me",
+            Some(&token),
+            Some(json!({"locale": "it", "time_zone": "Europe/Rome"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["locale"], "it");
+    assert_eq!(body["user"]["time_zone"], "Europe/Rome");
+    let (status, body) = app
+        .call(Method::PATCH, "/v1/me", Some(&token), Some(json!({"time_zone": "Rome"})))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"][0]["field"], "time_zone");
+    assert_eq!(body["fields"][0]["code"], "invalid_time_zone");
+
+    let service = app.service_token().await;
+    let in_rome = |at| Locale::It.format_datetime(at, chrono_tz::Europe::Rome);
+    let before = Utc::now();
+    app.call(
+        Method::POST,
+        "/v1/internal/notifications",
+        Some(&service),
+        Some(json!({
+            "recipients": [english, italian],
+            "payload": {
+                "type": "refuel",
+                "v": 1,
+                "owner": sender,
+                "car_name": "Panda",
+                "tank_size": 35,
+                "consumption": 5.5,
+                "fuel_value": 20.5,
+            },
+            "push": true,
+        })),
+    )
+    .await;
+    let after = Utc::now();
+
+    let received = messenger.received(&app).await;
+    let push_to = |token: &str| {
+        received
+            .iter()
+            .find(|push| push["tokens"] == json!([token]))
+            .unwrap()
+            .clone()
+    };
+    let push = push_to("en");
+    assert_eq!(push["title"], "Panda refueled");
+    assert!(push["body"]
+        .as_str()
+        .unwrap()
+        .starts_with("Mario Rossi put 20.5 l of fuel in Panda on "));
+    let push = push_to("it");
+    assert_eq!(push["title"], "Rifornimento per Panda");
+    let body = push["body"].as_str().unwrap();
+    let expected = |at| {
+        format!("Mario Rossi ha messo 20,5 l di carburante in Panda il {}", in_rome(at))
+    };
+    assert!(body == expected(before) || body == expected(after), "{body}");
+
+    // and timestamps are RFC 3339, for clients to show as they like
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+        .await;
+    let created_at = body["notifications"][0]["created_at"].as_str().unwrap();
+    let created_at = chrono::DateTime::parse_from_rfc3339(created_at).unwrap();
+    assert!(before.timestamp() <= created_at.timestamp());
+    assert!(created_at.timestamp() <= after.timestamp());
+}
+
+#[tokio::test]
+async fn validation_errors_point_at_fields() {
+    let app = TestApp::new();
+    let (status, body) = app
+        .call(
+            Method::POST,
+            "/auth/register",
+            None,
+            Some(json!({"email": "nope", "name": "", "surname": "", "password": "short"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["fields"],
+        json!([
+            {
+                "field": "email",
+                "code": "email",
+                "message": "must be a valid email address",
+                "params": {},
+            },
+            {
+                "field": "password",
+                "code": "length",
+                "message": "must be at least 8 characters long",
+                "params": {"min": 8},
+            },
+        ])
+    );
+
+    let (_, token) = app.user("mario@example.com").await;
+    let (status, body) = app
+        .call(
+            Method::PUT,
+            "/v1/me/notification-settings",
+            Some(&token),
+            Some(json!({
+                "channels": {"car_crash": {"push": true, "email": true, "in_app": true}},
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"][0]["field"], "channels");
+    assert_eq!(body["fields"][0]["code"], "unknown_notification_type");
+}
+
+#[tokio::test]
+async fn errors_speak_the_callers_language() {
+    let app = TestApp::new();
+    let (status, body) = app
+        .call_with(
+            Method::POST,
+            "/auth/register",
+            None,
+            Some(json!({"email": "mario@example.com", "name": "Mario", "surname": "Rossi", "password": "short"})),
+            &[("accept-language", "fr-FR, it;q=0.8, en;q=0.5")],
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["title"], "Campi non validi");
+    assert_eq!(body["detail"], "campi non validi: password");
+    assert_eq!(
+        body["fields"][0]["message"],
+        "deve essere lungo almeno 8 caratteri"
+    );
+
+    // without the header, the locale the user saved wins
+    let (id, token) = app.user("mario@example.com").await;
+    app.repository
+        .update_user(&id, |user| user.locale = "it".to_string());
+    let uri = "/v1/me/notifications/unknown";
+    let (_, body) = app.call(Method::DELETE, uri, Some(&token), None).await;
+    assert_eq!(body["title"], "Notifica non trovata");
+    assert_eq!(body["code"], "notification_not_found");
+
+    let (_, body) = app
+        .call_with(Method::DELETE, uri, Some(&token), None, &[("accept-language", "en-GB")])
+        .await;
+    assert_eq!(body["title"], "Notification not found");
+}
+
+#[tokio::test]
+async fn malformed_requests_say_where() {
+    let app = TestApp::new();
+    let login = |body: &str| {
+        Request::post("/auth/login")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let (status, body) = send(&app.router, login(r#"{"email": 3}"#)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_body");
+    assert_eq!(body["fields"][0]["field"], "email");
+    assert_eq!(body["location"], json!({"line": 1, "column": 11}));
+
+    let (_, body) = send(&app.router, login("{\n  \"email\": ")).await;
+    assert_eq!(body["code"], "invalid_body");
+    assert!(body.get("fields").is_none());
+    assert_eq!(body["location"]["line"], 2);
+
+    let (_, token) = app.user("mario@example.com").await;
+    let (status, body) = app
+        .call(Method::GET, "/v1/me/notifications?archived=maybe", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_query");
+    assert_eq!(body["fields"][0]["field"], "archived");
+}
+
+#[tokio::test]
+async fn forms_are_validated_too() {
+    #[derive(serde::Deserialize, validator::Validate)]
+    struct Form {
+        #[validate(length(min = 3))]
+        name: String,
+    }
+
+    let router = Router::new().route(
+        "/",
+        post(|ValidatedForm(form): ValidatedForm<Form>| async move {
+            form.name
+        }),
+    );
+    let form = |body: &str| {
+        Request::post("/")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let (status, _) = send(&router, form("name=mario")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(&router, form("name=mo")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"][0]["field"], "name");
+    let (_, body) = send(&router, form("")).await;
+    assert_eq!(body["code"], "invalid_body");
+}
+
+/// The router and the OpenAPI document are built from the same route
+/// tables: every documented operation has to be routed, and every other
+/// method on a documented path refused.
+#[tokio::test]
+async fn every_route_is_documented() {
+    let app = TestApp::new();
+    let mut documented = serde_json::Map::new();
+    for &version in ApiVersion::ALL {
+        let spec = serde_json::to_value(api_doc(version)).unwrap();
+        documented.extend(spec["paths"].as_object().unwrap().clone());
+    }
+    assert!(documented.contains_key("/v1/me/notifications/{id}"));
+    assert!(documented.contains_key("/health/ready"));
+
+    for (spec_path, item) in &documented {
+        let uri = spec_path
+            .split('/')
+            .map(|segment| match segment.starts_with('{') {
+                true => "unknown",
+                false => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        for method in [Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE] {
+            let operation = &item[method.as_str().to_lowercase()];
+            let (status, body) = app.call(method.clone(), &uri, None, None).await;
+            if !operation.is_object() {
+                assert_eq!(
+                    status,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "{method} {spec_path} is routed, but not documented"
+                );
+                continue;
+            }
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {spec_path} isn't routed");
+            assert!(
+                status != StatusCode::NOT_FOUND || body["code"].is_string(),
+                "{method} {spec_path} isn't routed"
+            );
+            if body["code"] == "no_auth_header" {
+                assert!(
+                    operation["security"].is_array(),
+                    "{method} {spec_path} needs a token, but the docs don't say so"
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn unversioned_routes_are_deprecated() {
+    let mut config = config();
+    config.server.unversioned_deprecated_at = "2026-11-01T00:00:00Z".parse().unwrap();
+    config.server.unversioned_sunset = "2027-04-01T00:00:00Z".parse().ok();
+    let app = TestApp::with_config(config);
+    let (_, token) = app.user("mario@example.com").await;
+    let get = |uri: &str| {
+        Request::get(uri)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.router.clone().oneshot(get("/v1/me/notifications")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("deprecation").is_none());
+
+    let response = app.router.clone().oneshot(get("/me/notifications")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers["deprecation"], "@1793491200");
+    assert_eq!(headers["sunset"], "Thu, 01 Apr 2027 00:00:00 GMT");
+    assert_eq!(
+        headers["link"],
+        "</v1/me/notifications>; rel=\"successor-version\""
+    );
+
+    // endpoints newer than versions were never at the root
+    for uri in [
+        "/me/notifications/stream",
+        "/me/notification-settings",
+        "/me/security-events",
+        "/admin/webhooks",
+    ] {
+        let response = app.router.clone().oneshot(get(uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        let response = app.router.clone().oneshot(get(&format!("/v1{uri}"))).await.unwrap();
+        assert_ne!(response.status(), StatusCode::NOT_FOUND, "/v1{uri}");
+    }
+    let (status, _) = app
+        .call(Method::POST, "/internal/notifications", None, Some(json!({})))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // and each document describes its own
+    let v1 = serde_json::to_value(api_doc(ApiVersion::V1)).unwrap();
+    let unversioned = serde_json::to_value(api_doc(ApiVersion::Unversioned)).unwrap();
+    assert!(v1["paths"]["/v1/auth/login"]["post"]["deprecated"].is_null());
+    assert_eq!(unversioned["paths"]["/auth/login"]["post"]["deprecated"], true);
+    assert!(v1["paths"]["/v1/internal/notifications"].is_object());
+    assert!(unversioned["paths"]["/internal/notifications"].is_null());
+    assert!(v1["paths"]["/health/live"].is_object());
+}
+
+#[tokio::test]
+async fn unversioned_routes_get_the_old_shapes() {
+    let app = TestApp::new();
+    let (id, token) = app.user("mario@example.com").await;
+    let service = app.service_token().await;
+    let (status, _) = app
+        .call(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({
+                "recipients": [id],
+                "payload": {
+                    "type": "refuel",
+                    "v": 1,
+                    "owner": id,
+                    "car_name": "Panda",
+                    "tank_size": 35.5,
+                    "consumption": 5,
+                    "fuel_value": 20.25,
+                },
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    // written before payloads were typed, by a producer with decimal commas
+    let legacy = json!({
+        "owner": id,
+        "car_name": "Punto",
+        "tank_size": "45.0",
+        "consumption": "6,1",
+        "fuel_value": "30",
+    });
+    app.repository
+        .insert_batch(std::slice::from_ref(&id), &[], &legacy, None)
+        .await
+        .unwrap();
+    let invite = car_invite(&id);
+    app.repository
+        .insert_batch(std::slice::from_ref(&id), &[], &invite, None)
+        .await
+        .unwrap();
+
+    let (_, body) = app
+        .call(Method::GET, "/v1/me/notifications", Some(&token), None)
+        .await;
+    let latest = body["notifications"].as_array().unwrap();
+    assert_eq!(latest.len(), 3);
+    assert_eq!(latest[2]["data"]["tank_size"], 35.5);
+    assert_eq!(latest[2]["data"]["consumption"], 5.0);
+    assert_eq!(latest[1]["data"]["consumption"], 6.1);
+
+    // exactly what the service answered before /v1: no read state, no
+    // sender, strings as they were stored and Postgres' to_char dates. car
+    // invites didn't exist
+    let (_, body) = app
+        .call(Method::GET, "/me/notifications", Some(&token), None)
+        .await;
+    // to_char(created_at, 'dd-mm-yyyy HH24:MI:SS (utc)')
+    let created_at = |notification: &Value| {
+        let at: chrono::DateTime<Utc> =
+            notification["created_at"].as_str().unwrap().parse().unwrap();
+        format!(
+            "{:02}-{:02}-{} {:02}:{:02}:{:02} (utc)",
+            at.day(),
+            at.month(),
+            at.year(),
+            at.hour(),
+            at.minute(),
+            at.second()
+        )
+    };
+    assert_eq!(
+        body,
+        json!({
+            "success": true,
+            "notifications": [
+                {
+                    "id": latest[1]["id"],
+                    "to_user": id,
+                    "created_at": created_at(&latest[1]),
+                    "data": {
+                        "owner": id,
+                        "car_name": "Punto",
+                        "tank_size": "45.0",
+                        "consumption": "6,1",
+                        "fuel_value": "30",
+                    },
+                },
+                {
+                    "id": latest[2]["id"],
+                    "to_user": id,
+                    "created_at": created_at(&latest[2]),
+                    "data": {
+                        "owner": id,
+                        "car_name": "Panda",
+                        "tank_size": "35.5",
+                        // stored typed, as a float
+                        "consumption": "5.0",
+                        "fuel_value": "20.25",
+                    },
+                },
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn browsers_get_cors_and_security_headers() {
+    let mut config = config();
+    config.cors.allowed_origins = vec!["https://dashboard.pieno.app".to_string()];
+    config.cors.allow_credentials = true;
+    config.security_headers.overrides.insert(
+        RouteGroup::Api,
+        SecurityHeaders {
+            referrer_policy: Some("same-origin".to_string()),
+            ..SecurityHeaders::default()
+        },
+    );
+    config.security_headers.overrides.insert(
+        RouteGroup::Service,
+        SecurityHeaders {
+            content_security_policy: Some(String::new()),
+            ..SecurityHeaders::default()
+        },
+    );
+    let app = TestApp::with_config(config);
+
+    let preflight = |origin: &str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/v1/auth/login")
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app
+        .router
+        .clone()
+        .oneshot(preflight("https://dashboard.pieno.app"))
+        .await
+        .unwrap();
+    let headers = response.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://dashboard.pieno.app"
+    );
+    assert_eq!(headers["access-control-allow-credentials"], "true");
+    let response = app
+        .router
+        .clone()
+        .oneshot(preflight("https://evil.example"))
+        .await
+        .unwrap();
+    assert!(response.headers().get("access-control-allow-origin").is_none());
+
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+    let response = app.router.clone().oneshot(get("/v1/auth")).await.unwrap();
+    let headers = response.headers();
+    assert_eq!(headers["strict-transport-security"], "max-age=60");
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["content-security-policy"], "default-src 'none'");
+
+    // overridden, down to dropping a header
+    let response = app.router.clone().oneshot(get("/health/live")).await.unwrap();
+    assert!(response.headers().get("content-security-policy").is_none());
+    assert_eq!(response.headers()["referrer-policy"], "no-referrer");
+
+    // a method the route doesn't have is still answered by its group
+    let response = app
+        .router
+        .clone()
+        .oneshot(Request::delete("/v1/auth/login").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(response.headers()["referrer-policy"], "same-origin");
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+
+    // while a path no group has gets the defaults
+    let response = app.router.clone().oneshot(get("/nowhere")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let headers = response.headers();
+    assert_eq!(headers["referrer-policy"], "no-referrer");
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["content-security-policy"], "default-src 'none'");
+}
+
+#[tokio::test]
+async fn readiness_only_tells_what_failed() {
+    let app = TestApp::new();
+    // the pool points at a database that isn't there
+    let (status, body) = app.call(Method::GET, "/health/ready", None, None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["database"]["ok"], false);
+    assert!(["unavailable", "timed_out"]
+        .contains(&body["checks"]["database"]["error"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn shutting_down_fails_readiness_and_closes_streams() {
+    let app = TestApp::new();
+    let (_, token) = app.user("mario@example.com").await;
+    let response = app
+        .router
+        .clone()
+        .oneshot(
+            Request::get("/v1/me/notifications/stream")
+                .header(AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let mut stream = response.into_body().into_data_stream();
+
+    // liveness never looks at the database, which isn't there
+    let (status, body) = app.call(Method::GET, "/health/live", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({"success": true}));
+
+    app.shutdown.cancel();
+    let (status, body) = app.call(Method::GET, "/health/ready", None, None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        body,
+        json!({
+            "success": false,
+            "checks": {"shutdown": {"ok": false, "error": "shutting_down"}},
+        })
+    );
+    let (status, _) = app.call(Method::GET, "/health/live", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    // so that draining connections doesn't wait on it
+    while let Some(chunk) = tokio::time::timeout(Duration::from_secs(1), stream.next())
+        .await
+        .expect("the stream is still open")
+    {
+        chunk.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_labelled_by_route_template() {
+    let app = TestApp::new();
+    let (_, token) = app.user("mario@example.com").await;
+    let id = User::new_id();
+    let (status, _) = app
+        .call(
+            Method::DELETE,
+            &format!("/v1/me/notifications/{id}"),
+            Some(&token),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .call(Method::GET, &format!("/v1/no-such-route/{id}"), None, None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .call(
+            Method::POST,
+            "/v1/auth/login",
+            None,
+            Some(json!({"email": "nobody@example.com", "password": "Password1!"})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let response = app
+        .router
+        .clone()
+        .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let metrics = String::from_utf8(bytes.to_vec()).unwrap();
+
+    for series in [
+        r#"http_requests_total{method="DELETE",route="/v1/me/notifications/:id",status="404"}"#,
+        r#"http_requests_total{method="GET",route="unmatched",status="404"}"#,
+        r#"http_request_duration_seconds_bucket{method="POST",route="/v1/auth/login",status="401",le="0.001"}"#,
+        r#"logins_total{outcome="failure"}"#,
+        r#"db_pool_connections{state="max"}"#,
+    ] {
+        assert!(metrics.contains(series), "{series} is missing");
+    }
+    // the ids asked for never make it to a label
+    assert!(!metrics.contains(&id));
+}
+
+#[tokio::test]
+async fn pushes_carry_on_the_callers_trace() {
+    // what `telemetry::init` sets up, without exporting anything
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = TracerProvider::builder().build();
+    let _tracing = tracing::subscriber::set_default(
+        tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("users"))),
+    );
+    let messenger = FakeMessenger::start().await;
+    let app = TestApp::with_push(config(), messenger.client());
+    let (id, token) = app.user("mario@example.com").await;
+    let (sender, _) = app.user("luigi@example.com").await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "device"})))
+        .await;
+    let service = app.service_token().await;
+    let notify = |headers: &'static [(&'static str, &'static str)]| {
+        app.call_with(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({
+                "recipients": [id],
+                "payload": car_invite(&sender),
+                "push": true,
+            })),
+            headers,
+        )
+    };
+
+    const CALLER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let (status, _) = notify(&[("traceparent", CALLER)]).await;
+    assert_eq!(status, StatusCode::OK);
+    // without one, it's a trace of its own
+    let (status, _) = notify(&[]).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(messenger.received(&app).await.len(), 2);
+    let sent: Vec<Vec<String>> = messenger
+        .headers
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|headers| {
+            headers["traceparent"]
+                .to_str()
+                .unwrap()
+                .split('-')
+                .map(str::to_string)
+                .collect()
+        })
+        .collect();
+    assert_eq!(sent[0][1], "4bf92f3577b34da6a3ce929d0e0e4736");
+    // the parent is one of our spans, not the caller's
+    assert_ne!(sent[0][2], "00f067aa0ba902b7");
+    assert_eq!(sent[0][3], "01");
+    assert_ne!(sent[1][1], sent[0][1]);
+    assert_ne!(sent[1][1], "0".repeat(32));
+}
+
+/// Log lines, as JSON like in production.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for Logs {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Logs {
+    fn lines(&self) -> Vec<Value> {
+        let bytes = self.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn requests_get_an_id_and_a_log_line() {
+    let logs = Logs::default();
+    let _tracing = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish(),
+    );
+    let messenger = FakeMessenger::start().await;
+    let app = TestApp::with_push(config(), messenger.client());
+    let (id, token) = app.user("mario@example.com").await;
+    let (sender, _) = app.user("luigi@example.com").await;
+    app.call(Method::PUT, "/v1/auth/fcm", Some(&token), Some(json!({"token": "device"})))
+        .await;
+    let service = app.service_token().await;
+    let list_with = |token: &str, request_id: Option<&str>| {
+        let mut request = Request::get("/v1/me/notifications")
+            .header(AUTHORIZATION, format!("Bearer {token}"));
+        if let Some(request_id) = request_id {
+            request = request.header("x-request-id", request_id);
+        }
+        let response = app
+            .router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap());
+        async move {
+            let response = response.await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            response.headers()["x-request-id"].to_str().unwrap().to_string()
+        }
+    };
+
+    // the caller's is kept, and goes along to fcm-messenger
+    let (status, _) = app
+        .call_with(
+            Method::POST,
+            "/v1/internal/notifications",
+            Some(&service),
+            Some(json!({
+                "recipients": [id],
+                "payload": car_invite(&sender),
+                "push": true,
+            })),
+            &[("x-request-id", "req-push")],
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    messenger.received(&app).await;
+    assert_eq!(messenger.headers.lock().unwrap()[0]["x-request-id"], "req-push");
+    assert_eq!(list_with(&token, Some("req-list")).await, "req-list");
+    // made up when missing, or when it's no good for a log line
+    let made_up = list_with(&token, None).await;
+    assert!(!made_up.is_empty());
+    assert_ne!(list_with(&token, None).await, made_up);
+    let long = "x".repeat(129);
+    for bad in ["has spaces", long.as_str()] {
+        assert_ne!(list_with(&token, Some(bad)).await, bad);
+    }
+
+    let lines = logs.lines();
+    let access_log = |request_id: &str| {
+        lines
+            .iter()
+            .find(|line| line["target"] == "access_log" && line["request_id"] == request_id)
+            .unwrap_or_else(|| panic!("no access log for {request_id}"))
+    };
+    let push = access_log("req-push");
+    assert_eq!(push["method"], "POST");
+    assert_eq!(push["route"], "/v1/internal/notifications");
+    assert_eq!(push["status"], 200);
+    assert!(push["latency_ms"].is_f64());
+    // services aren't users
+    assert!(push.get("user_id").is_none());
+    let list = access_log("req-list");
+    assert_eq!(list["route"], "/v1/me/notifications");
+    assert_eq!(list["user_id"], id.as_str());
+    assert_eq!(access_log(&made_up)["status"], 200);
+    // what's logged while handling it says which request it was about
+    assert!(lines.iter().any(|line| {
+        line["target"] != "access_log"
+            && line["span"]["request_id"] == "req-push"
+            && line["message"].as_str().unwrap().contains("created 1 notifications")
+    }));
+}
@@ -1,28 +1,39 @@
 use argon2::{password_hash::{rand_core::OsRng, SaltString}, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use axum::http::StatusCode;
+use tracing::info_span;
 
-use super::errors::HttpError;
+use super::{errors::{code::ErrorCode, HttpError}, metrics::{PASSWORD_HASHING_DURATION, PASSWORD_HASHING_QUEUE}};
 
 pub async fn hash_password(password: &str) -> Result<String, HttpError> {
     let password = password.to_string();
-    Ok(tokio::task::spawn_blocking(move || {
+    // blocking threads don't inherit the current span, it has to be carried over
+    let span = info_span!("argon2.hash");
+    PASSWORD_HASHING_QUEUE.inc();
+    tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        PASSWORD_HASHING_QUEUE.dec();
+        let _timer = PASSWORD_HASHING_DURATION.with_label_values(&["hash"]).start_timer();
         let salt = SaltString::generate(&mut OsRng);
         let argon2 = Argon2::default(); // default settings, we can tweak later
-        match argon2.hash_password(&password.as_bytes(), &salt){
+        match argon2.hash_password(password.as_bytes(), &salt){
             Ok(hash) => Ok(hash.to_string()),
-            Err(_) => Err(HttpError::Simple(StatusCode::INTERNAL_SERVER_ERROR, "internal_server_error".to_string()))
+            Err(_) => Err(HttpError::Simple(ErrorCode::InternalServerError))
         }
-    }).await??)
+    }).await?
 }
 
 pub async fn verify_password(password: &str, hash: &str) -> Result<bool, HttpError> {
     let password = password.to_string();
     let hash = hash.to_string();
+    let span = info_span!("argon2.verify");
+    PASSWORD_HASHING_QUEUE.inc();
     Ok(tokio::task::spawn_blocking(move || {
+        let _span = span.enter();
+        PASSWORD_HASHING_QUEUE.dec();
+        let _timer = PASSWORD_HASHING_DURATION.with_label_values(&["verify"]).start_timer();
         let argon2 = Argon2::default(); // default settings, we can tweak later
         let hash = PasswordHash::new(&hash)?;
 
         Ok::<bool, argon2::password_hash::Error>(argon2.verify_password(password.as_bytes(), &hash).is_ok())
         
     }).await??)
-}
\ No newline at end of file
+}
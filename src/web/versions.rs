@@ -0,0 +1,201 @@
+use async_trait::async_trait;
+use axum::{
+    extract::FromRequestParts,
+    http::request::Parts,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::config::ServerConfig;
+
+use super::{
+    dto::{
+        admin::{
+            audit_events_query::AuditEventsResponse,
+            create_webhook_request::{CreateWebhookResponse, WebhooksResponse},
+            update_webhook_request::WebhookResponse,
+            webhook_deliveries_query::{
+                WebhookDeliveriesResponse, WebhookDeliveryResponse,
+            },
+        },
+        auth::{
+            logged_user_response::LoggedUserResponse,
+            login_request::LoginResponse, register_request::RegisterResponse,
+        },
+        internal::create_notifications_request::CreateNotificationsResponse,
+        me::{
+            mark_read_request::MarkReadResponse,
+            notification_settings::NotificationSettingsResponse,
+            notifications::{
+                Notification, NotificationResponse, UnreadCountResponse,
+            },
+            security_events::SecurityEventsResponse,
+        },
+    },
+    errors::{code::ErrorCode, HttpError},
+};
+
+/// Versions of the API. They're all the same handlers, mounted under their
+/// own prefix: what changes between them are the bodies, see [`Versioned`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// the routes at the root, from before there were versions
+    Unversioned,
+    V1,
+}
+
+impl ApiVersion {
+    pub const ALL: &'static [ApiVersion] = &[ApiVersion::Unversioned, ApiVersion::V1];
+    pub const LATEST: ApiVersion = ApiVersion::V1;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiVersion::Unversioned => "unversioned",
+            ApiVersion::V1 => "v1",
+        }
+    }
+
+    /// Where its routes are mounted, e.g. `/v1`.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ApiVersion::Unversioned => "",
+            ApiVersion::V1 => "/v1",
+        }
+    }
+
+    /// Where its OpenAPI document is served.
+    pub fn schema_path(self) -> &'static str {
+        match self {
+            ApiVersion::Unversioned => "/json-schema",
+            ApiVersion::V1 => "/v1/json-schema",
+        }
+    }
+
+    /// Whether a newer version replaces it. Since when is up to the
+    /// config, see [`ApiVersion::deprecated_at`].
+    pub fn is_deprecated(self) -> bool {
+        match self {
+            ApiVersion::Unversioned => true,
+            ApiVersion::V1 => false,
+        }
+    }
+
+    /// When it was deprecated in favour of a newer one, if it was.
+    pub fn deprecated_at(self, config: &ServerConfig) -> Option<DateTime<Utc>> {
+        match self {
+            ApiVersion::Unversioned => Some(config.unversioned_deprecated_at),
+            ApiVersion::V1 => None,
+        }
+    }
+}
+
+impl std::str::FromStr for ApiVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ApiVersion::ALL
+            .iter()
+            .find(|version| version.name() == s)
+            .copied()
+            .ok_or(format!("unknown API version `{s}`"))
+    }
+}
+
+/// The version the request came in through, set on each route tree.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiVersion {
+    type Rejection = HttpError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ApiVersion>()
+            .copied()
+            // a route that isn't in any version tree is a bug of ours
+            .ok_or(HttpError::Simple(ErrorCode::InternalServerError))
+    }
+}
+
+/// A body whose shape can change between versions. By default it's the
+/// same in all of them: a version that breaks it overrides `adapt`, so
+/// handlers keep returning the latest DTO.
+pub trait VersionedBody: Serialize + Sized {
+    fn adapt(self, _version: ApiVersion) -> serde_json::Result<Value> {
+        serde_json::to_value(self)
+    }
+}
+
+/// `body`, the way `version` expects it. Every handler of the versioned
+/// routes answers with one of these.
+pub struct Versioned<T>(pub ApiVersion, pub T);
+
+impl<T: VersionedBody> IntoResponse for Versioned<T> {
+    fn into_response(self) -> Response {
+        let Versioned(version, body) = self;
+        match body.adapt(version) {
+            Ok(body) => Json(body).into_response(),
+            Err(err) => {
+                error!("couldn't serialize the body for {}: {err}", version.name());
+                HttpError::Simple(ErrorCode::InternalServerError).into_response()
+            }
+        }
+    }
+}
+
+/// Bodies built on the spot, such as `{"success": true}`.
+impl VersionedBody for Value {}
+
+/// Unversioned callers get notifications the way they were served before
+/// `/v1`, see `Notification::legacy`.
+impl VersionedBody for Notification {
+    fn adapt(self, version: ApiVersion) -> serde_json::Result<Value> {
+        match (version, self.legacy()) {
+            (ApiVersion::Unversioned, Some(legacy)) => Ok(legacy),
+            _ => serde_json::to_value(self),
+        }
+    }
+}
+
+impl VersionedBody for NotificationResponse {
+    fn adapt(self, version: ApiVersion) -> serde_json::Result<Value> {
+        let notifications = match version {
+            // the old apps decode every notification as a refuel, so a
+            // single one of another kind would break their whole list
+            ApiVersion::Unversioned => self
+                .notifications
+                .iter()
+                .filter_map(Notification::legacy)
+                .collect(),
+            _ => self
+                .notifications
+                .into_iter()
+                .map(|notification| notification.adapt(version))
+                .collect::<serde_json::Result<Vec<_>>>()?,
+        };
+
+        Ok(json!({"success": self.success, "notifications": notifications}))
+    }
+}
+
+// the same in every version
+impl VersionedBody for LoginResponse {}
+impl VersionedBody for RegisterResponse {}
+impl VersionedBody for LoggedUserResponse {}
+impl VersionedBody for UnreadCountResponse {}
+impl VersionedBody for MarkReadResponse {}
+impl VersionedBody for NotificationSettingsResponse {}
+impl VersionedBody for SecurityEventsResponse {}
+impl VersionedBody for CreateNotificationsResponse {}
+impl VersionedBody for AuditEventsResponse {}
+impl VersionedBody for WebhooksResponse {}
+impl VersionedBody for CreateWebhookResponse {}
+impl VersionedBody for WebhookResponse {}
+impl VersionedBody for WebhookDeliveriesResponse {}
+impl VersionedBody for WebhookDeliveryResponse {}